@@ -1,16 +1,19 @@
-use std::{cmp::Reverse, sync::Arc};
+use std::sync::Arc;
 
 use actix_web::{dev::HttpServiceFactory, web, HttpResponse};
+use chrono::Utc;
 use rust_decimal::Decimal;
 
 use super::CacheQuery;
 use crate::{
+    config,
     db::Database,
     hledger::Hledger,
     ib::Ib,
     import_account::ImportAccount,
-    model::balance::{BalanceResponse, BalancesResponse},
+    model::balance::BalancesResponse,
     n26::N26,
+    prices::{PriceTable, Prices},
     saltedge::SaltEdge,
 };
 
@@ -24,6 +27,7 @@ pub fn balance_routes() -> impl HttpServiceFactory {
 async fn get_account_balance<T>(
     import_account: web::Data<Arc<T>>,
     hledger: web::Data<Arc<Hledger>>,
+    prices: web::Data<Arc<Prices>>,
     db: web::Data<Arc<Database>>,
     query: web::Query<CacheQuery>,
 ) -> HttpResponse
@@ -39,19 +43,14 @@ where
     if hledger.is_empty() {
         return HttpResponse::InternalServerError().finish();
     }
-    let mut balances: Vec<BalanceResponse> = real
-        .into_iter()
-        .map(|b| BalanceResponse {
-            hledger: *hledger
-                .get(b.commodity.as_str())
-                .unwrap_or(&Decimal::new(0, 0)),
-            commodity: b.commodity,
-            real: b.amount,
-            real_euro: b.base_amount,
-        })
-        .collect();
-    balances.sort_by_key(|x| Reverse(x.real_euro));
-    let response = BalancesResponse { balances };
+    let response = BalancesResponse::compare(
+        real,
+        &hledger,
+        &PriceTable::new(Prices::read_prices()),
+        prices.base_currency(),
+        Utc::now().naive_utc().date(),
+        config::balance_tolerance().unwrap_or_else(|| Decimal::new(1, 1)),
+    );
 
     HttpResponse::Ok().json(response)
 }
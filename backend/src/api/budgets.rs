@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use actix_web::{dev::HttpServiceFactory, web, HttpResponse};
+use chrono::{Datelike, NaiveDate, Utc};
+use log::info;
+use serde::Deserialize;
+
+use crate::{budgets, db::Database, hledger::Hledger, model::budget::Budget};
+
+pub fn budgets_routes() -> impl HttpServiceFactory {
+    web::scope("/budgets")
+        .service(
+            web::resource("")
+                .route(web::get().to(get_budgets))
+                .route(web::post().to(add_budget)),
+        )
+        .route("/report", web::get().to(get_budget_report))
+        .route("/{budget_id}", web::delete().to(delete_budget))
+}
+
+#[derive(Deserialize)]
+struct BudgetReportQuery {
+    /// Defaults to the start of the current year
+    from: Option<NaiveDate>,
+    /// Defaults to today
+    to: Option<NaiveDate>,
+}
+
+/// Budgets stored in the database followed by the ones from the journal
+pub async fn get_all_budgets(db: &Database) -> Vec<Budget> {
+    let mut all = db.get_all_budgets().await.unwrap();
+    all.extend(budgets::read_journal_budgets());
+    all
+}
+
+async fn get_budgets(db: web::Data<Arc<Database>>) -> HttpResponse {
+    HttpResponse::Ok().json(get_all_budgets(&db).await)
+}
+
+async fn add_budget(budget: web::Json<Budget>, db: web::Data<Arc<Database>>) -> HttpResponse {
+    let result = db
+        .create_or_update_budget(budget.into_inner())
+        .await
+        .unwrap();
+    HttpResponse::Ok().json(result)
+}
+
+async fn delete_budget(budget_id: web::Path<String>, db: web::Data<Arc<Database>>) -> HttpResponse {
+    info!("Delete budget {}", &*budget_id);
+    db.delete_budget(&*budget_id).await.unwrap();
+    HttpResponse::Ok().finish()
+}
+
+async fn get_budget_report(
+    hledger: web::Data<Arc<Hledger>>,
+    db: web::Data<Arc<Database>>,
+    query: web::Query<BudgetReportQuery>,
+) -> HttpResponse {
+    let today = Utc::now().naive_utc().date();
+    let from = query
+        .from
+        .unwrap_or_else(|| NaiveDate::from_ymd(today.year(), 1, 1));
+    let to = query.to.unwrap_or(today);
+    let months = budgets::months(from, to);
+    let transactions = hledger.fetch_all_transactions().await;
+    let report = budgets::budget_report(get_all_budgets(&db).await, &transactions, &months, today);
+    HttpResponse::Ok().json(report)
+}
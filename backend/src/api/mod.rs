@@ -1,5 +1,6 @@
 pub mod accounts;
 pub mod balance;
+pub mod budgets;
 pub mod journal;
 pub mod prices;
 pub mod reports;
@@ -1,20 +1,88 @@
-use std::sync::Arc;
+use std::{collections::BTreeSet, io, sync::Arc, time::Duration};
 
+use actix_rt::time::{interval_at, Instant};
 use actix_web::{dev::HttpServiceFactory, web, HttpResponse};
-use log::info;
+use chrono::Utc;
+use log::{error, info};
 
-use crate::{hledger::Hledger, prices::Prices};
+use crate::{
+    db::Database, hledger::Hledger, ib::Ib, import_account::ImportAccount, prices::Prices,
+};
 
 pub fn prices_routes() -> impl HttpServiceFactory {
-    web::resource("/prices").route(web::post().to(update_prices))
+    web::scope("/prices")
+        .route("", web::post().to(update_prices))
+        .route("/freshness", web::get().to(get_freshness))
 }
 
 async fn update_prices(
     prices: web::Data<Arc<Prices>>,
     hledger: web::Data<Arc<Hledger>>,
+    db: web::Data<Arc<Database>>,
+    ib: web::Data<Arc<Ib>>,
 ) -> HttpResponse {
-    let commodities = hledger.get_commodities().await;
-    info!("{:#?}", commodities);
-    prices.update_prices(&commodities).await;
-    HttpResponse::Ok().finish()
+    match update_held_prices(&prices, &hledger, &db, &ib).await {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(e) => {
+            error!("Couldn't update prices: {}", e);
+            HttpResponse::InternalServerError().json(e.to_string())
+        }
+    }
+}
+
+/// Commodities with missing or stale prices, which `-V` valuations can't value correctly
+async fn get_freshness(
+    prices: web::Data<Arc<Prices>>,
+    hledger: web::Data<Arc<Hledger>>,
+    db: web::Data<Arc<Database>>,
+    ib: web::Data<Arc<Ib>>,
+) -> HttpResponse {
+    let commodities = held_commodities(&hledger, &db, &ib).await;
+    let today = Utc::now().naive_utc().date();
+    HttpResponse::Ok().json(prices.stale_prices(&commodities, today))
+}
+
+/// Update the prices every `hours` in the background
+pub fn schedule_price_updates(
+    hours: u64,
+    prices: Arc<Prices>,
+    hledger: Arc<Hledger>,
+    db: Arc<Database>,
+    ib: Arc<Ib>,
+) {
+    info!("Updating prices every {} hours", hours);
+    let period = Duration::from_secs(hours * 60 * 60);
+    actix_rt::spawn(async move {
+        let mut interval = interval_at(Instant::now() + period, period);
+        loop {
+            interval.tick().await;
+            // Keep the schedule going, the next update may work
+            if let Err(e) = update_held_prices(&prices, &hledger, &db, &ib).await {
+                error!("Couldn't update prices: {}", e);
+            }
+        }
+    });
+}
+
+async fn update_held_prices(
+    prices: &Prices,
+    hledger: &Hledger,
+    db: &Database,
+    ib: &Ib,
+) -> io::Result<()> {
+    let commodities = held_commodities(hledger, db, ib).await;
+    info!("Updating prices of {:?}", commodities);
+    prices.update_prices(&commodities).await
+}
+
+/// Commodities in the journal and the IB positions, which may not be in the journal yet
+async fn held_commodities(hledger: &Hledger, db: &Database, ib: &Ib) -> Vec<String> {
+    let positions = ib.get_balance_cached(db, false).await;
+    let commodities: BTreeSet<String> = hledger
+        .get_commodities()
+        .await
+        .into_iter()
+        .chain(positions.into_iter().map(|b| b.commodity))
+        .collect();
+    commodities.into_iter().collect()
 }
@@ -1,35 +1,304 @@
 use std::sync::Arc;
 
 use actix_web::{dev::HttpServiceFactory, web, HttpResponse};
-use chrono::NaiveDate;
+use chrono::{Datelike, NaiveDate, Utc};
+use rust_decimal::Decimal;
 use serde::Deserialize;
 
-use crate::hledger::Hledger;
+use super::budgets::get_all_budgets;
+use crate::{
+    db::Database,
+    flows,
+    forecast::{self, ForecastOptions},
+    hledger::{Hledger, Interval, ReportOptions},
+    ib::Ib,
+    import_account::ImportAccount,
+    lots,
+    model::hledger_transaction::HledgerTransaction,
+    n26::N26,
+    net_worth::{self, BreakdownOptions, Dimension},
+    portfolio::{self, CostMethod},
+    prices::{PriceTable, Prices},
+    saltedge::SaltEdge,
+    subscriptions::{self, Payment, Subscription},
+};
 
 pub fn reports_routes() -> impl HttpServiceFactory {
     web::scope("/reports")
         .route("/income_statement", web::get().to(get_income_statement))
         .route("/net_worth", web::get().to(get_net_worth))
+        .route(
+            "/net_worth/breakdown",
+            web::get().to(get_net_worth_breakdown),
+        )
+        .route("/subscriptions", web::get().to(get_subscriptions))
+        .route("/forecast", web::get().to(get_forecast))
+        .route("/flows", web::get().to(get_flows))
+        .route("/portfolio", web::get().to(get_portfolio))
+        .route("/capital_gains", web::get().to(get_capital_gains))
 }
 
 #[derive(Deserialize)]
 struct IncomeStatementQuery {
     from: Option<NaiveDate>,
     to: Option<NaiveDate>,
+    /// Only include transactions with this tag, either `name` or `name=value`
+    tag: Option<String>,
+    /// How many account levels to show, defaults to 1
+    depth: Option<u32>,
+    /// Only include this account and its subaccounts
+    account: Option<String>,
+    #[serde(default)]
+    interval: Interval,
+    /// Convert amounts to this commodity instead of the base currency
+    currency: Option<String>,
+}
+
+impl IncomeStatementQuery {
+    /// Converts to the base currency unless another currency is asked for
+    fn options<'a>(&'a self, prices: &'a Prices) -> ReportOptions<'a> {
+        ReportOptions {
+            from: self.from,
+            to: self.to,
+            tag: self.tag.as_deref(),
+            depth: self.depth.unwrap_or(1),
+            account: self.account.as_deref(),
+            interval: self.interval,
+            currency: self
+                .currency
+                .as_deref()
+                .unwrap_or_else(|| prices.base_currency()),
+        }
+    }
 }
 
 async fn get_income_statement(
     hledger: web::Data<Arc<Hledger>>,
+    prices: web::Data<Arc<Prices>>,
     query: web::Query<IncomeStatementQuery>,
 ) -> HttpResponse {
-    let response = hledger.get_income_statement(query.from, query.to).await;
+    let response = hledger.get_income_statement(&query.options(&prices)).await;
     HttpResponse::Ok().json(response)
 }
 
 async fn get_net_worth(
     hledger: web::Data<Arc<Hledger>>,
+    prices: web::Data<Arc<Prices>>,
     query: web::Query<IncomeStatementQuery>,
 ) -> HttpResponse {
-    let response = hledger.get_net_worth(query.from, query.to).await;
+    let response = hledger.get_net_worth(&query.options(&prices)).await;
     HttpResponse::Ok().json(response)
 }
+
+#[derive(Deserialize)]
+struct BreakdownQuery {
+    /// Defaults to the start of the current year
+    from: Option<NaiveDate>,
+    /// Defaults to today
+    to: Option<NaiveDate>,
+    #[serde(default)]
+    by: Dimension,
+}
+
+impl BreakdownQuery {
+    fn options(&self, today: NaiveDate) -> BreakdownOptions {
+        BreakdownOptions {
+            from: self
+                .from
+                .unwrap_or_else(|| NaiveDate::from_ymd(today.year(), 1, 1)),
+            to: self.to.unwrap_or(today),
+            dimension: self.by,
+        }
+    }
+}
+
+/// Net worth by institution, asset class or liquidity at the end of every month
+async fn get_net_worth_breakdown(
+    hledger: web::Data<Arc<Hledger>>,
+    prices: web::Data<Arc<Prices>>,
+    query: web::Query<BreakdownQuery>,
+) -> HttpResponse {
+    let today = Utc::now().naive_utc().date();
+    let transactions = hledger.fetch_all_transactions().await;
+    let mut price_table = PriceTable::new(Prices::read_prices());
+    portfolio::add_trade_prices(
+        &transactions,
+        &mut price_table,
+        prices.base_currency(),
+        |c| prices.is_currency(c),
+    );
+    let report = net_worth::net_worth_breakdown(
+        &transactions,
+        &net_worth::read_account_metadata(),
+        &price_table,
+        prices.base_currency(),
+        |c| prices.is_currency(c),
+        &query.options(today),
+    );
+    HttpResponse::Ok().json(report)
+}
+
+#[derive(Deserialize)]
+struct FlowQuery {
+    /// Defaults to the start of the current year
+    from: Option<NaiveDate>,
+    /// Defaults to today
+    to: Option<NaiveDate>,
+    /// How many account levels to show, defaults to 2
+    depth: Option<usize>,
+    /// Leave out links smaller than this
+    min_amount: Option<Decimal>,
+    /// Defaults to the base currency
+    commodity: Option<String>,
+}
+
+async fn get_flows(
+    hledger: web::Data<Arc<Hledger>>,
+    prices: web::Data<Arc<Prices>>,
+    query: web::Query<FlowQuery>,
+) -> HttpResponse {
+    let today = Utc::now().naive_utc().date();
+    let transactions = hledger.fetch_all_transactions().await;
+    let report = flows::flow_report(
+        &transactions,
+        query
+            .from
+            .unwrap_or_else(|| NaiveDate::from_ymd(today.year(), 1, 1)),
+        query.to.unwrap_or(today),
+        query
+            .commodity
+            .as_deref()
+            .unwrap_or_else(|| prices.base_currency()),
+        query.depth.unwrap_or(2),
+        query.min_amount.unwrap_or(Decimal::ZERO),
+    );
+    HttpResponse::Ok().json(report)
+}
+
+#[derive(Deserialize)]
+struct PortfolioQuery {
+    #[serde(default)]
+    method: CostMethod,
+}
+
+async fn get_portfolio(
+    ib: web::Data<Arc<Ib>>,
+    hledger: web::Data<Arc<Hledger>>,
+    prices: web::Data<Arc<Prices>>,
+    query: web::Query<PortfolioQuery>,
+) -> HttpResponse {
+    let mut transactions = hledger.fetch_all_transactions().await;
+    transactions.retain(|t| t.has_account(ib.get_hledger_account()));
+    let mut price_table = PriceTable::new(Prices::read_prices());
+    let events = portfolio::get_events(
+        &transactions,
+        &mut price_table,
+        prices.base_currency(),
+        |c| prices.is_currency(c),
+    );
+    let today = Utc::now().naive_utc().date();
+    let report = portfolio::portfolio_report(
+        &events,
+        &price_table,
+        prices.base_currency(),
+        query.method,
+        today,
+    );
+    HttpResponse::Ok().json(report)
+}
+
+/// Realised gains per year of the sales booked with lots
+async fn get_capital_gains(
+    ib: web::Data<Arc<Ib>>,
+    hledger: web::Data<Arc<Hledger>>,
+) -> HttpResponse {
+    let transactions = hledger
+        .fetch_account_transactions(&[ib.get_hledger_account()])
+        .await;
+    HttpResponse::Ok().json(lots::realised_gains(&transactions))
+}
+
+async fn get_subscriptions(
+    n26: web::Data<Arc<N26>>,
+    saltedge: web::Data<Arc<SaltEdge>>,
+    ib: web::Data<Arc<Ib>>,
+    hledger: web::Data<Arc<Hledger>>,
+    db: web::Data<Arc<Database>>,
+) -> HttpResponse {
+    let hledger_transactions = hledger.fetch_all_transactions().await;
+    let today = Utc::now().naive_utc().date();
+    let subscriptions =
+        find_subscriptions(&n26, &saltedge, &ib, &hledger_transactions, &db, today).await;
+    HttpResponse::Ok().json(subscriptions)
+}
+
+async fn find_subscriptions(
+    n26: &N26,
+    saltedge: &SaltEdge,
+    ib: &Ib,
+    hledger_transactions: &[HledgerTransaction],
+    db: &Database,
+    today: NaiveDate,
+) -> Vec<Subscription> {
+    let mut payments: Vec<Payment> = hledger_transactions
+        .iter()
+        .filter_map(Payment::from_hledger)
+        .collect();
+    payments.extend(get_unrecorded_payments(n26, hledger_transactions, db).await);
+    payments.extend(get_unrecorded_payments(saltedge, hledger_transactions, db).await);
+    payments.extend(get_unrecorded_payments(ib, hledger_transactions, db).await);
+    subscriptions::detect_subscriptions(payments, today)
+}
+
+#[derive(Deserialize)]
+struct ForecastQuery {
+    /// Months after the current one, defaults to 12
+    months: Option<u32>,
+}
+
+/// Expected cash and net worth from the budgets, periodic transactions and subscriptions
+async fn get_forecast(
+    n26: web::Data<Arc<N26>>,
+    saltedge: web::Data<Arc<SaltEdge>>,
+    ib: web::Data<Arc<Ib>>,
+    hledger: web::Data<Arc<Hledger>>,
+    prices: web::Data<Arc<Prices>>,
+    db: web::Data<Arc<Database>>,
+    query: web::Query<ForecastQuery>,
+) -> HttpResponse {
+    let transactions = hledger.fetch_all_transactions().await;
+    let today = Utc::now().naive_utc().date();
+    let subscriptions = find_subscriptions(&n26, &saltedge, &ib, &transactions, &db, today).await;
+    let mut price_table = PriceTable::new(Prices::read_prices());
+    portfolio::add_trade_prices(
+        &transactions,
+        &mut price_table,
+        prices.base_currency(),
+        |c| prices.is_currency(c),
+    );
+    let report = forecast::forecast(
+        &transactions,
+        &get_all_budgets(&db).await,
+        &subscriptions,
+        &price_table,
+        prices.base_currency(),
+        |c| prices.is_currency(c),
+        &ForecastOptions {
+            today,
+            months: query.months.unwrap_or(12),
+        },
+    );
+    HttpResponse::Ok().json(report)
+}
+
+async fn get_unrecorded_payments<T>(
+    import_account: &T,
+    hledger_transactions: &[HledgerTransaction],
+    db: &Database,
+) -> Vec<Payment>
+where
+    T: ImportAccount + Sync,
+{
+    let real_transactions = import_account.get_transactions_cached(db, false).await;
+    subscriptions::get_unrecorded_payments(import_account, hledger_transactions, &real_transactions)
+}
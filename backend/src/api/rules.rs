@@ -2,9 +2,16 @@ use std::sync::Arc;
 
 use actix_web::{dev::HttpServiceFactory, error::InternalError, web, HttpResponse};
 use log::{error, info};
+use serde::Deserialize;
+use serde_json::json;
 
 use crate::{
-    db::Database, ib::Ib, import_account::ImportAccount, model::rule::Rule, n26::N26,
+    csv_rules,
+    db::{self, Database},
+    ib::Ib,
+    import_account::ImportAccount,
+    model::rule::Rule,
+    n26::N26,
     saltedge::SaltEdge,
 };
 
@@ -25,6 +32,24 @@ pub fn rules_routes() -> impl HttpServiceFactory {
                 .route(web::get().to(rules_get::<Ib>))
                 .route(web::post().to(rules_add::<Ib>)),
         )
+        .service(
+            web::resource("/n26/hledger")
+                .route(web::get().to(rules_export_hledger::<N26>))
+                .route(web::post().to(rules_import_hledger::<N26>)),
+        )
+        .service(
+            web::resource("/ing/hledger")
+                .route(web::get().to(rules_export_hledger::<SaltEdge>))
+                .route(web::post().to(rules_import_hledger::<SaltEdge>)),
+        )
+        .service(
+            web::resource("/ib/hledger")
+                .route(web::get().to(rules_export_hledger::<Ib>))
+                .route(web::post().to(rules_import_hledger::<Ib>)),
+        )
+        // migrate rules between the database and the rules file in the journal repo
+        .route("/export", web::post().to(export_rules_to_file))
+        .route("/import", web::post().to(import_rules_from_file))
         // return json parsing errors
         .app_data(web::JsonConfig::default().error_handler(|err, _req| {
             let reponse = HttpResponse::BadRequest().json(err.to_string());
@@ -34,9 +59,23 @@ pub fn rules_routes() -> impl HttpServiceFactory {
 }
 
 pub fn rule_routes() -> impl HttpServiceFactory {
-    web::resource("/rule/{rule_id}")
-        .route(web::get().to(get_rule))
-        .route(web::delete().to(delete_rule))
+    web::scope("/rule/{rule_id}")
+        .service(
+            web::resource("")
+                .route(web::get().to(get_rule))
+                .route(web::delete().to(delete_rule)),
+        )
+        .route("/revisions", web::get().to(get_rule_revisions))
+        .route(
+            "/revisions/{revision}/restore",
+            web::post().to(restore_rule_revision),
+        )
+}
+
+/// Who made a change to the rules, recorded in the rule's revision history
+#[derive(Deserialize)]
+struct AuthorQuery {
+    author: Option<String>,
 }
 
 async fn rules_get<T>(
@@ -53,14 +92,71 @@ where
     )
 }
 
-async fn rules_add<T>(rule: web::Json<Rule>, db: web::Data<Arc<Database>>) -> HttpResponse
+async fn rules_add<T>(
+    rule: web::Json<Rule>,
+    query: web::Query<AuthorQuery>,
+    db: web::Data<Arc<Database>>,
+) -> HttpResponse
 where
     T: ImportAccount,
 {
-    let result = db.create_or_update_rule(rule.into_inner()).await.unwrap();
+    let result = db
+        .create_or_update_rule(rule.into_inner(), query.author.as_deref())
+        .await
+        .unwrap();
     HttpResponse::Ok().json(result)
 }
 
+/// Export the importer's rules as an hledger CSV rules file
+async fn rules_export_hledger<T>(
+    import_account: web::Data<Arc<T>>,
+    db: web::Data<Arc<Database>>,
+) -> HttpResponse
+where
+    T: ImportAccount,
+{
+    let rules = db
+        .get_all_rules(Some(import_account.get_id()))
+        .await
+        .unwrap();
+    let rules_file = csv_rules::export(
+        &rules,
+        import_account.get_id(),
+        import_account.get_hledger_account(),
+    );
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(rules_file)
+}
+
+/// Import an hledger CSV rules file (sent as the request body) as rules for the importer
+async fn rules_import_hledger<T>(
+    import_account: web::Data<Arc<T>>,
+    rules_file: String,
+    query: web::Query<AuthorQuery>,
+    db: web::Data<Arc<Database>>,
+) -> HttpResponse
+where
+    T: ImportAccount,
+{
+    let mut imported = csv_rules::import(&rules_file, import_account.get_id());
+    let mut saved = vec![];
+    for rule in imported.rules.drain(..) {
+        saved.push(
+            db.create_or_update_rule(rule, query.author.as_deref())
+                .await
+                .unwrap(),
+        );
+    }
+    imported.rules = saved;
+    info!(
+        "Imported {} hledger rules, skipped {}",
+        imported.rules.len(),
+        imported.skipped.len()
+    );
+    HttpResponse::Ok().json(imported)
+}
+
 async fn get_rule(rule_id: web::Path<String>, db: web::Data<Arc<Database>>) -> HttpResponse {
     info!("Get rule {}", &*rule_id);
     match db.get_rule(&*rule_id).await.unwrap() {
@@ -69,7 +165,67 @@ async fn get_rule(rule_id: web::Path<String>, db: web::Data<Arc<Database>>) -> H
     }
 }
 
-async fn delete_rule(rule_id: web::Path<String>, db: web::Data<Arc<Database>>) -> HttpResponse {
-    let result = db.delete_rule(&*rule_id).await.unwrap();
-    HttpResponse::Ok().json(result)
+async fn delete_rule(
+    rule_id: web::Path<String>,
+    query: web::Query<AuthorQuery>,
+    db: web::Data<Arc<Database>>,
+) -> HttpResponse {
+    let deleted_count = db
+        .delete_rule(&*rule_id, query.author.as_deref())
+        .await
+        .unwrap();
+    // Same shape as the MongoDB delete result the endpoint always returned
+    HttpResponse::Ok().json(json!({ "deletedCount": deleted_count }))
+}
+
+async fn get_rule_revisions(
+    rule_id: web::Path<String>,
+    db: web::Data<Arc<Database>>,
+) -> HttpResponse {
+    HttpResponse::Ok().json(db.get_rule_revisions(&*rule_id).await.unwrap())
+}
+
+async fn restore_rule_revision(
+    path: web::Path<(String, u32)>,
+    query: web::Query<AuthorQuery>,
+    db: web::Data<Arc<Database>>,
+) -> HttpResponse {
+    let (rule_id, revision) = path.into_inner();
+    info!("Restore rule {} to revision {}", rule_id, revision);
+    match db
+        .restore_rule_revision(&rule_id, revision, query.author.as_deref())
+        .await
+        .unwrap()
+    {
+        Some(r) => HttpResponse::Ok().json(r),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+async fn export_rules_to_file(db: web::Data<Arc<Database>>) -> HttpResponse {
+    match db.export_rules_to_file().await {
+        Ok(count) => {
+            info!("Exported {} rules to journal repo", count);
+            HttpResponse::Ok().json(json!({ "count": count }))
+        }
+        Err(e) => migration_error(e),
+    }
+}
+
+async fn import_rules_from_file(db: web::Data<Arc<Database>>) -> HttpResponse {
+    match db.import_rules_from_file().await {
+        Ok(count) => {
+            info!("Imported {} rules from journal repo", count);
+            HttpResponse::Ok().json(json!({ "count": count }))
+        }
+        Err(e) => migration_error(e),
+    }
+}
+
+fn migration_error(e: db::Error) -> HttpResponse {
+    error!("Couldn't migrate rules: {:?}", e);
+    match e {
+        db::Error::NoRulesFile => HttpResponse::BadRequest().json("No rules file is configured"),
+        e => HttpResponse::InternalServerError().json(format!("{:?}", e)),
+    }
 }
@@ -7,21 +7,50 @@ use std::{
 
 use actix_web::{web, HttpResponse};
 use log::info;
+use serde::Deserialize;
 use serde_json::json;
 
 use crate::{
     api::CacheQuery,
     db::Database,
     hledger::Hledger,
+    ib::{self, Ib},
     import_account::ImportAccount,
+    lots::{LotSelection, Lots},
     model::{
         hledger_transaction::HledgerTransaction, real_transaction::RealTransaction, rule::Rule,
         transaction_request::TransactionRequest, transaction_response::TransactionResponse,
     },
+    n26::N26,
+    prices::Prices,
+    saltedge::SaltEdge,
     templater::Templater,
     transactions,
+    transfers::{self, Transfer, TransferLeg},
 };
 
+/// Parse a saved IB Flex statement (sent as the request body) and add its transactions to the
+/// cache, so they show up in the other IB endpoints unless the cache is bypassed
+pub async fn upload_ib_statement(
+    import_account: web::Data<Arc<Ib>>,
+    statement: String,
+    db: web::Data<Arc<Database>>,
+) -> HttpResponse {
+    match ib::parse_flex_statement(&statement) {
+        Ok(transactions) => {
+            db.cache_transactions(import_account.get_id(), &transactions)
+                .await
+                .unwrap();
+            info!(
+                "Cached {} transactions from Flex statement",
+                transactions.len()
+            );
+            HttpResponse::Ok().json(json!({ "transactions": transactions.len() }))
+        }
+        Err(e) => HttpResponse::BadRequest().json(format!("Invalid Flex statement: {}", e)),
+    }
+}
+
 /// Get transactions whose ids match
 pub async fn get_existing_transactions<T>(
     import_account: web::Data<Arc<T>>,
@@ -53,6 +82,7 @@ pub async fn get_generated_transactions<T>(
     import_account: web::Data<Arc<T>>,
     hledger: web::Data<Arc<Hledger>>,
     db: web::Data<Arc<Database>>,
+    prices: web::Data<Arc<Prices>>,
     query: web::Query<CacheQuery>,
 ) -> HttpResponse
 where
@@ -84,12 +114,20 @@ where
 
     let import_hledger_account = import_account.get_hledger_account();
 
-    let generated = transactions::get_generated_transactions(
+    let mut generated = transactions::get_generated_transactions(
         import_hledger_account,
         &hledger_transactions,
         &real_transactions,
         &rules,
     );
+    book_lots(
+        &***import_account,
+        &hledger_transactions,
+        generated
+            .iter_mut()
+            .filter_map(|t| t.hledger_transaction.as_mut()),
+        &prices,
+    );
 
     info!("Generated transactions ({:?})", start.elapsed());
 
@@ -100,6 +138,7 @@ pub async fn write_generated_transactions<T>(
     import_account: web::Data<Arc<T>>,
     hledger: web::Data<Arc<Hledger>>,
     db: web::Data<Arc<Database>>,
+    prices: web::Data<Arc<Prices>>,
     query: web::Query<CacheQuery>,
 ) -> HttpResponse
 where
@@ -137,6 +176,12 @@ where
     .into_iter()
     .filter_map(|t| t.hledger_transaction)
     .collect();
+    book_lots(
+        &***import_account,
+        &hledger_transactions,
+        &mut generated,
+        &prices,
+    );
 
     info!("Generated transactions ({:?})", start.elapsed());
     let start = Instant::now();
@@ -230,15 +275,21 @@ pub async fn generate_single_transaction<T>(
 where
     T: ImportAccount,
 {
-    let description = Templater::new()
-        .render_description(&request.description_template, &request.source_transaction);
-    match description {
-        Ok(description) => {
+    let templater = Templater::with_lookup_tables();
+    let rendered = templater
+        .render_description(&request.description_template, &request.source_transaction)
+        .and_then(|description| {
+            let postings =
+                templater.render_postings(&request.postings, &request.source_transaction)?;
+            Ok((description, postings))
+        });
+    match rendered {
+        Ok((description, postings)) => {
             let transaction = HledgerTransaction::new_with_postings(
                 &request.source_transaction,
                 import_account.get_hledger_account(),
                 &description,
-                &request.postings,
+                &postings,
             );
             if request.should_write.unwrap_or(false) {
                 hledger.write_single_transaction(&transaction).await;
@@ -317,6 +368,140 @@ where
     HttpResponse::Ok().json(json!({ "dupe_ids": dupe_ids }))
 }
 
+#[derive(Deserialize)]
+pub struct TransferQuery {
+    /// How many days apart the two sides of a transfer may be
+    window_days: Option<i64>,
+}
+
+// Get pairs of unrecorded transactions of different importers which look like transfers
+pub async fn get_transfers(
+    n26: web::Data<Arc<N26>>,
+    saltedge: web::Data<Arc<SaltEdge>>,
+    ib: web::Data<Arc<Ib>>,
+    hledger: web::Data<Arc<Hledger>>,
+    db: web::Data<Arc<Database>>,
+    query: web::Query<CacheQuery>,
+    transfer_query: web::Query<TransferQuery>,
+) -> HttpResponse {
+    let transfers = find_transfers(
+        &n26,
+        &saltedge,
+        &ib,
+        &hledger,
+        &db,
+        query.bypass_cache(),
+        transfer_query.window_days,
+    )
+    .await;
+    HttpResponse::Ok().json(transfers)
+}
+
+pub async fn write_transfers(
+    n26: web::Data<Arc<N26>>,
+    saltedge: web::Data<Arc<SaltEdge>>,
+    ib: web::Data<Arc<Ib>>,
+    hledger: web::Data<Arc<Hledger>>,
+    db: web::Data<Arc<Database>>,
+    query: web::Query<CacheQuery>,
+    transfer_query: web::Query<TransferQuery>,
+) -> HttpResponse {
+    let transfers: Vec<HledgerTransaction> = find_transfers(
+        &n26,
+        &saltedge,
+        &ib,
+        &hledger,
+        &db,
+        query.bypass_cache(),
+        transfer_query.window_days,
+    )
+    .await
+    .into_iter()
+    .map(|t| t.hledger_transaction)
+    .collect();
+
+    info!("Writing {} transfers to hledger", transfers.len());
+    if hledger.write_transactions(&transfers).await {
+        HttpResponse::Created().finish()
+    } else {
+        HttpResponse::InternalServerError().finish()
+    }
+}
+
+async fn find_transfers(
+    n26: &N26,
+    saltedge: &SaltEdge,
+    ib: &Ib,
+    hledger: &Hledger,
+    db: &Database,
+    bypass_cache: bool,
+    window_days: Option<i64>,
+) -> Vec<Transfer> {
+    let start = Instant::now();
+
+    let hledger_transactions = hledger
+        .fetch_account_transactions(&[
+            n26.get_hledger_account(),
+            saltedge.get_hledger_account(),
+            ib.get_hledger_account(),
+        ])
+        .await;
+
+    info!("Fetched hledger transactions ({:?})", start.elapsed());
+    let start = Instant::now();
+
+    let mut legs = get_transfer_legs(n26, &hledger_transactions, db, bypass_cache).await;
+    legs.extend(get_transfer_legs(saltedge, &hledger_transactions, db, bypass_cache).await);
+    legs.extend(get_transfer_legs(ib, &hledger_transactions, db, bypass_cache).await);
+
+    info!("Fetched real transactions ({:?})", start.elapsed());
+    let start = Instant::now();
+
+    let transfers =
+        transfers::find_transfers(legs, window_days.unwrap_or(transfers::DEFAULT_WINDOW_DAYS));
+
+    info!(
+        "Found {} transfers ({:?})",
+        transfers.len(),
+        start.elapsed()
+    );
+    transfers
+}
+
+async fn get_transfer_legs<T>(
+    import_account: &T,
+    hledger_transactions: &[HledgerTransaction],
+    db: &Database,
+    bypass_cache: bool,
+) -> Vec<TransferLeg>
+where
+    T: ImportAccount + Sync,
+{
+    let real_transactions = import_account
+        .get_transactions_cached(db, bypass_cache)
+        .await;
+    transfers::get_transfer_legs(import_account, hledger_transactions, &real_transactions)
+}
+
+/// Split sales into the lots they close, for importers which track lots
+fn book_lots<'a>(
+    import_account: &impl ImportAccount,
+    hledger_transactions: &[HledgerTransaction],
+    generated: impl IntoIterator<Item = &'a mut HledgerTransaction>,
+    prices: &Prices,
+) {
+    if !import_account.tracks_lots() {
+        return;
+    }
+    let is_currency = |commodity: &str| prices.is_currency(commodity);
+    let mut lots = Lots::from_journal(
+        hledger_transactions,
+        LotSelection::from_config(),
+        is_currency,
+    );
+    lots.book_sales(generated, is_currency);
+}
+
 async fn get_rules(db: &Database, import_account: &impl ImportAccount) -> Vec<Rule> {
     db.get_all_rules(Some(import_account.get_id()))
         .await
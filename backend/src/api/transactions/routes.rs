@@ -4,6 +4,8 @@ use log::error;
 use super::requests;
 use crate::{ib::Ib, n26::N26, saltedge::SaltEdge};
 
+const MAX_STATEMENT_SIZE: usize = 20 * 1024 * 1024;
+
 pub fn transactions_routes() -> impl HttpServiceFactory {
     web::scope("/transactions")
         .service(
@@ -87,6 +89,18 @@ pub fn transactions_routes() -> impl HttpServiceFactory {
                 .route("/ing", web::get().to(requests::check::<SaltEdge>))
                 .route("/ib", web::get().to(requests::check::<Ib>)),
         )
+        // saved IB Flex statements, which can be several MB
+        .service(
+            web::resource("/upload/ib")
+                .app_data(web::PayloadConfig::new(MAX_STATEMENT_SIZE))
+                .route(web::post().to(requests::upload_ib_statement)),
+        )
+        // transfers between the accounts of different importers
+        .route("/transfers", web::get().to(requests::get_transfers))
+        .route(
+            "/transfers/write",
+            web::post().to(requests::write_transfers),
+        )
         .route(
             "/stats",
             web::get().to(requests::get_transaction_stats::<N26>),
@@ -0,0 +1,244 @@
+use std::{fs, str::FromStr};
+
+use chrono::{Datelike, NaiveDate};
+use log::{info, warn};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{
+    file_utils,
+    model::{
+        budget::{Budget, BudgetPeriod},
+        hledger_transaction::HledgerTransaction,
+    },
+};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BudgetMonth {
+    /// First day of the month
+    pub month: NaiveDate,
+    pub budget: Decimal,
+    pub actual: Decimal,
+    pub remaining: Decimal,
+    /// Spend at the end of the month if the current pace continues. None for future months.
+    pub projected: Option<Decimal>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BudgetReport {
+    pub budget: Budget,
+    pub months: Vec<BudgetMonth>,
+}
+
+/// Budgets from periodic transactions in budget.ledger in the journal, the same ones
+/// `hledger balance --budget` uses
+pub fn read_journal_budgets() -> Vec<Budget> {
+    let path = match file_utils::get_budget_file() {
+        Some(path) if path.exists() => path,
+        _ => return vec![],
+    };
+    match fs::read_to_string(&path) {
+        Ok(journal) => {
+            info!("Reading budgets from {}", path.to_string_lossy());
+            parse_periodic_transactions(&journal)
+        }
+        Err(e) => {
+            warn!("Couldn't read {}: {}", path.to_string_lossy(), e);
+            vec![]
+        }
+    }
+}
+
+/// Only monthly and yearly periodic transactions are supported. Postings without an
+/// amount, like the balancing asset posting, are skipped.
+pub fn parse_periodic_transactions(journal: &str) -> Vec<Budget> {
+    let mut budgets = vec![];
+    let mut period = None;
+    for line in journal.lines() {
+        let line = line.split(';').next().unwrap_or_default();
+        if let Some(expression) = line.strip_prefix('~') {
+            period = match expression.split_whitespace().next() {
+                Some("monthly") => Some(BudgetPeriod::Monthly),
+                Some("yearly") | Some("annually") => Some(BudgetPeriod::Yearly),
+                _ => {
+                    warn!("Unsupported budget period: {}", expression.trim());
+                    None
+                }
+            };
+            continue;
+        }
+        if !line.starts_with(char::is_whitespace) {
+            period = None;
+            continue;
+        }
+        let period = match period {
+            Some(period) => period,
+            None => continue,
+        };
+        // The account is separated from the amount by at least two spaces or a tab
+        let posting = line.trim().replace('\t', "  ");
+        if let Some((account, amount)) = posting.split_once("  ") {
+            if let Some((amount, commodity)) = parse_amount(amount.trim()) {
+                budgets.push(Budget {
+                    id: None,
+                    account: account.trim().to_string(),
+                    amount,
+                    commodity,
+                    period,
+                });
+            }
+        }
+    }
+    budgets
+}
+
+// Either `400 EUR`, `EUR 400` or `€400`
+fn parse_amount(amount: &str) -> Option<(Decimal, String)> {
+    let amount = amount.replace(',', "");
+    let parts: Vec<&str> = amount.split_whitespace().collect();
+    match parts[..] {
+        [a, b] => Decimal::from_str(a)
+            .map(|q| (q, b.to_string()))
+            .or_else(|_| Decimal::from_str(b).map(|q| (q, a.to_string())))
+            .ok(),
+        [a] => {
+            let start = a.find(|c: char| c.is_ascii_digit() || c == '-')?;
+            let (commodity, quantity) = a.split_at(start);
+            Some((Decimal::from_str(quantity).ok()?, commodity.to_string()))
+        }
+        _ => None,
+    }
+}
+
+/// First days of every month from `from` up to and including `to`
+pub fn months(from: NaiveDate, to: NaiveDate) -> Vec<NaiveDate> {
+    let mut months = vec![];
+    let mut month = NaiveDate::from_ymd(from.year(), from.month(), 1);
+    while month <= to {
+        months.push(month);
+        month = next_month(month);
+    }
+    months
+}
+
+/// Compare the actual spend in every month with the budgets. Only postings in the budget's
+/// commodity are counted.
+pub fn budget_report(
+    budgets: Vec<Budget>,
+    transactions: &[HledgerTransaction],
+    months: &[NaiveDate],
+    today: NaiveDate,
+) -> Vec<BudgetReport> {
+    budgets
+        .into_iter()
+        .map(|budget| {
+            let mut actuals = vec![Decimal::ZERO; months.len()];
+            for (account, date, amount, commodity) in
+                transactions.iter().flat_map(|t| t.get_posting_amounts())
+            {
+                if commodity != budget.commodity || !budget.covers(account) {
+                    continue;
+                }
+                if let Some(i) = months
+                    .iter()
+                    .position(|m| m.year() == date.year() && m.month() == date.month())
+                {
+                    actuals[i] += amount;
+                }
+            }
+            let amount = budget.monthly_amount();
+            let months = months
+                .iter()
+                .zip(actuals)
+                .map(|(&month, actual)| BudgetMonth {
+                    month,
+                    budget: amount,
+                    actual,
+                    remaining: amount - actual,
+                    projected: project(month, actual, today),
+                })
+                .collect();
+            BudgetReport { budget, months }
+        })
+        .collect()
+}
+
+fn project(month: NaiveDate, actual: Decimal, today: NaiveDate) -> Option<Decimal> {
+    let next = next_month(month);
+    if today >= next {
+        return Some(actual);
+    }
+    if today < month {
+        return None;
+    }
+    let days_in_month = (next - month).num_days();
+    let elapsed = today.day() as i64;
+    Some((actual * Decimal::from(days_in_month) / Decimal::from(elapsed)).round_dp(2))
+}
+
+fn next_month(month: NaiveDate) -> NaiveDate {
+    NaiveDate::from_ymd_opt(month.year(), month.month() + 1, 1)
+        .unwrap_or_else(|| NaiveDate::from_ymd(month.year() + 1, 1, 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+
+    use super::{budget_report, months, parse_periodic_transactions};
+    use crate::{
+        model::budget::BudgetPeriod,
+        test_statics::{EXPENSE_ACCOUNT, HLEDGER},
+    };
+
+    #[test]
+    fn periodic_transactions() {
+        let budgets = parse_periodic_transactions(
+            "~ monthly from 2021 ; groceries
+    Expenses:Food        400 EUR
+    Expenses:Eating Out\tEUR 1,100.50
+    Assets:Cash:N26
+
+2021-01-01 Not a budget
+    Expenses:Food        12 EUR
+    Assets:Cash:N26
+
+~ yearly
+    Expenses:Travel  €2400
+",
+        );
+        assert_eq!(budgets.len(), 3);
+        assert_eq!(budgets[0].account, "Expenses:Food");
+        assert_eq!(budgets[0].amount, Decimal::new(400, 0));
+        assert_eq!(budgets[1].account, "Expenses:Eating Out");
+        assert_eq!(budgets[1].amount, Decimal::new(110050, 2));
+        assert_eq!(budgets[1].commodity, "EUR");
+        assert_eq!(budgets[2].period, BudgetPeriod::Yearly);
+        assert_eq!(budgets[2].commodity, "€");
+        assert_eq!(budgets[2].monthly_amount(), Decimal::new(200, 0));
+    }
+
+    #[test]
+    fn actual_vs_budget() {
+        let budgets = parse_periodic_transactions(&format!(
+            "~ monthly\n    {}  300 EUR\n",
+            EXPENSE_ACCOUNT.rsplit_once(':').unwrap().0
+        ));
+        let months = months(
+            NaiveDate::from_ymd(2020, 7, 1),
+            NaiveDate::from_ymd(2020, 8, 31),
+        );
+        let report = budget_report(budgets, &HLEDGER, &months, NaiveDate::from_ymd(2020, 8, 15));
+        let months = &report[0].months;
+        assert_eq!(months.len(), 2);
+        assert_eq!(months[0].actual, Decimal::ZERO);
+        assert_eq!(months[0].projected, Some(Decimal::ZERO));
+        assert_eq!(months[1].actual, Decimal::new(21956, 2));
+        assert_eq!(months[1].remaining, Decimal::new(8044, 2));
+        // 15 of 31 days have passed
+        assert_eq!(months[1].projected, Some(Decimal::new(45376, 2)));
+    }
+}
@@ -1,5 +1,7 @@
 use std::env;
 
+use rust_decimal::Decimal;
+
 pub fn api_key() -> Option<String> {
     env::var("API_KEY").ok()
 }
@@ -32,16 +34,85 @@ pub fn alpha_vantage_key() -> Option<String> {
     env::var("ALPHA_VANTAGE_KEY").ok()
 }
 
-pub fn ib_flex_token() -> String {
-    env::var("IB_FLEX_TOKEN").expect("Need to set IB_FLEX_TOKEN")
+/// Fetch a price for every day instead of every week
+pub fn daily_prices() -> bool {
+    matches!(env::var("DAILY_PRICES").as_deref(), Ok("true") | Ok("1"))
+}
+
+/// Prices older than this many days are reported as stale
+pub fn price_max_age_days() -> Option<i64> {
+    env::var("PRICE_MAX_AGE_DAYS").ok()?.parse().ok()
+}
+
+/// Update the prices of all commodities held every this many hours
+pub fn price_update_interval_hours() -> Option<u64> {
+    env::var("PRICE_UPDATE_INTERVAL_HOURS").ok()?.parse().ok()
+}
+
+/// Currency to fetch prices, value balances and convert reports in, defaults to EUR
+pub fn base_currency() -> String {
+    env::var("BASE_CURRENCY").unwrap_or_else(|_| "EUR".to_string())
+}
+
+/// Largest difference between real and hledger balances, in the base currency, that is
+/// still shown as synchronised, defaults to 0.1
+pub fn balance_tolerance() -> Option<Decimal> {
+    env::var("BALANCE_TOLERANCE").ok()?.parse().ok()
+}
+
+/// Comma separated price sources to try in order: alphavantage, ecb, file or http
+pub fn price_sources() -> Option<String> {
+    env::var("PRICE_SOURCES").ok()
+}
+
+/// Price sources for single commodities, e.g. `BTC=file;USD=ecb,alphavantage`
+pub fn price_sources_by_commodity() -> Option<String> {
+    env::var("PRICE_SOURCES_BY_COMMODITY").ok()
+}
+
+/// CSV or JSON file with prices for the file price source
+pub fn prices_source_file() -> Option<String> {
+    env::var("PRICES_SOURCE_FILE").ok()
 }
 
-pub fn ib_flex_balance_query_id() -> String {
-    env::var("IB_FLEX_BALANCE_QUERY_ID").expect("Need to set IB_FLEX_BALANCE_QUERY_ID")
+/// Service for the http price source
+pub fn prices_source_url() -> Option<String> {
+    env::var("PRICES_SOURCE_URL").ok()
 }
 
-pub fn ib_flex_transactions_query_id() -> String {
-    env::var("IB_FLEX_TRANSACTIONS_QUERY_ID").expect("Need to set IB_FLEX_TRANSACTIONS_QUERY_ID")
+pub fn ib_flex_token() -> Option<String> {
+    env::var("IB_FLEX_TOKEN").ok()
+}
+
+pub fn ib_flex_balance_query_id() -> Option<String> {
+    env::var("IB_FLEX_BALANCE_QUERY_ID").ok()
+}
+
+pub fn ib_flex_transactions_query_id() -> Option<String> {
+    env::var("IB_FLEX_TRANSACTIONS_QUERY_ID").ok()
+}
+
+/// Read saved Flex statements (*.xml) from this directory instead of the Flex web service
+pub fn ib_flex_statements_dir() -> Option<String> {
+    env::var("IB_FLEX_STATEMENTS_DIR").ok()
+}
+
+/// Use an embedded SQLite database at this path instead of MongoDB
+pub fn sqlite_path() -> Option<String> {
+    env::var("SQLITE_PATH").ok()
+}
+
+/// Store rules in the journal repo instead of the database
+pub fn rules_in_journal() -> bool {
+    matches!(
+        env::var("RULES_IN_JOURNAL").as_deref(),
+        Ok("true") | Ok("1")
+    )
+}
+
+/// Sell the newest lots of an investment first instead of the oldest
+pub fn lifo_lots() -> bool {
+    matches!(env::var("LIFO_LOTS").as_deref(), Ok("true") | Ok("1"))
 }
 
 pub fn mongodb_url() -> String {
@@ -58,6 +129,11 @@ pub fn journal_repo_url() -> String {
     env::var("JOURNAL_REPO_URL").expect("JOURNAL_REPO_URL must be set!")
 }
 
+/// Like `journal_repo_url`, for the paths which are optional
+pub fn journal_repo_url_if_set() -> Option<String> {
+    env::var("JOURNAL_REPO_URL").ok()
+}
+
 pub fn journal_repo_credentials() -> Option<(String, String)> {
     Some((
         env::var("JOURNAL_REPO_USERNAME").ok()?,
@@ -0,0 +1,477 @@
+//! Conversion between [Rule]s and hledger's native CSV rules format
+//! https://hledger.org/csv.html
+
+use std::collections::BTreeMap;
+
+use regex::{Captures, Regex};
+use serde::Serialize;
+
+use crate::model::rule::{Rule, RulePosting};
+
+const RULE_NAME_PREFIX: &str = "# rule:";
+const CASE_INSENSITIVE_FLAG: &str = "(?i)";
+// Used when a posting doesn't name its amount field explicitly
+const DEFAULT_AMOUNT_FIELD: &str = "amount";
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportedRules {
+    pub rules: Vec<Rule>,
+    /// Descriptions of the if blocks which couldn't be represented as a Rule
+    pub skipped: Vec<String>,
+}
+
+/// Render rules as an hledger CSV rules file.
+/// hledger applies every matching if block with later blocks taking precedence, whereas only
+/// the first matching Rule is used here, so rules are written in reverse priority order.
+pub fn export(rules: &[Rule], importer_id: &str, hledger_account: &str) -> String {
+    let mut sorted: Vec<&Rule> = rules.iter().collect();
+    sorted.sort_by_key(|r| r.priority);
+
+    let mut out = format!(
+        "# hledger CSV rules exported from ledger-dashboard for importer {}\n\
+         # Field names refer to the importer's transactions, so add a matching `fields` list.\n\n\
+         account1 {}\n",
+        importer_id, hledger_account
+    );
+    for rule in sorted.into_iter().rev() {
+        out.push('\n');
+        out.push_str(&format!("{} {}\n", RULE_NAME_PREFIX, rule.rule_name));
+        let regex = rule.match_field_regex.as_str();
+        out.push_str(&format!(
+            "if %{} {}\n",
+            rule.match_field_name,
+            anchors_from_json(regex.strip_prefix(CASE_INSENSITIVE_FLAG).unwrap_or(regex))
+        ));
+        if !rule.description_template.is_empty() {
+            let note = match &rule.note_template {
+                Some(note) => format!(" | {}", template_to_hledger(note)),
+                None => String::new(),
+            };
+            out.push_str(&format!(
+                "  description {}{}\n",
+                template_to_hledger(&rule.description_template),
+                note
+            ));
+        }
+        for (n, posting) in numbered_postings(&rule.postings) {
+            out.push_str(&format!("  account{} {}\n", n, posting.account));
+            if rule.postings.len() > 1 || posting.amount_field_name.is_some() || !posting.negate {
+                let field = posting
+                    .amount_field_name
+                    .as_deref()
+                    .unwrap_or(DEFAULT_AMOUNT_FIELD);
+                let sign = if posting.negate { "-" } else { "" };
+                out.push_str(&format!("  amount{} {}%{}\n", n, sign, field));
+            }
+            if let Some(currency) = &posting.currency_field_name {
+                out.push_str(&format!("  currency{} %{}\n", n, currency));
+            }
+            if let Some(comment) = &posting.comment {
+                out.push_str(&format!(
+                    "  comment{} {}\n",
+                    n,
+                    template_to_hledger(comment)
+                ));
+            }
+        }
+    }
+    out
+}
+
+/// Parse an hledger CSV rules file into rules for the given importer.
+/// Only if blocks whose matchers all test the same CSV field can be represented.
+pub fn import(rules_file: &str, importer_id: &str) -> ImportedRules {
+    let blocks = parse_blocks(rules_file);
+    let mut imported = ImportedRules::default();
+    let count = blocks.len() as i32;
+    for (i, block) in blocks.into_iter().enumerate() {
+        let name = block
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("hledger rule {}", i + 1));
+        match block.into_rule(importer_id, &name, count - i as i32) {
+            Ok(rule) => imported.rules.push(rule),
+            Err(reason) => imported.skipped.push(format!("{}: {}", name, reason)),
+        }
+    }
+    imported
+}
+
+// Rules with a single posting only describe the counterpart of the import account
+fn numbered_postings(postings: &[RulePosting]) -> impl Iterator<Item = (usize, &RulePosting)> {
+    let first = if postings.len() == 1 { 2 } else { 1 };
+    postings
+        .iter()
+        .enumerate()
+        .map(move |(i, p)| (i + first, p))
+}
+
+fn template_to_hledger(template: &str) -> String {
+    let field = Regex::new(r"\{\{\{?\s*([\w.]+)\s*\}?\}\}").unwrap();
+    field.replace_all(template, "%$1").to_string()
+}
+
+fn hledger_to_template(value: &str) -> String {
+    let field = Regex::new(r"%(\w+)").unwrap();
+    field
+        .replace_all(value, |c: &Captures| format!("{{{{{{{}}}}}}}", &c[1]))
+        .to_string()
+}
+
+#[derive(Debug, Default)]
+struct Block {
+    name: Option<String>,
+    /// (field, regex). Whole record matchers have no field
+    matchers: Vec<(Option<String>, String)>,
+    assignments: Vec<(String, String)>,
+}
+
+impl Block {
+    fn into_rule(self, importer_id: &str, name: &str, priority: i32) -> Result<Rule, String> {
+        let mut field = None;
+        let mut patterns = vec![];
+        for (f, pattern) in self.matchers {
+            let f = f.ok_or("whole record matchers aren't supported")?;
+            if field.get_or_insert_with(|| f.clone()) != &f {
+                return Err("matchers on multiple fields aren't supported".to_string());
+            }
+            patterns.push(pattern);
+        }
+        let field = field.ok_or("no matchers")?;
+        let pattern = match patterns.len() {
+            1 => patterns.remove(0),
+            _ => patterns.join("|"),
+        };
+        // hledger matches case insensitively
+        let regex = Regex::new(&format!(
+            "{}{}",
+            CASE_INSENSITIVE_FLAG,
+            anchors_to_json(&pattern)
+        ))
+        .map_err(|e| e.to_string())?;
+
+        let mut description_template = String::new();
+        let mut postings = BTreeMap::<usize, RulePosting>::new();
+        let mut explicit_amounts = false;
+        for (key, value) in self.assignments {
+            if key == "description" {
+                description_template = hledger_to_template(&value);
+                continue;
+            }
+            let split = key.find(|c: char| c.is_ascii_digit()).unwrap_or(key.len());
+            let (name, n) = key.split_at(split);
+            let n = match n.parse::<usize>() {
+                Ok(n) => n,
+                // Unnumbered fields like `amount` or `comment` apply to the whole transaction
+                Err(_) => continue,
+            };
+            let posting = postings.entry(n).or_default();
+            match name {
+                "account" => posting.account = value,
+                "amount" => {
+                    explicit_amounts = true;
+                    let (negate, field) = match value.strip_prefix('-') {
+                        Some(v) => (true, v),
+                        None => (false, value.as_str()),
+                    };
+                    posting.negate = negate;
+                    posting.amount_field_name = field.strip_prefix('%').map(str::to_string);
+                }
+                "currency" => {
+                    posting.currency_field_name = value.strip_prefix('%').map(str::to_string)
+                }
+                "comment" => posting.comment = Some(hledger_to_template(&value)),
+                _ => {}
+            }
+        }
+        postings.retain(|_, p| !p.account.is_empty());
+        if postings.is_empty() {
+            return Err("no accounts assigned".to_string());
+        }
+        if !explicit_amounts {
+            // The import account receives the amount (implicitly unless account1 is assigned)
+            // and the remaining postings balance it
+            let has_account1 = postings.contains_key(&1);
+            for (i, posting) in postings.values_mut().enumerate() {
+                posting.negate = i > 0 || !has_account1;
+            }
+        }
+
+        Ok(Rule {
+            priority,
+            importer_id: importer_id.to_string(),
+            rule_name: name.to_string(),
+            match_field_name: field,
+            match_field_regex: regex,
+            description_template,
+            postings: postings.into_values().collect(),
+            ..Rule::default()
+        })
+    }
+}
+
+fn parse_blocks(rules_file: &str) -> Vec<Block> {
+    let mut blocks = vec![];
+    let mut current: Option<Block> = None;
+    let mut pending_name = None;
+    let mut reading_matchers = false;
+
+    for line in rules_file.lines() {
+        let trimmed = line.trim();
+        if let Some(name) = trimmed.strip_prefix(RULE_NAME_PREFIX) {
+            pending_name = Some(name.trim().to_string());
+            continue;
+        }
+        if trimmed.is_empty() || trimmed.starts_with(['#', ';', '*']) {
+            reading_matchers = false;
+            continue;
+        }
+        let indented = line.starts_with(char::is_whitespace);
+
+        if let Some(rest) = trimmed.strip_prefix("if") {
+            if rest.is_empty() || rest.starts_with(char::is_whitespace) {
+                blocks.extend(current.take());
+                let mut block = Block {
+                    name: pending_name.take(),
+                    ..Block::default()
+                };
+                let rest = rest.trim();
+                if !rest.is_empty() {
+                    block.matchers.push(parse_matcher(rest));
+                }
+                current = Some(block);
+                reading_matchers = true;
+                continue;
+            }
+        }
+
+        match &mut current {
+            Some(block) if reading_matchers && !indented => {
+                block.matchers.push(parse_matcher(trimmed))
+            }
+            Some(block) if indented => {
+                reading_matchers = false;
+                if let Some((key, value)) = trimmed.split_once(char::is_whitespace) {
+                    block
+                        .assignments
+                        .push((key.to_string(), value.trim().to_string()));
+                }
+            }
+            // Top level directives end the current block
+            _ => {
+                reading_matchers = false;
+                blocks.extend(current.take());
+            }
+        }
+    }
+    blocks.extend(current);
+    blocks
+}
+
+fn parse_matcher(matcher: &str) -> (Option<String>, String) {
+    if let Some(rest) = matcher.strip_prefix('%') {
+        if let Some((field, pattern)) = rest.split_once(char::is_whitespace) {
+            return (Some(field.to_string()), pattern.trim().to_string());
+        }
+    }
+    (None, matcher.to_string())
+}
+
+/// Rules match against the field as JSON, so anchors have to allow for the quotes of strings
+fn anchors_to_json(pattern: &str) -> String {
+    alternatives(pattern)
+        .into_iter()
+        .map(|alternative| {
+            let mut alternative = match alternative.strip_prefix('^') {
+                Some(rest) => format!("^\"?{}", rest),
+                None => alternative.to_string(),
+            };
+            if ends_with_anchor(&alternative) {
+                alternative.insert_str(alternative.len() - 1, "\"?");
+            }
+            alternative
+        })
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+/// The reverse of `anchors_to_json`, hledger matches the field without quotes
+fn anchors_from_json(pattern: &str) -> String {
+    alternatives(pattern)
+        .into_iter()
+        .map(|alternative| {
+            let alternative = match alternative.strip_prefix('^') {
+                Some(rest) => {
+                    let rest = rest.strip_prefix("\"?").or_else(|| rest.strip_prefix('"'));
+                    format!("^{}", rest.unwrap_or(&alternative[1..]))
+                }
+                None => alternative.to_string(),
+            };
+            if !ends_with_anchor(&alternative) {
+                return alternative;
+            }
+            let rest = &alternative[..alternative.len() - 1];
+            let rest = rest.strip_suffix("\"?").or_else(|| rest.strip_suffix('"'));
+            format!("{}$", rest.unwrap_or(&alternative[..alternative.len() - 1]))
+        })
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+fn ends_with_anchor(pattern: &str) -> bool {
+    pattern.ends_with('$') && !pattern.ends_with("\\$")
+}
+
+/// The top level alternatives of a pattern, leaving groups, classes and escapes alone
+fn alternatives(pattern: &str) -> Vec<&str> {
+    let mut alternatives = vec![];
+    let (mut start, mut depth, mut in_class, mut escaped) = (0, 0, false, false);
+    for (i, c) in pattern.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '[' => in_class = true,
+            ']' => in_class = false,
+            _ if in_class => {}
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '|' if depth == 0 => {
+                alternatives.push(&pattern[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    alternatives.push(&pattern[start..]);
+    alternatives
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    use super::{export, import};
+    use crate::{
+        model::rule::Rule,
+        test_statics::{ASSET_ACCOUNT, EXPENSE_ACCOUNT, RULES},
+    };
+
+    #[test]
+    fn export_rule() {
+        let exported = export(&RULES, "n26", ASSET_ACCOUNT);
+        assert!(exported.contains("account1 Assets:Cash:N26\n"));
+        assert!(exported.contains("if %partnerName amazon\n"));
+        assert!(exported.contains("  description Test %partnerName with %referenceText\n"));
+        assert!(
+            exported.contains("  account2 Expenses:Personal:Entertainment\n  amount2 -%amount\n")
+        );
+        assert!(exported.contains("  currency1 %currencyCode\n"));
+    }
+
+    #[test]
+    fn import_rules() {
+        let rules_file = r#"
+skip 1
+fields date, partnerName, referenceText, amount
+account1 Assets:Cash:N26
+currency EUR
+
+# rule: Groceries
+if %partnerName rewe
+%partnerName edeka
+  account2 Expenses:Personal:Food:Groceries
+  description Groceries at %partnerName
+
+if %partnerName (landlord|rent)
+  account2 Expenses:Personal:Rent
+  comment2 rent for %referenceText
+
+if
+paypal
+  account2 Expenses:Unknown
+
+if %partnerName amazon
+%referenceText book
+  account2 Expenses:Books
+"#;
+        let imported = import(rules_file, "n26");
+        assert_eq!(imported.rules.len(), 2);
+        assert_eq!(imported.skipped.len(), 2);
+
+        let groceries = &imported.rules[0];
+        assert_eq!(groceries.rule_name, "Groceries");
+        assert_eq!(groceries.importer_id, "n26");
+        assert_eq!(groceries.match_field_name, "partnerName");
+        assert!(groceries.match_field_regex.is_match("\"EDEKA Center\""));
+        assert!(groceries.match_field_regex.is_match("\"Rewe\""));
+        assert_eq!(
+            groceries.description_template,
+            "Groceries at {{{partnerName}}}"
+        );
+        assert_eq!(groceries.postings.len(), 1);
+        assert!(groceries.postings[0].negate);
+
+        let rent = &imported.rules[1];
+        assert_eq!(rent.rule_name, "hledger rule 2");
+        assert_eq!(
+            rent.postings[0].comment.as_deref(),
+            Some("rent for {{{referenceText}}}")
+        );
+        // Later hledger blocks take precedence
+        assert!(rent.priority < groceries.priority);
+    }
+
+    #[test]
+    fn anchored_round_trip() {
+        let rules_file = "account1 Assets:Cash:N26
+
+if %partnerName ^rewe|amazon$
+  account2 Expenses:Shopping
+";
+        let imported = import(rules_file, "n26");
+        let regex = &imported.rules[0].match_field_regex;
+        // Fields are matched as JSON
+        assert!(regex.is_match("\"REWE Markt\""));
+        assert!(regex.is_match("\"Amazon\""));
+        assert!(!regex.is_match("\"Amazon Prime\""));
+        assert!(!regex.is_match("\"Super REWE\""));
+
+        let exported = export(&imported.rules, "n26", ASSET_ACCOUNT);
+        assert!(exported.contains("if %partnerName ^rewe|amazon$\n"));
+    }
+
+    #[test]
+    fn export_quoted_anchors() {
+        let rule = Rule {
+            match_field_regex: Regex::new("^\"Amazon\"$").unwrap(),
+            ..RULES[0].clone()
+        };
+        let exported = export(&[rule], "n26", ASSET_ACCOUNT);
+        assert!(exported.contains("if %partnerName ^Amazon$\n"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let exported = export(&RULES, "n26", ASSET_ACCOUNT);
+        let imported = import(&exported, "n26");
+        assert!(imported.skipped.is_empty());
+        let rule = &imported.rules[0];
+        assert_eq!(rule.rule_name, RULES[0].rule_name);
+        assert_eq!(rule.match_field_name, RULES[0].match_field_name);
+        assert_eq!(
+            rule.match_field_regex.as_str(),
+            RULES[0].match_field_regex.as_str()
+        );
+        assert_eq!(rule.description_template, RULES[0].description_template);
+        assert_eq!(rule.postings.len(), 2);
+        assert_eq!(rule.postings[0].account, ASSET_ACCOUNT);
+        assert!(!rule.postings[0].negate);
+        assert_eq!(rule.postings[1].account, EXPENSE_ACCOUNT);
+        assert!(rule.postings[1].negate);
+        assert_eq!(
+            rule.postings[1].currency_field_name.as_deref(),
+            Some("currencyCode")
+        );
+    }
+}
@@ -0,0 +1,376 @@
+mod mongo;
+mod rules_file;
+mod sqlite;
+
+use async_trait::async_trait;
+use bson::Document;
+use serde::de::DeserializeOwned;
+
+use self::{mongo::MongoStorage, rules_file::RulesFile, sqlite::SqliteStorage};
+use crate::{
+    config, file_utils,
+    model::{
+        balance::RealBalance,
+        budget::Budget,
+        real_transaction::RealTransaction,
+        rule::Rule,
+        rule_revision::{RuleChange, RuleRevision},
+        token_data::TokenData,
+    },
+};
+
+#[derive(Debug)]
+pub enum Error {
+    BsonSer(bson::ser::Error),
+    BsonDe(bson::de::Error),
+    BsonOid(bson::oid::Error),
+    MongoDb(mongodb::error::Error),
+    Sqlite(rusqlite::Error),
+    Json(serde_json::Error),
+    Yaml(serde_yaml::Error),
+    Io(std::io::Error),
+    NoRulesFile,
+}
+
+impl From<bson::ser::Error> for Error {
+    fn from(e: bson::ser::Error) -> Self {
+        Error::BsonSer(e)
+    }
+}
+
+impl From<bson::de::Error> for Error {
+    fn from(e: bson::de::Error) -> Self {
+        Error::BsonDe(e)
+    }
+}
+
+impl From<bson::oid::Error> for Error {
+    fn from(e: bson::oid::Error) -> Self {
+        Error::BsonOid(e)
+    }
+}
+
+impl From<mongodb::error::Error> for Error {
+    fn from(e: mongodb::error::Error) -> Self {
+        Error::MongoDb(e)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Sqlite(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+impl From<serde_yaml::Error> for Error {
+    fn from(e: serde_yaml::Error) -> Self {
+        Error::Yaml(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// A backend which is able to persist everything the dashboard needs to remember
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Returns the stored rule, which always has an id
+    async fn create_or_update_rule(&self, rule: Rule) -> Result<Rule>;
+    async fn get_all_rules(&self, filter_by_importer_id: Option<&str>) -> Result<Vec<Rule>>;
+    async fn get_rule(&self, rule_id: &str) -> Result<Option<Rule>>;
+    /// Returns how many rules were deleted
+    async fn delete_rule(&self, rule_id: &str) -> Result<u64>;
+
+    async fn add_rule_revision(&self, revision: RuleRevision) -> Result<()>;
+    /// Oldest revision first
+    async fn get_rule_revisions(&self, rule_id: &str) -> Result<Vec<RuleRevision>>;
+
+    /// Returns the stored budget, which always has an id
+    async fn create_or_update_budget(&self, budget: Budget) -> Result<Budget>;
+    async fn get_all_budgets(&self) -> Result<Vec<Budget>>;
+    async fn delete_budget(&self, budget_id: &str) -> Result<()>;
+
+    async fn get_auth(&self) -> Result<Option<TokenData>>;
+    async fn set_auth(&self, auth: Option<TokenData>) -> Result<()>;
+
+    async fn get_transactions(&self, account_id: &str) -> Result<Vec<Document>>;
+    /// Documents whose `_id` has already been cached are skipped
+    async fn cache_transactions(&self, account_id: &str, docs: Vec<Document>) -> Result<()>;
+
+    async fn get_balance(&self, account_id: &str) -> Result<Vec<RealBalance>>;
+    async fn cache_balance(&self, account_id: &str, balance: Vec<RealBalance>) -> Result<()>;
+}
+
+pub struct Database {
+    storage: Box<dyn Storage>,
+    rules_file: Option<RulesFile>,
+    rules_in_journal: bool,
+}
+
+impl Database {
+    /// Uses SQLite if SQLITE_PATH is set, otherwise MongoDB
+    pub async fn new() -> Result<Self> {
+        let storage: Box<dyn Storage> = match config::sqlite_path() {
+            Some(path) => Box::new(SqliteStorage::open(&path)?),
+            None => Box::new(MongoStorage::new().await?),
+        };
+        Ok(Self {
+            storage,
+            // Also used to migrate rules while they're stored in the database
+            rules_file: file_utils::get_rules_file().map(RulesFile::new),
+            rules_in_journal: config::rules_in_journal(),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn in_memory() -> Result<Self> {
+        Ok(Self {
+            storage: Box::new(SqliteStorage::open_in_memory()?),
+            rules_file: None,
+            rules_in_journal: false,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn in_memory_with_rules_file(
+        path: std::path::PathBuf,
+        rules_in_journal: bool,
+    ) -> Result<Self> {
+        Ok(Self {
+            storage: Box::new(SqliteStorage::open_in_memory()?),
+            rules_file: Some(RulesFile::new(path)),
+            rules_in_journal,
+        })
+    }
+
+    // RULES
+
+    /// Save the rule as a new revision
+    pub async fn create_or_update_rule(&self, rule: Rule, author: Option<&str>) -> Result<Rule> {
+        self.save_rule(self.active_rules_file()?, rule, author)
+            .await
+    }
+
+    /// Save the rule to the rules file if given, otherwise to the database
+    async fn save_rule(
+        &self,
+        file: Option<&RulesFile>,
+        rule: Rule,
+        author: Option<&str>,
+    ) -> Result<Rule> {
+        let previous = match (rule.id, file) {
+            (Some(id), Some(file)) => file.get_rule(&id.to_hex())?,
+            (Some(id), None) => self.storage.get_rule(&id.to_hex()).await?,
+            (None, _) => None,
+        };
+        let change = if previous.is_some() {
+            RuleChange::Updated
+        } else {
+            RuleChange::Created
+        };
+        self.save_rule_revision(file, rule, previous, change, author)
+            .await
+    }
+
+    pub async fn get_all_rules(&self, filter_by_importer_id: Option<&str>) -> Result<Vec<Rule>> {
+        match self.active_rules_file()? {
+            Some(file) => file.get_all_rules(filter_by_importer_id),
+            None => self.storage.get_all_rules(filter_by_importer_id).await,
+        }
+    }
+
+    pub async fn get_rule(&self, rule_id: &str) -> Result<Option<Rule>> {
+        match self.active_rules_file()? {
+            Some(file) => file.get_rule(rule_id),
+            None => self.storage.get_rule(rule_id).await,
+        }
+    }
+
+    /// Returns how many rules were deleted
+    pub async fn delete_rule(&self, rule_id: &str, author: Option<&str>) -> Result<u64> {
+        let previous = self.get_rule(rule_id).await?;
+        let deleted_count = match self.active_rules_file()? {
+            Some(file) => file.delete_rule(rule_id)?,
+            None => self.storage.delete_rule(rule_id).await?,
+        };
+        if let Some(previous) = previous {
+            let deleted = Rule {
+                revision: self.next_rule_revision(rule_id).await?,
+                ..previous.clone()
+            };
+            self.storage
+                .add_rule_revision(RuleRevision::new(
+                    &deleted,
+                    Some(&previous),
+                    RuleChange::Deleted,
+                    author,
+                ))
+                .await?;
+        }
+        Ok(deleted_count)
+    }
+
+    pub async fn get_rule_revisions(&self, rule_id: &str) -> Result<Vec<RuleRevision>> {
+        self.storage.get_rule_revisions(rule_id).await
+    }
+
+    /// Save the rule as it was at the given revision as a new revision. Also works for
+    /// deleted rules. Returns None if there is no such revision.
+    pub async fn restore_rule_revision(
+        &self,
+        rule_id: &str,
+        revision: u32,
+        author: Option<&str>,
+    ) -> Result<Option<Rule>> {
+        let snapshot = match self
+            .get_rule_revisions(rule_id)
+            .await?
+            .into_iter()
+            .find(|r| r.revision == revision)
+        {
+            Some(r) => r.rule,
+            None => return Ok(None),
+        };
+        let previous = self.get_rule(rule_id).await?;
+        let restored = self
+            .save_rule_revision(
+                self.active_rules_file()?,
+                snapshot,
+                previous,
+                RuleChange::Restored,
+                author,
+            )
+            .await?;
+        Ok(Some(restored))
+    }
+
+    async fn save_rule_revision(
+        &self,
+        file: Option<&RulesFile>,
+        rule: Rule,
+        previous: Option<Rule>,
+        change: RuleChange,
+        author: Option<&str>,
+    ) -> Result<Rule> {
+        let id = rule.id.unwrap_or_default();
+        let rule = Rule {
+            id: Some(id),
+            revision: self.next_rule_revision(&id.to_hex()).await?,
+            ..rule
+        };
+        let saved = match file {
+            Some(file) => file.create_or_update_rule(rule)?,
+            None => self.storage.create_or_update_rule(rule).await?,
+        };
+        self.storage
+            .add_rule_revision(RuleRevision::new(&saved, previous.as_ref(), change, author))
+            .await?;
+        Ok(saved)
+    }
+
+    async fn next_rule_revision(&self, rule_id: &str) -> Result<u32> {
+        Ok(self
+            .get_rule_revisions(rule_id)
+            .await?
+            .last()
+            .map_or(1, |r| r.revision + 1))
+    }
+
+    /// Overwrite the rules file in the journal repo with all rules from the database.
+    /// Returns the number of rules exported.
+    pub async fn export_rules_to_file(&self) -> Result<usize> {
+        let file = self.rules_file.as_ref().ok_or(Error::NoRulesFile)?;
+        let rules = self.storage.get_all_rules(None).await?;
+        let count = rules.len();
+        file.replace_all(rules)?;
+        Ok(count)
+    }
+
+    /// Upsert all rules from the rules file in the journal repo into the database, each as a
+    /// new revision. Returns the number of rules imported.
+    pub async fn import_rules_from_file(&self) -> Result<usize> {
+        let file = self.rules_file.as_ref().ok_or(Error::NoRulesFile)?;
+        let rules = file.get_all_rules(None)?;
+        let count = rules.len();
+        for rule in rules {
+            self.save_rule(None, rule, None).await?;
+        }
+        Ok(count)
+    }
+
+    fn active_rules_file(&self) -> Result<Option<&RulesFile>> {
+        if !self.rules_in_journal {
+            return Ok(None);
+        }
+        self.rules_file.as_ref().map(Some).ok_or(Error::NoRulesFile)
+    }
+
+    // BUDGETS
+
+    pub async fn create_or_update_budget(&self, budget: Budget) -> Result<Budget> {
+        self.storage.create_or_update_budget(budget).await
+    }
+
+    pub async fn get_all_budgets(&self) -> Result<Vec<Budget>> {
+        self.storage.get_all_budgets().await
+    }
+
+    pub async fn delete_budget(&self, budget_id: &str) -> Result<()> {
+        self.storage.delete_budget(budget_id).await
+    }
+
+    // AUTH
+
+    pub async fn get_auth(&self) -> Result<Option<TokenData>> {
+        self.storage.get_auth().await
+    }
+
+    pub async fn set_auth(&self, auth: Option<TokenData>) -> Result<()> {
+        self.storage.set_auth(auth).await
+    }
+
+    // TRANSACTIONS CACHE
+
+    pub async fn get_transactions<T>(&self, account_id: &str) -> Result<Vec<T>>
+    where
+        T: DeserializeOwned,
+    {
+        Ok(self
+            .storage
+            .get_transactions(account_id)
+            .await?
+            .into_iter()
+            .flat_map(bson::from_document::<T>)
+            .collect())
+    }
+
+    pub async fn cache_transactions(
+        &self,
+        account_id: &str,
+        real_transactions: &[impl RealTransaction],
+    ) -> Result<()> {
+        let docs = real_transactions.iter().flat_map(|t| t.to_doc()).collect();
+        self.storage.cache_transactions(account_id, docs).await
+    }
+
+    // BALANCE CACHE
+
+    pub async fn get_balance(&self, account_id: &str) -> Result<Vec<RealBalance>> {
+        self.storage.get_balance(account_id).await
+    }
+
+    pub async fn cache_balance(&self, account_id: &str, balance: Vec<RealBalance>) -> Result<()> {
+        self.storage.cache_balance(account_id, balance).await
+    }
+}
@@ -1,6 +1,7 @@
 use std::time::Instant;
 
-use bson::{doc, oid::ObjectId};
+use async_trait::async_trait;
+use bson::{doc, oid::ObjectId, Document};
 use futures::StreamExt;
 use log::info;
 use mongodb::{
@@ -8,15 +9,16 @@ use mongodb::{
         ClientOptions, FindOptions, InsertManyOptions, ResolverConfig, UpdateModifications,
         UpdateOptions,
     },
-    results::{DeleteResult, UpdateResult},
     Client, Collection,
 };
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde::{Deserialize, Serialize};
 
+use super::{Result, Storage};
 use crate::{
     config,
     model::{
-        balance::RealBalance, real_transaction::RealTransaction, rule::Rule, token_data::TokenData,
+        balance::RealBalance, budget::Budget, rule::Rule, rule_revision::RuleRevision,
+        token_data::TokenData,
     },
 };
 
@@ -27,41 +29,16 @@ struct Balance {
     balance: Vec<RealBalance>,
 }
 
-#[derive(Debug)]
-pub enum Error {
-    BsonSer(bson::ser::Error),
-    BsonOid(bson::oid::Error),
-    MongoDb(mongodb::error::Error),
-}
-
-impl From<bson::ser::Error> for Error {
-    fn from(e: bson::ser::Error) -> Self {
-        Error::BsonSer(e)
-    }
-}
-
-impl From<bson::oid::Error> for Error {
-    fn from(e: bson::oid::Error) -> Self {
-        Error::BsonOid(e)
-    }
-}
-
-impl From<mongodb::error::Error> for Error {
-    fn from(e: mongodb::error::Error) -> Self {
-        Error::MongoDb(e)
-    }
-}
-
-type Result<T> = std::result::Result<T, Error>;
-
-pub struct Database {
+pub struct MongoStorage {
     rules: Collection<Rule>,
+    rule_revisions: Collection<RuleRevision>,
+    budgets: Collection<Budget>,
     authentication: Collection<TokenData>,
     balances: Collection<Balance>,
     database: mongodb::Database,
 }
 
-impl Database {
+impl MongoStorage {
     pub async fn new() -> Result<Self> {
         // Parse a connection string into an options struct.
         let start = Instant::now();
@@ -78,13 +55,17 @@ impl Database {
         let client = Client::with_options(options)?;
         let database = client.database("ledger");
         let rules = database.collection::<Rule>("rules");
+        let rule_revisions = database.collection::<RuleRevision>("rule_revisions");
+        let budgets = database.collection::<Budget>("budgets");
         let authentication = database.collection::<TokenData>("auth");
         let balances = database.collection::<Balance>("balances");
 
         info!("Connected to MongoDB! This took {:?}", start.elapsed());
 
-        let db = Database {
+        let db = MongoStorage {
             rules,
+            rule_revisions,
+            budgets,
             authentication,
             balances,
             database,
@@ -92,24 +73,26 @@ impl Database {
 
         Ok(db)
     }
+}
 
+#[async_trait]
+impl Storage for MongoStorage {
     // RULES
 
-    pub async fn create_or_update_rule(&self, rule: Rule) -> Result<UpdateResult> {
+    async fn create_or_update_rule(&self, rule: Rule) -> Result<Rule> {
+        let id = rule.id.unwrap_or_default();
         let opts = UpdateOptions::builder().upsert(true).build();
         let update = make_update(&rule)?;
-        let result = self
-            .rules
-            .update_one(
-                doc! {"_id": &rule.id.unwrap_or_default()},
-                update,
-                Some(opts),
-            )
+        self.rules
+            .update_one(doc! {"_id": &id}, update, Some(opts))
             .await?;
-        Ok(result)
+        Ok(Rule {
+            id: Some(id),
+            ..rule
+        })
     }
 
-    pub async fn get_all_rules(&self, filter_by_importer_id: Option<&str>) -> Result<Vec<Rule>> {
+    async fn get_all_rules(&self, filter_by_importer_id: Option<&str>) -> Result<Vec<Rule>> {
         let options = FindOptions::builder().sort(doc!["priority": 1]).build();
         Ok(self
             .rules
@@ -125,23 +108,77 @@ impl Database {
             .collect())
     }
 
-    pub async fn get_rule(&self, rule_id: &str) -> Result<Option<Rule>> {
+    async fn get_rule(&self, rule_id: &str) -> Result<Option<Rule>> {
         Ok(self
             .rules
             .find_one(doc!["_id": ObjectId::parse_str(rule_id)?], None)
             .await?)
     }
 
-    pub async fn delete_rule(&self, rule_id: &str) -> Result<DeleteResult> {
+    async fn delete_rule(&self, rule_id: &str) -> Result<u64> {
         Ok(self
             .rules
             .delete_one(doc!["_id": ObjectId::parse_str(rule_id)?], None)
-            .await?)
+            .await?
+            .deleted_count)
+    }
+
+    async fn add_rule_revision(&self, revision: RuleRevision) -> Result<()> {
+        self.rule_revisions.insert_one(revision, None).await?;
+        Ok(())
+    }
+
+    async fn get_rule_revisions(&self, rule_id: &str) -> Result<Vec<RuleRevision>> {
+        let options = FindOptions::builder().sort(doc!["revision": 1]).build();
+        Ok(self
+            .rule_revisions
+            .find(doc!["ruleId": ObjectId::parse_str(rule_id)?], Some(options))
+            .await?
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .flatten()
+            .collect())
+    }
+
+    // BUDGETS
+
+    async fn create_or_update_budget(&self, budget: Budget) -> Result<Budget> {
+        let id = budget.id.unwrap_or_default();
+        let opts = UpdateOptions::builder().upsert(true).build();
+        let update = make_update(&budget)?;
+        self.budgets
+            .update_one(doc! {"_id": &id}, update, Some(opts))
+            .await?;
+        Ok(Budget {
+            id: Some(id),
+            ..budget
+        })
+    }
+
+    async fn get_all_budgets(&self) -> Result<Vec<Budget>> {
+        let options = FindOptions::builder().sort(doc!["account": 1]).build();
+        Ok(self
+            .budgets
+            .find(None, Some(options))
+            .await?
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .flatten()
+            .collect())
+    }
+
+    async fn delete_budget(&self, budget_id: &str) -> Result<()> {
+        self.budgets
+            .delete_one(doc!["_id": ObjectId::parse_str(budget_id)?], None)
+            .await?;
+        Ok(())
     }
 
     // AUTH
 
-    pub async fn get_auth(&self) -> Result<Option<TokenData>> {
+    async fn get_auth(&self) -> Result<Option<TokenData>> {
         let start = Instant::now();
 
         let doc = self.authentication.find_one(None, None).await?;
@@ -150,7 +187,7 @@ impl Database {
         Ok(doc)
     }
 
-    pub async fn set_auth(&self, auth: Option<TokenData>) -> Result<()> {
+    async fn set_auth(&self, auth: Option<TokenData>) -> Result<()> {
         if let Some(auth) = auth {
             self.authentication.insert_one(auth, None).await?;
         } else {
@@ -161,11 +198,8 @@ impl Database {
 
     // TRANSACTIONS CACHE
 
-    pub async fn get_transactions<T>(&self, account_id: &str) -> Result<Vec<T>>
-    where
-        T: DeserializeOwned,
-    {
-        let collection = self.database.collection(account_id);
+    async fn get_transactions(&self, account_id: &str) -> Result<Vec<Document>> {
+        let collection = self.database.collection::<Document>(account_id);
         Ok(collection
             .find(None, None)
             .await?
@@ -173,17 +207,11 @@ impl Database {
             .await
             .into_iter()
             .flatten()
-            .flat_map(bson::from_document::<T>)
             .collect())
     }
 
-    pub async fn cache_transactions(
-        &self,
-        account_id: &str,
-        real_transactions: &[impl RealTransaction],
-    ) -> Result<()> {
-        let collection = self.database.collection(account_id);
-        let docs = real_transactions.iter().flat_map(|t| t.to_doc());
+    async fn cache_transactions(&self, account_id: &str, docs: Vec<Document>) -> Result<()> {
+        let collection = self.database.collection::<Document>(account_id);
         let options = InsertManyOptions::builder().ordered(false).build();
         let result = collection.insert_many(docs, options).await;
         // Swallow BulkWriteErrors as these are thrown when duplicate keys exist
@@ -198,7 +226,7 @@ impl Database {
 
     // BALANCE CACHE
 
-    pub async fn get_balance(&self, account_id: &str) -> Result<Vec<RealBalance>> {
+    async fn get_balance(&self, account_id: &str) -> Result<Vec<RealBalance>> {
         let doc = self
             .balances
             .find_one(doc!["_id": account_id], None)
@@ -208,7 +236,7 @@ impl Database {
         Ok(doc)
     }
 
-    pub async fn cache_balance(&self, account_id: &str, balance: Vec<RealBalance>) -> Result<()> {
+    async fn cache_balance(&self, account_id: &str, balance: Vec<RealBalance>) -> Result<()> {
         let options = UpdateOptions::builder().upsert(true).build();
         let update = make_update(&Balance {
             account_id: account_id.to_string(),
@@ -0,0 +1,152 @@
+use std::{fs, io, path::PathBuf, sync::Mutex};
+
+use bson::oid::ObjectId;
+use log::info;
+
+use super::Result;
+use crate::model::rule::Rule;
+
+/// Rules persisted as YAML inside the journal repo, so they are versioned and committed
+/// together with the journal they shape.
+pub struct RulesFile {
+    path: PathBuf,
+    // Serialize read-modify-write cycles
+    lock: Mutex<()>,
+}
+
+impl RulesFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn create_or_update_rule(&self, rule: Rule) -> Result<Rule> {
+        let _guard = self.lock.lock().unwrap();
+        let rule = Rule {
+            id: Some(rule.id.unwrap_or_default()),
+            ..rule
+        };
+        let mut rules = self.read()?;
+        match rules.iter_mut().find(|r| r.id == rule.id) {
+            Some(existing) => *existing = rule.clone(),
+            None => rules.push(rule.clone()),
+        }
+        self.write(rules)?;
+        Ok(rule)
+    }
+
+    pub fn get_all_rules(&self, filter_by_importer_id: Option<&str>) -> Result<Vec<Rule>> {
+        let _guard = self.lock.lock().unwrap();
+        let mut rules = self.read()?;
+        if let Some(importer_id) = filter_by_importer_id {
+            rules.retain(|r| r.importer_id == importer_id);
+        }
+        rules.sort_by_key(|r| r.priority);
+        Ok(rules)
+    }
+
+    pub fn get_rule(&self, rule_id: &str) -> Result<Option<Rule>> {
+        let _guard = self.lock.lock().unwrap();
+        let id = ObjectId::parse_str(rule_id)?;
+        Ok(self.read()?.into_iter().find(|r| r.id == Some(id)))
+    }
+
+    /// Returns how many rules were deleted
+    pub fn delete_rule(&self, rule_id: &str) -> Result<u64> {
+        let _guard = self.lock.lock().unwrap();
+        let id = ObjectId::parse_str(rule_id)?;
+        let mut rules = self.read()?;
+        let count = rules.len();
+        rules.retain(|r| r.id != Some(id));
+        let deleted = (count - rules.len()) as u64;
+        self.write(rules)?;
+        Ok(deleted)
+    }
+
+    /// Replace the contents of the file with the given rules
+    pub fn replace_all(&self, rules: Vec<Rule>) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        self.write(rules)
+    }
+
+    fn read(&self) -> Result<Vec<Rule>> {
+        match fs::read_to_string(&self.path) {
+            Ok(yaml) => Ok(serde_yaml::from_str::<Option<Vec<Rule>>>(&yaml)?.unwrap_or_default()),
+            // No rules have been written yet
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write(&self, mut rules: Vec<Rule>) -> Result<()> {
+        // Keep a stable order so diffs in the journal repo stay small
+        rules.sort_by(|a, b| {
+            a.importer_id
+                .cmp(&b.importer_id)
+                .then(a.priority.cmp(&b.priority))
+                .then(a.rule_name.cmp(&b.rule_name))
+        });
+        info!("Writing {} rules to {:#?}", rules.len(), self.path);
+        fs::write(&self.path, serde_yaml::to_string(&rules)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use regex::Regex;
+
+    use super::RulesFile;
+    use crate::model::rule::Rule;
+
+    #[test]
+    fn rules_round_trip() {
+        let path = env::temp_dir().join(format!("rules_round_trip-{}.yml", std::process::id()));
+        let file = RulesFile::new(path.clone());
+        assert!(file.get_all_rules(None).unwrap().is_empty());
+
+        let rule = file
+            .create_or_update_rule(Rule {
+                importer_id: "n26".to_string(),
+                rule_name: "Amazon".to_string(),
+                priority: 2,
+                match_field_regex: Regex::new("(?i)amazon").unwrap(),
+                ..Rule::default()
+            })
+            .unwrap();
+        file.create_or_update_rule(Rule {
+            importer_id: "n26".to_string(),
+            rule_name: "Rent".to_string(),
+            priority: 1,
+            ..Rule::default()
+        })
+        .unwrap();
+        let id = rule.id.unwrap().to_hex();
+
+        let rules = file.get_all_rules(Some("n26")).unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].rule_name, "Rent");
+        assert!(file.get_all_rules(Some("ing")).unwrap().is_empty());
+
+        let found = file.get_rule(&id).unwrap().unwrap();
+        assert_eq!(found.match_field_regex.as_str(), "(?i)amazon");
+
+        file.create_or_update_rule(Rule {
+            priority: 0,
+            ..found
+        })
+        .unwrap();
+        assert_eq!(file.get_all_rules(None).unwrap()[0].rule_name, "Amazon");
+
+        assert_eq!(file.delete_rule(&id).unwrap(), 1);
+        assert!(file.get_rule(&id).unwrap().is_none());
+        assert_eq!(file.delete_rule(&id).unwrap(), 0);
+        assert_eq!(file.get_all_rules(None).unwrap().len(), 1);
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,522 @@
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use bson::{oid::ObjectId, Document};
+use log::info;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::{Result, Storage};
+use crate::model::{
+    balance::RealBalance, budget::Budget, rule::Rule, rule_revision::RuleRevision,
+    token_data::TokenData,
+};
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS rules (
+    id          TEXT PRIMARY KEY,
+    importer_id TEXT NOT NULL,
+    priority    INTEGER NOT NULL,
+    rule        TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS rule_revisions (
+    rule_id  TEXT NOT NULL,
+    revision INTEGER NOT NULL,
+    data     TEXT NOT NULL,
+    PRIMARY KEY (rule_id, revision)
+);
+CREATE TABLE IF NOT EXISTS budgets (
+    id      TEXT PRIMARY KEY,
+    account TEXT NOT NULL,
+    budget  TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS auth (
+    id    INTEGER PRIMARY KEY CHECK (id = 0),
+    token TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS balances (
+    account_id TEXT PRIMARY KEY,
+    balance    TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS transactions (
+    account_id TEXT NOT NULL,
+    id         TEXT NOT NULL,
+    document   BLOB NOT NULL,
+    PRIMARY KEY (account_id, id)
+);
+"#;
+
+/// Embedded storage for setups which don't want to run a MongoDB server.
+/// Rules, auth and balances are stored as JSON, cached transactions as BSON so they
+/// deserialize exactly as they would from MongoDB.
+pub struct SqliteStorage {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &str) -> Result<Self> {
+        info!("Opening SQLite database {}", path);
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(connection: Connection) -> Result<Self> {
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    // RULES
+
+    async fn create_or_update_rule(&self, rule: Rule) -> Result<Rule> {
+        let rule = Rule {
+            id: Some(rule.id.unwrap_or_default()),
+            ..rule
+        };
+        let json = serde_json::to_string(&rule)?;
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO rules (id, importer_id, priority, rule) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(id) DO UPDATE SET importer_id = ?2, priority = ?3, rule = ?4",
+            params![
+                rule.id.unwrap().to_hex(),
+                rule.importer_id,
+                rule.priority,
+                json
+            ],
+        )?;
+        Ok(rule)
+    }
+
+    async fn get_all_rules(&self, filter_by_importer_id: Option<&str>) -> Result<Vec<Rule>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(
+            "SELECT rule FROM rules WHERE ?1 IS NULL OR importer_id = ?1 ORDER BY priority",
+        )?;
+        let rows = statement.query_map(params![filter_by_importer_id], |row| {
+            row.get::<_, String>(0)
+        })?;
+        let mut rules = vec![];
+        for json in rows {
+            rules.push(serde_json::from_str(&json?)?);
+        }
+        Ok(rules)
+    }
+
+    async fn get_rule(&self, rule_id: &str) -> Result<Option<Rule>> {
+        let id = ObjectId::parse_str(rule_id)?;
+        let json: Option<String> = self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT rule FROM rules WHERE id = ?1",
+                params![id.to_hex()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(json.map(|j| serde_json::from_str(&j)).transpose()?)
+    }
+
+    async fn delete_rule(&self, rule_id: &str) -> Result<u64> {
+        let id = ObjectId::parse_str(rule_id)?;
+        let deleted = self
+            .connection
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM rules WHERE id = ?1", params![id.to_hex()])?;
+        Ok(deleted as u64)
+    }
+
+    async fn add_rule_revision(&self, revision: RuleRevision) -> Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO rule_revisions (rule_id, revision, data) VALUES (?1, ?2, ?3)",
+            params![
+                revision.rule_id.to_hex(),
+                revision.revision,
+                serde_json::to_string(&revision)?
+            ],
+        )?;
+        Ok(())
+    }
+
+    async fn get_rule_revisions(&self, rule_id: &str) -> Result<Vec<RuleRevision>> {
+        let id = ObjectId::parse_str(rule_id)?;
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare("SELECT data FROM rule_revisions WHERE rule_id = ?1 ORDER BY revision")?;
+        let rows = statement.query_map(params![id.to_hex()], |row| row.get::<_, String>(0))?;
+        let mut revisions = vec![];
+        for json in rows {
+            revisions.push(serde_json::from_str(&json?)?);
+        }
+        Ok(revisions)
+    }
+
+    // BUDGETS
+
+    async fn create_or_update_budget(&self, budget: Budget) -> Result<Budget> {
+        let budget = Budget {
+            id: Some(budget.id.unwrap_or_default()),
+            ..budget
+        };
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO budgets (id, account, budget) VALUES (?1, ?2, ?3)
+             ON CONFLICT(id) DO UPDATE SET account = ?2, budget = ?3",
+            params![
+                budget.id.unwrap().to_hex(),
+                budget.account,
+                serde_json::to_string(&budget)?
+            ],
+        )?;
+        Ok(budget)
+    }
+
+    async fn get_all_budgets(&self) -> Result<Vec<Budget>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare("SELECT budget FROM budgets ORDER BY account")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+        let mut budgets = vec![];
+        for json in rows {
+            budgets.push(serde_json::from_str(&json?)?);
+        }
+        Ok(budgets)
+    }
+
+    async fn delete_budget(&self, budget_id: &str) -> Result<()> {
+        let id = ObjectId::parse_str(budget_id)?;
+        self.connection
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM budgets WHERE id = ?1", params![id.to_hex()])?;
+        Ok(())
+    }
+
+    // AUTH
+
+    async fn get_auth(&self) -> Result<Option<TokenData>> {
+        let json: Option<String> = self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row("SELECT token FROM auth WHERE id = 0", [], |row| row.get(0))
+            .optional()?;
+        Ok(json.map(|j| serde_json::from_str(&j)).transpose()?)
+    }
+
+    async fn set_auth(&self, auth: Option<TokenData>) -> Result<()> {
+        let connection = self.connection.lock().unwrap();
+        if let Some(auth) = auth {
+            connection.execute(
+                "INSERT INTO auth (id, token) VALUES (0, ?1)
+                 ON CONFLICT(id) DO UPDATE SET token = ?1",
+                params![serde_json::to_string(&auth)?],
+            )?;
+        } else {
+            connection.execute("DELETE FROM auth", [])?;
+        }
+        Ok(())
+    }
+
+    // TRANSACTIONS CACHE
+
+    async fn get_transactions(&self, account_id: &str) -> Result<Vec<Document>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement =
+            connection.prepare("SELECT document FROM transactions WHERE account_id = ?1")?;
+        let rows = statement.query_map(params![account_id], |row| row.get::<_, Vec<u8>>(0))?;
+        let mut docs = vec![];
+        for bytes in rows {
+            docs.push(Document::from_reader(&mut bytes?.as_slice())?);
+        }
+        Ok(docs)
+    }
+
+    async fn cache_transactions(&self, account_id: &str, docs: Vec<Document>) -> Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        {
+            let mut statement = transaction.prepare(
+                "INSERT OR IGNORE INTO transactions (account_id, id, document) VALUES (?1, ?2, ?3)",
+            )?;
+            for doc in docs {
+                let id = match doc.get("_id") {
+                    Some(bson::Bson::String(id)) => id.clone(),
+                    Some(other) => other.to_string(),
+                    None => continue,
+                };
+                let mut bytes = vec![];
+                doc.to_writer(&mut bytes)?;
+                statement.execute(params![account_id, id, bytes])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    // BALANCE CACHE
+
+    async fn get_balance(&self, account_id: &str) -> Result<Vec<RealBalance>> {
+        let json: Option<String> = self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT balance FROM balances WHERE account_id = ?1",
+                params![account_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(json
+            .map(|j| serde_json::from_str(&j))
+            .transpose()?
+            .unwrap_or_default())
+    }
+
+    async fn cache_balance(&self, account_id: &str, balance: Vec<RealBalance>) -> Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO balances (account_id, balance) VALUES (?1, ?2)
+             ON CONFLICT(account_id) DO UPDATE SET balance = ?2",
+            params![account_id, serde_json::to_string(&balance)?],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+    use rust_decimal::Decimal;
+
+    use super::{SqliteStorage, Storage};
+    use crate::{
+        db::Database,
+        model::{
+            balance::RealBalance,
+            budget::{Budget, BudgetPeriod},
+            n26_transaction::N26Transaction,
+            real_transaction::RealTransaction,
+            rule::Rule,
+            rule_revision::RuleChange,
+        },
+        test_statics::REAL,
+    };
+
+    #[actix_rt::test]
+    async fn rules_round_trip() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        let rule = Rule {
+            importer_id: "n26".to_string(),
+            rule_name: "Amazon".to_string(),
+            priority: 2,
+            match_field_regex: Regex::new("(?i)amazon").unwrap(),
+            ..Rule::default()
+        };
+        let stored = storage.create_or_update_rule(rule).await.unwrap();
+        let id = stored.id.unwrap().to_hex();
+
+        let other = Rule {
+            importer_id: "ing".to_string(),
+            priority: 1,
+            ..Rule::default()
+        };
+        storage.create_or_update_rule(other).await.unwrap();
+
+        let found = storage.get_rule(&id).await.unwrap().unwrap();
+        assert_eq!(found.rule_name, "Amazon");
+        assert_eq!(found.match_field_regex.as_str(), "(?i)amazon");
+        assert_eq!(storage.get_all_rules(None).await.unwrap().len(), 2);
+        assert_eq!(storage.get_all_rules(Some("n26")).await.unwrap().len(), 1);
+
+        let updated = Rule {
+            rule_name: "Amazon Prime".to_string(),
+            ..found
+        };
+        storage.create_or_update_rule(updated).await.unwrap();
+        let found = storage.get_rule(&id).await.unwrap().unwrap();
+        assert_eq!(found.rule_name, "Amazon Prime");
+
+        assert_eq!(storage.delete_rule(&id).await.unwrap(), 1);
+        assert!(storage.get_rule(&id).await.unwrap().is_none());
+        assert_eq!(storage.delete_rule(&id).await.unwrap(), 0);
+    }
+
+    #[actix_rt::test]
+    async fn rule_revisions() {
+        let db = Database::in_memory().unwrap();
+        let rule = Rule {
+            importer_id: "n26".to_string(),
+            rule_name: "Amazon".to_string(),
+            ..Rule::default()
+        };
+        let created = db.create_or_update_rule(rule, Some("sam")).await.unwrap();
+        assert_eq!(created.revision, 1);
+        let id = created.id.unwrap().to_hex();
+
+        let updated = Rule {
+            rule_name: "Amazon Prime".to_string(),
+            ..created
+        };
+        let updated = db.create_or_update_rule(updated, None).await.unwrap();
+        assert_eq!(updated.revision, 2);
+
+        assert_eq!(db.delete_rule(&id, Some("sam")).await.unwrap(), 1);
+        assert!(db.get_rule(&id).await.unwrap().is_none());
+
+        let revisions = db.get_rule_revisions(&id).await.unwrap();
+        assert_eq!(revisions.len(), 3);
+        assert_eq!(revisions[0].change, RuleChange::Created);
+        assert_eq!(revisions[1].change, RuleChange::Updated);
+        assert_eq!(revisions[1].diff[0].field, "ruleName");
+        assert_eq!(revisions[2].change, RuleChange::Deleted);
+
+        let restored = db
+            .restore_rule_revision(&id, 1, Some("sam"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(restored.rule_name, "Amazon");
+        assert_eq!(restored.revision, 4);
+        assert_eq!(db.get_rule(&id).await.unwrap().unwrap().rule_name, "Amazon");
+        assert!(db
+            .restore_rule_revision(&id, 9, None)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[actix_rt::test]
+    async fn budgets_round_trip() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        let budget = Budget {
+            id: None,
+            account: "Expenses:Food".to_string(),
+            amount: Decimal::new(400, 0),
+            commodity: "EUR".to_string(),
+            period: BudgetPeriod::Monthly,
+        };
+        let stored = storage.create_or_update_budget(budget).await.unwrap();
+        let id = stored.id.unwrap().to_hex();
+        storage
+            .create_or_update_budget(Budget {
+                amount: Decimal::new(450, 0),
+                ..stored
+            })
+            .await
+            .unwrap();
+        let budgets = storage.get_all_budgets().await.unwrap();
+        assert_eq!(budgets.len(), 1);
+        assert_eq!(budgets[0].amount, Decimal::new(450, 0));
+
+        storage.delete_budget(&id).await.unwrap();
+        assert!(storage.get_all_budgets().await.unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn import_rules_with_revisions() {
+        let path = std::env::temp_dir().join(format!(
+            "import_rules_with_revisions-{}.yml",
+            std::process::id()
+        ));
+        let db = Database::in_memory_with_rules_file(path.clone(), true).unwrap();
+        let rule = db
+            .create_or_update_rule(
+                Rule {
+                    importer_id: "n26".to_string(),
+                    rule_name: "Amazon".to_string(),
+                    ..Rule::default()
+                },
+                None,
+            )
+            .await
+            .unwrap();
+        let id = rule.id.unwrap().to_hex();
+
+        assert_eq!(db.import_rules_from_file().await.unwrap(), 1);
+        let revisions = db.get_rule_revisions(&id).await.unwrap();
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[1].change, RuleChange::Created);
+        assert_eq!(revisions[1].rule.revision, 2);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn export_rules_from_database() {
+        let path = std::env::temp_dir().join(format!(
+            "export_rules_from_database-{}.yml",
+            std::process::id()
+        ));
+        let db = Database::in_memory_with_rules_file(path.clone(), false).unwrap();
+        db.create_or_update_rule(
+            Rule {
+                importer_id: "n26".to_string(),
+                rule_name: "Amazon".to_string(),
+                ..Rule::default()
+            },
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(db.export_rules_to_file().await.unwrap(), 1);
+        let journal = Database::in_memory_with_rules_file(path.clone(), true).unwrap();
+        let rules = journal.get_all_rules(None).await.unwrap();
+        assert_eq!(rules[0].rule_name, "Amazon");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn transactions_round_trip() {
+        let db = Database::in_memory().unwrap();
+        db.cache_transactions("n26", &REAL).await.unwrap();
+        // Caching again should ignore duplicates
+        db.cache_transactions("n26", &REAL[..1]).await.unwrap();
+
+        let cached: Vec<N26Transaction> = db.get_transactions("n26").await.unwrap();
+        assert_eq!(cached.len(), REAL.len());
+        assert!(cached.iter().any(|t| t.get_id() == REAL[0].get_id()));
+        assert!(db
+            .get_transactions::<N26Transaction>("ing")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn balance_round_trip() {
+        let db = Database::in_memory().unwrap();
+        assert!(db.get_balance("n26").await.unwrap().is_empty());
+        let balance = vec![RealBalance {
+            commodity: "EUR".to_string(),
+            amount: Decimal::new(12345, 2),
+            base_amount: None,
+        }];
+        db.cache_balance("n26", balance.clone()).await.unwrap();
+        db.cache_balance("n26", balance).await.unwrap();
+
+        let cached = db.get_balance("n26").await.unwrap();
+        assert_eq!(cached.len(), 1);
+        assert_eq!(cached[0].commodity, "EUR");
+        assert_eq!(cached[0].amount, Decimal::new(12345, 2));
+    }
+
+    #[actix_rt::test]
+    async fn auth_round_trip() {
+        let db = Database::in_memory().unwrap();
+        assert!(db.get_auth().await.unwrap().is_none());
+        let auth = serde_json::from_str(
+            r#"{"access_token": "access", "refresh_token": "refresh", "expires_in": 100}"#,
+        )
+        .unwrap();
+        db.set_auth(Some(auth)).await.unwrap();
+        assert_eq!(db.get_auth().await.unwrap().unwrap().access_token, "access");
+        db.set_auth(None).await.unwrap();
+        assert!(db.get_auth().await.unwrap().is_none());
+    }
+}
@@ -35,8 +35,24 @@ pub fn get_prices_file() -> Option<PathBuf> {
     Some(get_journal_path()?.join("prices.ledger"))
 }
 
+pub fn get_rules_file() -> Option<PathBuf> {
+    Some(get_journal_path()?.join("rules.yml"))
+}
+
+pub fn get_budget_file() -> Option<PathBuf> {
+    Some(get_journal_path()?.join("budget.ledger"))
+}
+
+pub fn get_account_metadata_file() -> Option<PathBuf> {
+    Some(get_journal_path()?.join("accounts.yml"))
+}
+
+pub fn get_lookup_tables_file() -> Option<PathBuf> {
+    Some(get_journal_path()?.join("lookups.yml"))
+}
+
 pub fn get_repo_path() -> Option<PathBuf> {
-    let repo_url = config::journal_repo_url();
+    let repo_url = config::journal_repo_url_if_set()?;
     let (_, repo_name) = repo_url.rsplit_once("/")?;
     let root = get_root_path()?;
     Some(root.join(repo_name))
@@ -0,0 +1,199 @@
+use std::{cmp::Reverse, collections::BTreeMap};
+
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::model::hledger_transaction::HledgerTransaction;
+
+#[derive(Debug, Serialize)]
+pub struct FlowNode {
+    pub name: String,
+}
+
+/// `source` and `target` are indices into the nodes
+#[derive(Debug, Serialize)]
+pub struct FlowLink {
+    pub source: usize,
+    pub target: usize,
+    pub value: Decimal,
+}
+
+/// Nodes and links for a Sankey chart
+#[derive(Debug, Serialize)]
+pub struct FlowReport {
+    pub nodes: Vec<FlowNode>,
+    pub links: Vec<FlowLink>,
+}
+
+/// Money flowing between accounts in the period, e.g. from income to assets and from assets to
+/// expenses. Within a transaction every posting that's credited is split over the debited
+/// postings by their share of the total. Accounts are cut to `depth` levels, flows within an
+/// account are dropped and flows in both directions between two accounts are netted. Links
+/// smaller than `min_amount` are left out.
+pub fn flow_report(
+    transactions: &[HledgerTransaction],
+    from: NaiveDate,
+    to: NaiveDate,
+    commodity: &str,
+    depth: usize,
+    min_amount: Decimal,
+) -> FlowReport {
+    let mut flows = BTreeMap::<(String, String), Decimal>::new();
+    for transaction in transactions {
+        let postings: Vec<(String, Decimal)> = transaction
+            .get_posting_amounts()
+            .filter(|&(_, date, _, c)| c == commodity && from <= date && date <= to)
+            .map(|(account, _, amount, _)| (truncate_account(account, depth), amount))
+            .collect();
+        let debited: Decimal = postings
+            .iter()
+            .map(|(_, a)| a)
+            .filter(|a| a.is_sign_positive())
+            .sum();
+        if debited.is_zero() {
+            continue;
+        }
+        for (source, credit) in postings.iter().filter(|(_, a)| a.is_sign_negative()) {
+            for (target, debit) in postings.iter().filter(|(_, a)| a.is_sign_positive()) {
+                if source == target {
+                    continue;
+                }
+                let value = -credit * debit / debited;
+                // Keep a single direction per pair of accounts
+                if source < target {
+                    *flows.entry((source.clone(), target.clone())).or_default() += value;
+                } else {
+                    *flows.entry((target.clone(), source.clone())).or_default() -= value;
+                }
+            }
+        }
+    }
+
+    let mut nodes: Vec<String> = vec![];
+    let mut node_index = |name: String| match nodes.iter().position(|n| *n == name) {
+        Some(i) => i,
+        None => {
+            nodes.push(name);
+            nodes.len() - 1
+        }
+    };
+    let mut links = vec![];
+    for ((a, b), value) in flows {
+        let (source, target, value) = if value.is_sign_negative() {
+            (b, a, -value)
+        } else {
+            (a, b, value)
+        };
+        let value = value.round_dp(2);
+        if value.is_zero() || value < min_amount {
+            continue;
+        }
+        links.push(FlowLink {
+            source: node_index(source),
+            target: node_index(target),
+            value,
+        });
+    }
+    links.sort_by_key(|l| Reverse(l.value));
+
+    FlowReport {
+        nodes: nodes.into_iter().map(|name| FlowNode { name }).collect(),
+        links,
+    }
+}
+
+fn truncate_account(account: &str, depth: usize) -> String {
+    account.split(':').take(depth).collect::<Vec<_>>().join(":")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+
+    use super::flow_report;
+    use crate::{model::hledger_transaction::HledgerTransaction, test_statics::transaction};
+
+    /// A transaction in EUR in March
+    fn march(day: u32, postings: &[(&str, i64)]) -> HledgerTransaction {
+        let postings: Vec<(&str, &str, i64)> = postings
+            .iter()
+            .map(|&(account, amount)| (account, "EUR", amount))
+            .collect();
+        transaction(NaiveDate::from_ymd(2021, 3, day), &postings)
+    }
+
+    fn links(report: &super::FlowReport) -> Vec<(&str, &str, Decimal)> {
+        report
+            .links
+            .iter()
+            .map(|l| {
+                (
+                    report.nodes[l.source].name.as_str(),
+                    report.nodes[l.target].name.as_str(),
+                    l.value,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn income_to_assets_to_expenses() {
+        let transactions = vec![
+            march(1, &[("Income:Salary", -3000), ("Assets:Cash:N26", 3000)]),
+            // Split over two categories
+            march(
+                2,
+                &[
+                    ("Assets:Cash:N26", -100),
+                    ("Expenses:Food:Groceries", 60),
+                    ("Expenses:Food:Restaurants", 10),
+                    ("Expenses:Household", 30),
+                ],
+            ),
+            // Outside the period
+            march(31, &[("Assets:Cash:N26", -50), ("Expenses:Household", 50)]),
+        ];
+        let report = flow_report(
+            &transactions,
+            NaiveDate::from_ymd(2021, 3, 1),
+            NaiveDate::from_ymd(2021, 3, 30),
+            "EUR",
+            2,
+            Decimal::ZERO,
+        );
+        assert_eq!(
+            links(&report),
+            vec![
+                ("Income:Salary", "Assets:Cash", Decimal::new(3000, 0)),
+                ("Assets:Cash", "Expenses:Food", Decimal::new(70, 0)),
+                ("Assets:Cash", "Expenses:Household", Decimal::new(30, 0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn nets_opposite_flows_and_filters_small_links() {
+        let transactions = vec![
+            march(1, &[("Assets:Cash", -500), ("Assets:Savings", 500)]),
+            march(2, &[("Assets:Savings", -200), ("Assets:Cash", 200)]),
+            // Within one account at depth 2
+            march(3, &[("Assets:Cash:N26", -10), ("Assets:Cash:ING", 10)]),
+            march(4, &[("Assets:Cash", -5), ("Expenses:Fees", 5)]),
+        ];
+        let report = flow_report(
+            &transactions,
+            NaiveDate::from_ymd(2021, 3, 1),
+            NaiveDate::from_ymd(2021, 3, 31),
+            "EUR",
+            2,
+            Decimal::new(10, 0),
+        );
+        assert_eq!(
+            links(&report),
+            vec![("Assets:Cash", "Assets:Savings", Decimal::new(300, 0))]
+        );
+        assert_eq!(report.nodes.len(), 2);
+    }
+}
@@ -0,0 +1,300 @@
+use chrono::{Datelike, NaiveDate};
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use serde::Serialize;
+
+use crate::{
+    model::{aligned_data::AlignedData, budget::Budget, hledger_transaction::HledgerTransaction},
+    net_worth::{self, is_asset_or_liability},
+    prices::PriceTable,
+    subscriptions::{add_months, Subscription},
+};
+
+const DAYS_PER_MONTH: f64 = 30.44;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Overdraft {
+    pub date: NaiveDate,
+    pub expected: Decimal,
+    pub lower: Decimal,
+    /// The expected cash is negative, not just the lower end of the band
+    pub likely: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Forecast {
+    /// Expected cash, the lower and upper end of its band and the expected net worth from
+    /// today to the end of every month
+    pub data: AlignedData,
+    /// Months in which the cash may run out
+    pub overdrafts: Vec<Overdraft>,
+}
+
+/// From when and how far to forecast
+#[derive(Debug, Clone, Copy)]
+pub struct ForecastOptions {
+    pub today: NaiveDate,
+    /// Months after the current one
+    pub months: u32,
+}
+
+/// Project the cash, the currency balances of the asset and liability accounts, and the net
+/// worth the months of `options` past the current one. The planned cash flow is made up of the
+/// budgets and periodic transactions of income and expense accounts and the expected payments
+/// of the active subscriptions, so budgets are best kept for the spending subscriptions don't
+/// cover. Other holdings keep their current value. The band is one standard deviation of the
+/// monthly cash flow of the last year, growing with the square root of the months ahead.
+pub fn forecast(
+    transactions: &[HledgerTransaction],
+    budgets: &[Budget],
+    subscriptions: &[Subscription],
+    prices: &PriceTable,
+    base_currency: &str,
+    is_currency: impl Fn(&str) -> bool,
+    options: &ForecastOptions,
+) -> Forecast {
+    let ForecastOptions { today, months } = *options;
+    let month_start = NaiveDate::from_ymd(today.year(), today.month(), 1);
+    // The spread is taken from the cash flow of the last year
+    let history = net_worth::month_ends(
+        NaiveDate::from_ymd(today.year() - 1, today.month(), 1),
+        today,
+    );
+    let (valued, _) = net_worth::value_balances(transactions, prices, base_currency, &history);
+    let cash_history: Vec<Decimal> = valued
+        .iter()
+        .map(|balances| {
+            balances
+                .iter()
+                .filter(|b| is_currency(b.commodity))
+                .map(|b| b.value)
+                .sum()
+        })
+        .collect();
+    let cash = cash_history.last().copied().unwrap_or_default();
+    let current_net_worth: Decimal = valued.last().into_iter().flatten().map(|b| b.value).sum();
+    // The current month isn't over yet
+    let full_months = &cash_history[..cash_history.len() - 1];
+    let spread = standard_deviation(
+        &full_months
+            .windows(2)
+            .map(|w| (w[1] - w[0]).to_f64().unwrap_or_default())
+            .collect::<Vec<f64>>(),
+    );
+
+    let mut dates = vec![today];
+    dates.extend(net_worth::month_ends(
+        today,
+        add_months(month_start, months + 1).pred(),
+    ));
+    dates.dedup();
+    let flows = planned_flows(&dates, budgets, subscriptions, |amount, commodity| {
+        prices.convert(amount, commodity, base_currency, today)
+    });
+
+    let mut expected = vec![cash];
+    for flow in &flows[1..] {
+        expected.push(expected[expected.len() - 1] + flow);
+    }
+    let band: Vec<Decimal> = dates
+        .iter()
+        .map(|&date| {
+            let months_ahead = (date - today).num_days() as f64 / DAYS_PER_MONTH;
+            Decimal::from_f64_retain(spread * months_ahead.sqrt())
+                .unwrap_or_default()
+                .round_dp(2)
+        })
+        .collect();
+    let lower: Vec<Decimal> = expected.iter().zip(&band).map(|(e, b)| e - b).collect();
+    let upper: Vec<Decimal> = expected.iter().zip(&band).map(|(e, b)| e + b).collect();
+    let expected_net_worth: Vec<Decimal> = expected
+        .iter()
+        .map(|e| current_net_worth + e - cash)
+        .collect();
+
+    let overdrafts = dates
+        .iter()
+        .zip(expected.iter().zip(&lower))
+        .filter(|(_, (_, lower))| lower.is_sign_negative() && !lower.is_zero())
+        .map(|(&date, (&expected, &lower))| Overdraft {
+            date,
+            expected,
+            lower,
+            likely: expected.is_sign_negative() && !expected.is_zero(),
+        })
+        .collect();
+
+    Forecast {
+        data: AlignedData {
+            x_values: net_worth::timestamps(&dates),
+            y_values: vec![
+                net_worth::numbers(&expected),
+                net_worth::numbers(&lower),
+                net_worth::numbers(&upper),
+                net_worth::numbers(&expected_net_worth),
+            ],
+        },
+        overdrafts,
+    }
+}
+
+/// Planned cash flow up to every date since the one before. Monthly budgets are prorated in
+/// the current month and payments already overdue fall into it.
+fn planned_flows(
+    dates: &[NaiveDate],
+    budgets: &[Budget],
+    subscriptions: &[Subscription],
+    convert: impl Fn(Decimal, &str) -> Option<Decimal>,
+) -> Vec<Decimal> {
+    let mut flows = vec![Decimal::ZERO; dates.len()];
+    let monthly: Decimal = budgets
+        .iter()
+        .filter(|b| !is_asset_or_liability(&b.account))
+        .filter_map(|b| convert(-b.monthly_amount(), &b.commodity))
+        .sum();
+    for i in 1..dates.len() {
+        let days = (dates[i] - dates[i - 1]).num_days();
+        let days_in_month = dates[i].day() as i64;
+        flows[i] += (monthly * Decimal::from(days) / Decimal::from(days_in_month)).round_dp(2);
+    }
+
+    let last = match dates.last() {
+        Some(&last) => last,
+        None => return flows,
+    };
+    for subscription in subscriptions.iter().filter(|s| s.active) {
+        let amount = match convert(subscription.last_amount, &subscription.commodity) {
+            Some(amount) => amount,
+            None => continue,
+        };
+        let mut date = subscription.next_expected;
+        while date <= last {
+            let i = dates.iter().position(|&d| d >= date).unwrap_or(0).max(1);
+            flows[i] -= amount;
+            date = subscription.cadence.next(date);
+        }
+    }
+    flows
+}
+
+fn standard_deviation(values: &[f64]) -> f64 {
+    if values.len() < 2 {
+        return 0.0;
+    }
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64;
+    variance.sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+
+    use super::{forecast, ForecastOptions};
+    use crate::{
+        model::budget::{Budget, BudgetPeriod},
+        prices::{Price, PriceTable},
+        subscriptions::{detect_subscriptions, Payment},
+        test_statics::transaction,
+    };
+
+    fn budget(account: &str, amount: i64) -> Budget {
+        Budget {
+            id: None,
+            account: account.to_string(),
+            amount: Decimal::from(amount),
+            commodity: "EUR".to_string(),
+            period: BudgetPeriod::Monthly,
+        }
+    }
+
+    #[test]
+    fn cash_and_net_worth() {
+        let today = NaiveDate::from_ymd(2021, 6, 15);
+        let mut transactions = vec![
+            transaction(
+                NaiveDate::from_ymd(2020, 6, 1),
+                &[("assets:cash", "EUR", 1000), ("equity", "EUR", -1000)],
+            ),
+            transaction(
+                NaiveDate::from_ymd(2021, 1, 1),
+                &[("assets:ib", "EMIM", 10), ("equity", "EUR", -300)],
+            ),
+        ];
+        // Alternating income for the band
+        for month in 0..12 {
+            let amount = if month % 2 == 0 { 100 } else { 300 };
+            let date =
+                NaiveDate::from_ymd(2020 + ((month + 6) / 12) as i32, (month + 6) % 12 + 1, 1);
+            transactions.push(transaction(
+                date,
+                &[("assets:cash", "EUR", amount), ("income", "EUR", -amount)],
+            ));
+        }
+        let prices = PriceTable::new(vec![Price {
+            date: NaiveDate::from_ymd(2021, 1, 1),
+            from_commodity: "EMIM".to_string(),
+            to_commodity: "EUR".to_string(),
+            amount: Decimal::from(30),
+        }]);
+        let budgets = vec![
+            budget("expenses:rent", 2000),
+            budget("income:salary", -500),
+            // The balancing posting isn't a flow
+            budget("assets:cash", 1500),
+        ];
+        let payments = (3..=5)
+            .map(|month| Payment {
+                payee: "Netflix".to_string(),
+                date: NaiveDate::from_ymd(2021, month, 20),
+                amount: Decimal::from(50),
+                commodity: "EUR".to_string(),
+            })
+            .collect();
+        let subscriptions = detect_subscriptions(payments, today);
+
+        let forecast = forecast(
+            &transactions,
+            &budgets,
+            &subscriptions,
+            &prices,
+            "EUR",
+            |c| c == "EUR",
+            &ForecastOptions { today, months: 3 },
+        );
+        let json = serde_json::to_value(&forecast.data).unwrap();
+        let series = |i: usize| -> Vec<f64> {
+            json[i]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|v| v.as_f64().unwrap())
+                .collect()
+        };
+        // Today, then the end of June to September
+        assert_eq!(series(0).len(), 5);
+        // Half a month of budgets and a payment on the 20th, then a full month and a payment
+        assert_eq!(series(1), vec![3400.0, 2600.0, 1050.0, -500.0, -2050.0]);
+        assert_eq!(series(4), vec![3700.0, 2900.0, 1350.0, -200.0, -1750.0]);
+
+        let (lower, upper) = (series(2), series(3));
+        assert_eq!(lower[0], 3400.0);
+        assert!(lower[1] < 2600.0 && upper[1] > 2600.0);
+        assert!(upper[4] - lower[4] > upper[1] - lower[1]);
+
+        let overdrafts: Vec<(NaiveDate, bool)> = forecast
+            .overdrafts
+            .iter()
+            .map(|o| (o.date, o.likely))
+            .collect();
+        assert_eq!(
+            overdrafts,
+            vec![
+                (NaiveDate::from_ymd(2021, 8, 31), true),
+                (NaiveDate::from_ymd(2021, 9, 30), true)
+            ]
+        );
+    }
+}
@@ -12,17 +12,20 @@ use std::{
     time,
 };
 
-use chrono::{Datelike, NaiveDate};
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
 use csv::ReaderBuilder;
 use futures::future::{BoxFuture, FutureExt};
 use log::{error, info, warn};
 use rust_decimal::{prelude::ToPrimitive, Decimal};
+use serde::Deserialize;
 
 use crate::{
     file_utils::{get_default_ledger_file, get_ledger_year_files},
     model::{
-        aligned_data::AlignedData, hledger_transaction::HledgerTransaction,
-        income_statement::IncomeStatementResponse,
+        account_tree::AccountTree,
+        aligned_data::AlignedData,
+        hledger_transaction::HledgerTransaction,
+        income_statement::{IncomeStatementResponse, NetWorthResponse},
     },
 };
 
@@ -36,6 +39,87 @@ const NET_CSV_HEADING: &str = "Net:";
 const ACCOUNT_CSV_HEADING: &str = "Account";
 const MAX_TOP_TRANSACTIONS: usize = 5;
 
+/// Length of the periods in a report
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Interval {
+    Weekly,
+    Monthly,
+    Quarterly,
+    Yearly,
+}
+
+impl Default for Interval {
+    fn default() -> Self {
+        Interval::Monthly
+    }
+}
+
+impl Interval {
+    fn arg(&self) -> &'static str {
+        match self {
+            Interval::Weekly => "--weekly",
+            Interval::Monthly => "--monthly",
+            Interval::Quarterly => "--quarterly",
+            Interval::Yearly => "--yearly",
+        }
+    }
+}
+
+/// What the income statement and the balance sheet cover
+#[derive(Debug)]
+pub struct ReportOptions<'a> {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    /// Either a tag name or `name=value`
+    pub tag: Option<&'a str>,
+    /// How many account levels to show
+    pub depth: u32,
+    /// Only include this account and its subaccounts
+    pub account: Option<&'a str>,
+    pub interval: Interval,
+    /// Amounts are converted to this commodity
+    pub currency: &'a str,
+}
+
+/// hledger matches tags by unanchored regexes, so the name and value are anchored and escaped
+/// to select the same transactions as `HledgerTransaction::has_tag`
+fn tag_query(tag: &str) -> String {
+    match tag.split_once('=') {
+        Some((name, value)) => format!("tag:^{}$=^{}$", regex::escape(name), regex::escape(value)),
+        None => format!("tag:^{}$", regex::escape(tag)),
+    }
+}
+
+impl ReportOptions<'_> {
+    fn args(&self) -> Vec<String> {
+        let mut args: Vec<String> = vec![
+            "-X".to_string(),
+            self.currency.to_string(),
+            self.interval.arg().to_string(),
+            "--flat".to_string(),
+            "--depth".to_string(),
+            self.depth.to_string(),
+        ];
+        if let Some(tag) = self.tag {
+            args.push(tag_query(tag));
+        }
+        if let Some(account) = self.account {
+            // Not sibling accounts with the same prefix
+            args.push(format!("^{}(:|$)", account));
+        }
+        if let Some(from) = self.from {
+            args.push("-b".to_string());
+            args.push(from.format(DATE_FMT).to_string());
+        }
+        if let Some(to) = self.to {
+            args.push("-e".to_string());
+            args.push(to.format(DATE_FMT).to_string());
+        }
+        args
+    }
+}
+
 pub struct HledgerProcess {
     journal_file: PathBuf,
     process: Mutex<Option<Child>>,
@@ -329,64 +413,57 @@ impl Hledger {
         get_total_from_csv(stdout)
     }
 
+    /// The top transactions are filtered by the options' tag and account, like the report
     pub async fn get_income_statement(
         &self,
-        from: Option<NaiveDate>,
-        to: Option<NaiveDate>,
+        options: &ReportOptions<'_>,
     ) -> IncomeStatementResponse {
-        let command = "is";
-        let mut args = vec!["--monthly", "--depth", "1"];
-        let s;
-        if let Some(from) = from {
-            args.push("-b");
-            s = from.format(DATE_FMT).to_string();
-            args.push(&s);
-        }
-        let s;
-        if let Some(to) = to {
-            args.push("-e");
-            s = to.format(DATE_FMT).to_string();
-            args.push(&s);
-        }
-        let stdout = self.hledger_csv_command(command, &args).await;
+        let args = options.args();
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let stdout = self.hledger_csv_command("is", &args).await;
 
         let is = get_report_from_csv(stdout);
 
-        let all = self.fetch_all_transactions().await;
-        let top_expenses = get_top_transactions("Expenses", &all, &is.dates);
-        let top_revenues = get_top_transactions("Income", &all, &is.dates);
+        let mut all = self.fetch_all_transactions().await;
+        if let Some(tag) = options.tag {
+            let (name, value) = match tag.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (tag, None),
+            };
+            all.retain(|t| t.has_tag(name, value));
+        }
+        if let Some(account) = options.account {
+            all.retain(|t| {
+                t.tpostings
+                    .iter()
+                    .any(|p| is_account_or_subaccount(&p.paccount, account))
+            });
+        }
+        let top_expenses = get_top_transactions("Expenses", &all, is.start_date, &is.dates);
+        let top_revenues = get_top_transactions("Income", &all, is.start_date, &is.dates);
+        let accounts = AccountTree::from_rows(&is.accounts);
 
         IncomeStatementResponse {
             data: is.into(),
             top_revenues,
             top_expenses,
+            accounts,
         }
     }
 
-    pub async fn get_net_worth(
-        &self,
-        from: Option<NaiveDate>,
-        to: Option<NaiveDate>,
-    ) -> AlignedData {
-        let command = "bs";
-        let mut args = vec!["-V", "--monthly", "--depth", "1"];
-        let s;
-        if let Some(from) = from {
-            args.push("-b");
-            s = from.format(DATE_FMT).to_string();
-            args.push(&s);
-        }
-        let s;
-        if let Some(to) = to {
-            args.push("-e");
-            s = to.format(DATE_FMT).to_string();
-            args.push(&s);
-        }
-        let stdout = self.hledger_csv_command(command, &args).await;
+    /// Balances valued in the options' currency at the end of every period
+    pub async fn get_net_worth(&self, options: &ReportOptions<'_>) -> NetWorthResponse {
+        let args = options.args();
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let stdout = self.hledger_csv_command("bs", &args).await;
 
-        let is = get_report_from_csv(stdout);
+        let bs = get_report_from_csv(stdout);
+        let accounts = AccountTree::from_rows(&bs.accounts);
 
-        is.into()
+        NetWorthResponse {
+            data: bs.into(),
+            accounts,
+        }
     }
 
     async fn hledger_csv_command(&self, command: &str, args: &[&str]) -> impl std::io::Read {
@@ -429,10 +506,14 @@ fn get_total_from_csv(reader: impl std::io::Read) -> HashMap<String, Decimal> {
 
 #[derive(Debug)]
 struct Report {
+    start_date: NaiveDate,
+    /// Last day of every period
     dates: Vec<NaiveDate>,
     section_a: Vec<Decimal>,
     section_b: Vec<Decimal>,
     net: Vec<Decimal>,
+    /// Amounts of the accounts in both sections, excluding subaccounts
+    accounts: Vec<(String, Vec<Decimal>)>,
 }
 
 impl From<Report> for AlignedData {
@@ -469,15 +550,33 @@ fn get_report_from_csv(reader: impl std::io::Read) -> Report {
     let mut section_a: Vec<Decimal> = vec![];
     let mut section_b: Vec<Decimal> = vec![];
     let mut net: Vec<Decimal> = vec![];
+    let mut accounts: Vec<(String, Vec<Decimal>)> = vec![];
+    // Columns with a known period, the others are left out
+    let mut columns: Vec<usize> = vec![];
 
-    fn record_prices(record: csv::StringRecord) -> Vec<Decimal> {
-        record
+    fn record_prices(record: csv::StringRecord, columns: &[usize]) -> Vec<Decimal> {
+        columns
             .iter()
-            .skip(1)
-            .map(|amount| parse_commodity_amount(amount).unwrap().1)
+            .map(|&i| {
+                parse_commodity_amount(record.get(i).unwrap_or("0"))
+                    .unwrap()
+                    .1
+            })
             .collect()
     }
 
+    // Section headings have no amounts
+    fn account_row(
+        title: &str,
+        record: &csv::StringRecord,
+        columns: &[usize],
+    ) -> Option<(String, Vec<Decimal>)> {
+        if record.iter().skip(1).all(str::is_empty) {
+            return None;
+        }
+        Some((title.to_string(), record_prices(record.clone(), columns)))
+    }
+
     let mut reader = csv::ReaderBuilder::new()
         .has_headers(false)
         .flexible(true)
@@ -499,10 +598,14 @@ fn get_report_from_csv(reader: impl std::io::Read) -> Report {
             ParseState::Months => {
                 if let Some(title) = record.get(0) {
                     if title == ACCOUNT_CSV_HEADING {
-                        let mut date = last_day_of_month(start_date);
-                        for _ in record.iter().skip(1) {
-                            dates.push(date);
-                            date = last_day_of_next_month(date);
+                        for (i, heading) in record.iter().enumerate().skip(1) {
+                            match parse_period_end(heading, start_date, dates.last()) {
+                                Some(date) => {
+                                    dates.push(date);
+                                    columns.push(i);
+                                }
+                                None => warn!("Skipping unknown report period {}", heading),
+                            }
                         }
                         parse_state = ParseState::SectionA;
                     }
@@ -511,25 +614,29 @@ fn get_report_from_csv(reader: impl std::io::Read) -> Report {
             ParseState::SectionA => {
                 if let Some(title) = record.get(0) {
                     if title == TOTAL_CSV_HEADING {
-                        section_a = record_prices(record);
+                        section_a = record_prices(record, &columns);
                         assert_eq!(dates.len(), section_a.len());
                         parse_state = ParseState::SectionB;
+                    } else if let Some(row) = account_row(title, &record, &columns) {
+                        accounts.push(row);
                     }
                 }
             }
             ParseState::SectionB => {
                 if let Some(title) = record.get(0) {
                     if title == TOTAL_CSV_HEADING {
-                        section_b = record_prices(record);
+                        section_b = record_prices(record, &columns);
                         assert_eq!(dates.len(), section_b.len());
                         parse_state = ParseState::Net;
+                    } else if let Some(row) = account_row(title, &record, &columns) {
+                        accounts.push(row);
                     }
                 }
             }
             ParseState::Net => {
                 if let Some(title) = record.get(0) {
                     if title == NET_CSV_HEADING {
-                        net = record_prices(record);
+                        net = record_prices(record, &columns);
                         assert_eq!(dates.len(), net.len());
                     }
                 }
@@ -538,11 +645,56 @@ fn get_report_from_csv(reader: impl std::io::Read) -> Report {
         }
     }
     Report {
+        start_date,
         dates,
         section_a,
         section_b,
         net,
+        accounts,
+    }
+}
+
+/// Last day of the period in a report column heading, which is either the end date (balance
+/// sheets), `2021-01-04..2021-01-10`, a week (`2021-W01` or `2021-01-04W01`), `2021Q1`,
+/// `2021-01`, `2021` or, in older hledger versions, only the month name, which is counted on
+/// from the previous column.
+fn parse_period_end(
+    heading: &str,
+    start_date: NaiveDate,
+    previous: Option<&NaiveDate>,
+) -> Option<NaiveDate> {
+    let heading = heading.rsplit("..").next()?;
+    if let Ok(date) = NaiveDate::parse_from_str(heading, DATE_FMT) {
+        return Some(date);
+    }
+    if let Some((start, week)) = heading.split_once('W') {
+        let start = start.trim_end_matches('-');
+        if let Ok(date) = NaiveDate::parse_from_str(start, DATE_FMT) {
+            return Some(date + Duration::days(6));
+        }
+        return NaiveDate::from_isoywd_opt(start.parse().ok()?, week.parse().ok()?, Weekday::Sun);
+    }
+    if let Some((year, quarter)) = heading.split_once('Q') {
+        let quarter: u32 = quarter.parse().ok()?;
+        return Some(last_day_of_month(NaiveDate::from_ymd_opt(
+            year.parse().ok()?,
+            quarter * 3,
+            1,
+        )?));
+    }
+    if let Ok(month) = NaiveDate::parse_from_str(&format!("{}-01", heading), DATE_FMT) {
+        return Some(last_day_of_month(month));
+    }
+    if let Ok(year) = heading.parse() {
+        return NaiveDate::from_ymd_opt(year, 12, 31);
     }
+    if heading.len() == 3 && heading.chars().all(char::is_alphabetic) {
+        return Some(match previous {
+            Some(&previous) => last_day_of_next_month(previous),
+            None => last_day_of_month(start_date),
+        });
+    }
+    None
 }
 
 fn last_day_of_month(date: NaiveDate) -> NaiveDate {
@@ -579,9 +731,16 @@ fn parse_commodity_amount(amount: &str) -> Option<(Option<String>, Decimal)> {
     Some((commodity, quantity))
 }
 
+/// Like the `^account(:|$)` query, case insensitively
+fn is_account_or_subaccount(account: &str, parent: &str) -> bool {
+    let (account, parent) = (account.to_lowercase(), parent.to_lowercase());
+    matches!(account.strip_prefix(&parent), Some(rest) if rest.is_empty() || rest.starts_with(':'))
+}
+
 fn get_top_transactions(
     account: &str,
     transactions: &[HledgerTransaction],
+    start_date: NaiveDate,
     dates: &[NaiveDate],
 ) -> Vec<Vec<HledgerTransaction>> {
     let mut top_transactions: Vec<Vec<HledgerTransaction>> = vec![vec![]; dates.len()];
@@ -590,14 +749,12 @@ fn get_top_transactions(
             continue;
         }
         let date = t.get_date(Some(account));
-        if date < dates[0] {
+        if date < start_date {
             continue;
         }
-        let month = dates
-            .iter()
-            .position(|d| d.year() == date.year() && d.month() == date.month())
-            .unwrap();
-        top_transactions[month].push(t.clone());
+        if let Some(period) = dates.iter().position(|&d| date <= d) {
+            top_transactions[period].push(t.clone());
+        }
     }
 
     for top in &mut top_transactions {
@@ -613,10 +770,32 @@ mod tests {
     use rust_decimal::{prelude::FromPrimitive, Decimal};
 
     use super::{
-        get_report_from_csv, get_total_from_csv, last_day_of_next_month, parse_commodity_amount,
+        get_report_from_csv, get_total_from_csv, is_account_or_subaccount, last_day_of_next_month,
+        parse_commodity_amount, parse_period_end, tag_query,
     };
     use crate::hledger::parse_multi_commodity_amount;
 
+    #[test]
+    fn account_filter() {
+        assert!(is_account_or_subaccount("Expenses:Food", "Expenses:Food"));
+        assert!(is_account_or_subaccount(
+            "Expenses:Food:Groceries",
+            "expenses:food"
+        ));
+        assert!(!is_account_or_subaccount(
+            "Expenses:Foodstuff",
+            "Expenses:Food"
+        ));
+        assert!(!is_account_or_subaccount("Expenses", "Expenses:Food"));
+    }
+
+    #[test]
+    fn exact_tag_query() {
+        assert_eq!(tag_query("trip"), "tag:^trip$");
+        assert_eq!(tag_query("trip=Rome 2021"), "tag:^trip$=^Rome 2021$");
+        assert_eq!(tag_query("project=a.b"), "tag:^project$=^a\\.b$");
+    }
+
     #[test]
     fn currency_convert_simple() {
         let (commodity, quantity) = parse_commodity_amount("100 EUR").unwrap();
@@ -692,6 +871,58 @@ mod tests {
         );
     }
 
+    #[test]
+    fn period_headings() {
+        let start = NaiveDate::from_ymd(2020, 12, 28);
+        let end = |heading| parse_period_end(heading, start, None).unwrap();
+        // weekly
+        assert_eq!(end("2020-12-28W53"), NaiveDate::from_ymd(2021, 1, 3));
+        assert_eq!(end("2021-W01"), NaiveDate::from_ymd(2021, 1, 10));
+        assert_eq!(
+            end("2021-01-04..2021-01-10"),
+            NaiveDate::from_ymd(2021, 1, 10)
+        );
+        // monthly
+        assert_eq!(end("2021-02"), NaiveDate::from_ymd(2021, 2, 28));
+        assert_eq!(end("Dec"), NaiveDate::from_ymd(2020, 12, 31));
+        assert_eq!(
+            parse_period_end("Jan", start, Some(&NaiveDate::from_ymd(2020, 12, 31))),
+            Some(NaiveDate::from_ymd(2021, 1, 31))
+        );
+        // quarterly
+        assert_eq!(end("2021Q1"), NaiveDate::from_ymd(2021, 3, 31));
+        assert_eq!(end("2021Q4"), NaiveDate::from_ymd(2021, 12, 31));
+        // yearly
+        assert_eq!(end("2021"), NaiveDate::from_ymd(2021, 12, 31));
+        // balance sheets show the end date
+        assert_eq!(end("2021-06-30"), NaiveDate::from_ymd(2021, 6, 30));
+        assert_eq!(parse_period_end("Total", start, None), None);
+    }
+
+    #[test]
+    fn quarterly_income_statement() {
+        let data = r#"
+"Income Statement 2021-01-01..2021-06-30","",""
+"Account","2021Q1","2021Q2"
+"Revenues","",""
+"Income","3,000.00 EUR","2,500.00 EUR"
+"total","3,000.00 EUR","2,500.00 EUR"
+"Expenses","",""
+"Expenses","1,000.00 EUR","1,200.00 EUR"
+"total","1,000.00 EUR","1,200.00 EUR"
+"Net:","2,000.00 EUR","1,300.00 EUR"
+"#;
+        let is = get_report_from_csv(data.as_bytes());
+        assert_eq!(
+            is.dates,
+            vec![
+                NaiveDate::from_ymd(2021, 3, 31),
+                NaiveDate::from_ymd(2021, 6, 30)
+            ]
+        );
+        assert_eq!(is.net[1], Decimal::from_f64(1300.).unwrap());
+    }
+
     #[test]
     fn income_statement() {
         let data = r#"
@@ -721,6 +952,9 @@ mod tests {
             is.section_b.last().unwrap(),
             &Decimal::from_f64(2688.94).unwrap()
         );
+        let accounts: Vec<&str> = is.accounts.iter().map(|(a, _)| a.as_str()).collect();
+        assert_eq!(accounts, vec!["Income", "Expenses"]);
+        assert_eq!(is.accounts[1].1, is.section_b);
     }
 
     #[test]
@@ -753,4 +987,29 @@ mod tests {
             &Decimal::from_f64(64334.85).unwrap()
         );
     }
+
+    #[test]
+    fn unknown_period_heading() {
+        let data = r#"
+"Balance Sheet 2021-01-31..2021-02-28, valued at period ends","","",""
+"Account","2021-01-31","Average","2021-02-28"
+"Assets","","",""
+"Assets","100.00 EUR","150.00 EUR","200.00 EUR"
+"total","100.00 EUR","150.00 EUR","200.00 EUR"
+"Liabilities","","",""
+"total","0","0","0"
+"Net:","100.00 EUR","150.00 EUR","200.00 EUR"
+"#;
+        let bs = get_report_from_csv(data.as_bytes());
+        assert_eq!(
+            bs.dates,
+            vec![
+                NaiveDate::from_ymd(2021, 1, 31),
+                NaiveDate::from_ymd(2021, 2, 28)
+            ]
+        );
+        assert_eq!(bs.section_a, vec![Decimal::from(100), Decimal::from(200)]);
+        assert_eq!(bs.accounts[0].1, bs.section_a);
+        assert_eq!(bs.net[1], Decimal::from(200));
+    }
 }
@@ -10,7 +10,8 @@ use saltedge::SaltEdge;
 use serde_json::json;
 
 use crate::{
-    alpha_vantage, api, auth::validator, config, db, hledger, ib::Ib, n26, prices, saltedge,
+    api, auth::validator, config, db, hledger, ib::Ib, n26, price_sources::PriceSources, prices,
+    saltedge,
 };
 
 pub async fn run_server() -> io::Result<()> {
@@ -19,8 +20,17 @@ pub async fn run_server() -> io::Result<()> {
     let saltedge = Arc::new(SaltEdge);
     let ib = Arc::new(Ib);
     let hledger = Arc::new(hledger::Hledger::new());
-    let alpha_vantage = Arc::new(alpha_vantage::AlphaVantage::new());
-    let prices = Arc::new(prices::Prices::new(alpha_vantage.clone()));
+    let price_sources = Arc::new(PriceSources::from_config());
+    let prices = Arc::new(prices::Prices::new(price_sources));
+    if let Some(hours) = config::price_update_interval_hours() {
+        api::prices::schedule_price_updates(
+            hours,
+            prices.clone(),
+            hledger.clone(),
+            db.clone(),
+            ib.clone(),
+        );
+    }
 
     HttpServer::new(move || {
         App::new()
@@ -48,6 +58,7 @@ pub async fn run_server() -> io::Result<()> {
             .service(api::transactions::routes::transactions_routes())
             .service(api::accounts::accounts_routes())
             .service(api::balance::balance_routes())
+            .service(api::budgets::budgets_routes())
             .service(api::reports::reports_routes())
             .service(api::prices::prices_routes())
             .service(api::journal::journal_routes())
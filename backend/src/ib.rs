@@ -1,9 +1,9 @@
-use std::time::Duration;
+use std::{fs, path::Path, time::Duration};
 
 use actix::clock::sleep;
 use async_trait::async_trait;
 use chrono::NaiveDate;
-use log::info;
+use log::{info, warn};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use serde_xml_rs::from_str;
@@ -18,7 +18,7 @@ const DATETIME_FMT: &str = "%Y%m%d;%H%M%S";
 const DATE_FMT: &str = "%Y%m%d";
 const MAX_RETRIES: u32 = 10;
 const FIRST_RETRY_DELAY: u64 = 10;
-const BASE_CURRENCY: &str = "EUR";
+const WITHHOLDING_TAX_TYPE: &str = "Withholding Tax";
 
 pub struct Ib;
 
@@ -56,10 +56,17 @@ struct FlexStatements {
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 struct FlexStatement {
+    /// Required so other XML documents aren't taken for an empty statement
+    #[serde(rename = "accountId")]
+    account_id: String,
     trades: Option<Trades>,
     cash_transactions: Option<CashTransactions>,
     open_positions: Option<OpenPositions>,
     fx_positions: Option<FxPositions>,
+    corporate_actions: Option<CorporateActions>,
+    change_in_dividend_accruals: Option<ChangeInDividendAccruals>,
+    transfers: Option<Transfers>,
+    interest_accruals: Option<InterestAccruals>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -74,6 +81,30 @@ struct CashTransactions {
     items: Vec<CashTransaction>,
 }
 
+#[derive(Debug, Deserialize)]
+struct CorporateActions {
+    #[serde(rename = "CorporateAction", default)]
+    items: Vec<CorporateAction>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChangeInDividendAccruals {
+    #[serde(rename = "ChangeInDividendAccrual", default)]
+    items: Vec<DividendAccrual>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Transfers {
+    #[serde(rename = "Transfer", default)]
+    items: Vec<Transfer>,
+}
+
+#[derive(Debug, Deserialize)]
+struct InterestAccruals {
+    #[serde(rename = "InterestAccrualsCurrency", default)]
+    items: Vec<InterestAccrual>,
+}
+
 #[derive(Debug, Deserialize)]
 struct OpenPositions {
     #[serde(rename = "OpenPosition", default)]
@@ -95,10 +126,47 @@ pub struct Trade {
     #[serde(rename = "transactionID")]
     transaction_id: String,
     date_time: String,
-    quantity: i32,
+    /// Shares, which may be fractional, or contracts for options and futures
+    quantity: Decimal,
+    /// Price per share, so per contract it's multiplied by the multiplier
     trade_price: Decimal,
     trade_money: Decimal,
     ib_commission: Decimal,
+    /// E.g. `STK`, `OPT`, `FUT` or `FOP`
+    #[serde(default)]
+    asset_category: String,
+    #[serde(default = "default_multiplier")]
+    multiplier: Decimal,
+    #[serde(default)]
+    underlying_symbol: String,
+    #[serde(default)]
+    expiry: String,
+    #[serde(default)]
+    strike: Option<Decimal>,
+    /// `C` or `P`
+    #[serde(default)]
+    put_call: String,
+}
+
+fn default_multiplier() -> Decimal {
+    Decimal::ONE
+}
+
+impl Trade {
+    /// IB option symbols like `AAPL  210618C00130000` contain spaces, so options get a symbol
+    /// of underlying, expiry, right and strike instead, e.g. `AAPL210618C130`
+    fn hledger_commodity(&self) -> String {
+        match (self.asset_category.as_str(), self.strike) {
+            ("OPT" | "FOP", Some(strike)) if !self.underlying_symbol.is_empty() => format!(
+                "{}{}{}{}",
+                self.underlying_symbol,
+                self.expiry.get(2..).unwrap_or_default(),
+                self.put_call,
+                strike.normalize()
+            ),
+            _ => self.symbol.split_whitespace().collect(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -110,6 +178,86 @@ pub struct CashTransaction {
     transaction_id: String,
     date_time: String,
     amount: Decimal,
+    /// E.g. `Dividends`, `Withholding Tax`, `Broker Interest Received` or `Deposits/Withdrawals`
+    #[serde(rename = "cashType", alias = "type", default)]
+    cash_type: String,
+    #[serde(default)]
+    symbol: String,
+}
+
+/// Splits, mergers, spin-offs and the like, which change the quantity of a holding
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CorporateAction {
+    currency: String,
+    symbol: String,
+    description: String,
+    #[serde(rename = "transactionID")]
+    transaction_id: String,
+    date_time: String,
+    /// E.g. `FS` for a forward split or `SO` for a spin-off
+    #[serde(rename = "actionType", alias = "type", default)]
+    action_type: String,
+    quantity: Decimal,
+    /// Cash paid out by the action
+    #[serde(default)]
+    proceeds: Decimal,
+    #[serde(default)]
+    value: Decimal,
+}
+
+/// A dividend which has been declared but not paid yet (code `Po`) or reversed once it's
+/// paid (code `Re`)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DividendAccrual {
+    currency: String,
+    symbol: String,
+    report_date: String,
+    ex_date: String,
+    pay_date: String,
+    quantity: Decimal,
+    gross_rate: Decimal,
+    gross_amount: Decimal,
+    #[serde(default)]
+    tax: Decimal,
+    net_amount: Decimal,
+    code: String,
+}
+
+/// Positions or cash moved in or out of the account from another broker
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Transfer {
+    currency: String,
+    symbol: String,
+    description: String,
+    #[serde(rename = "transactionID")]
+    transaction_id: String,
+    date_time: String,
+    /// E.g. `INTERNAL` or `ACATS`
+    #[serde(rename = "transferType", alias = "type", default)]
+    transfer_type: String,
+    /// `IN` or `OUT`
+    direction: String,
+    quantity: Decimal,
+    #[serde(default)]
+    position_amount: Decimal,
+    #[serde(default)]
+    cash_transfer: Decimal,
+}
+
+/// Interest accrued on the cash in one currency over the statement period
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct InterestAccrual {
+    currency: String,
+    from_date: String,
+    to_date: String,
+    starting_accrual_balance: Decimal,
+    interest_accrued: Decimal,
+    accrual_reversal: Decimal,
+    ending_accrual_balance: Decimal,
 }
 
 #[derive(Debug, Deserialize)]
@@ -120,7 +268,15 @@ struct OpenPosition {
     description: String,
     position: Decimal,
     mark_price: Decimal,
+    /// In the currency of the position
     position_value: Decimal,
+    /// To the base currency of the IB account
+    #[serde(default = "default_fx_rate")]
+    fx_rate_to_base: Decimal,
+}
+
+fn default_fx_rate() -> Decimal {
+    Decimal::ONE
 }
 
 #[derive(Debug, Deserialize)]
@@ -141,51 +297,120 @@ struct FxPosition {
 pub enum IbTransaction {
     Cash(CashTransaction),
     Trade(Trade),
+    /// Cash transactions of the `Withholding Tax` type
+    WithholdingTax(CashTransaction),
+    CorporateAction(CorporateAction),
+    DividendAccrual(DividendAccrual),
+    Transfer(Transfer),
+    InterestAccrual(InterestAccrual),
 }
 
 impl RealTransaction for IbTransaction {
     fn get_id(&self) -> std::borrow::Cow<str> {
         match self {
-            IbTransaction::Cash(c) => c.transaction_id.as_str().into(),
+            IbTransaction::Cash(c) | IbTransaction::WithholdingTax(c) => {
+                c.transaction_id.as_str().into()
+            }
             IbTransaction::Trade(t) => t.transaction_id.as_str().into(),
+            IbTransaction::CorporateAction(a) => a.transaction_id.as_str().into(),
+            IbTransaction::Transfer(t) => t.transaction_id.as_str().into(),
+            // Accruals have no id of their own
+            IbTransaction::DividendAccrual(d) => {
+                format!("accrual:{}:{}:{}", d.symbol, d.ex_date, d.code).into()
+            }
+            IbTransaction::InterestAccrual(i) => {
+                format!("interest:{}:{}", i.currency, i.to_date).into()
+            }
         }
     }
 
     fn get_date(&self) -> chrono::NaiveDate {
         match self {
-            IbTransaction::Cash(c) => ib_date(&c.date_time),
+            IbTransaction::Cash(c) | IbTransaction::WithholdingTax(c) => ib_date(&c.date_time),
             IbTransaction::Trade(t) => ib_date(&t.date_time),
+            IbTransaction::CorporateAction(a) => ib_date(&a.date_time),
+            IbTransaction::DividendAccrual(d) => ib_date(&d.report_date),
+            IbTransaction::Transfer(t) => ib_date(&t.date_time),
+            IbTransaction::InterestAccrual(i) => ib_date(&i.to_date),
         }
     }
 
     fn get_default_amount_field_name(&self) -> &str {
         match self {
-            IbTransaction::Cash(_) => "amount",
+            IbTransaction::Cash(_) | IbTransaction::WithholdingTax(_) => "amount",
             IbTransaction::Trade(_) => "tradeMoney",
+            IbTransaction::CorporateAction(_) => "quantity",
+            IbTransaction::DividendAccrual(_) => "netAmount",
+            IbTransaction::Transfer(_) => "cashTransfer",
+            IbTransaction::InterestAccrual(_) => "interestAccrued",
         }
     }
 
     fn get_default_currency_field_name(&self) -> &str {
-        "currency"
+        match self {
+            IbTransaction::CorporateAction(_) => "symbol",
+            _ => "currency",
+        }
+    }
+
+    /// Trades get a `commodity` usable in hledger and the `contractPrice`, which is the
+    /// `tradePrice` times the multiplier. They're derived here instead of being stored, so
+    /// cached trades have them too.
+    fn to_json_value(&self) -> serde_json::Value {
+        let mut value = serde_json::to_value(self).unwrap();
+        value["id"] = serde_json::to_value(self.get_id()).unwrap();
+        if let IbTransaction::Trade(t) = self {
+            value["commodity"] = t.hledger_commodity().into();
+            value["contractPrice"] = serde_json::to_value(t.trade_price * t.multiplier).unwrap();
+        }
+        value
     }
 }
 
 pub async fn get_balances() -> Vec<RealBalance> {
-    let token = config::ib_flex_token();
-    let query_id = config::ib_flex_balance_query_id();
-    let balance = fetch_flex_statement(token, query_id).await;
+    let statement = match config::ib_flex_statements_dir() {
+        // The latest statement has the current positions
+        Some(dir) => read_statements_dir(Path::new(&dir)).pop(),
+        None => {
+            match (config::ib_flex_token(), config::ib_flex_balance_query_id()) {
+                (Some(token), Some(query_id)) => Some(fetch_flex_statement(token, query_id).await),
+                _ => {
+                    warn!("IB_FLEX_TOKEN and IB_FLEX_BALANCE_QUERY_ID need to be set to get IB balances");
+                    None
+                }
+            }
+        }
+    };
+    let base_currency = config::base_currency();
+    statement
+        .map(|s| get_statement_balances(s, &base_currency))
+        .unwrap_or_default()
+}
 
+fn get_statement_balances(balance: FlexStatement, base_currency: &str) -> Vec<RealBalance> {
+    // The base currency of the IB account may differ from ours
+    let same_base = balance
+        .fx_positions
+        .iter()
+        .flat_map(|x| &x.items)
+        .all(|fx| fx.functional_currency == base_currency);
     let positions = balance.open_positions.into_iter().flat_map(|x| {
         x.items.into_iter().map(|op| RealBalance {
+            base_amount: if op.currency == base_currency {
+                Some(op.position_value)
+            } else if same_base {
+                Some(op.position_value * op.fx_rate_to_base)
+            } else {
+                None
+            },
             commodity: op.symbol,
             amount: op.position,
-            base_amount: Some(op.position_value),
         })
     });
 
     let forex = balance.fx_positions.into_iter().flat_map(|x| {
         x.items.into_iter().flat_map(|fx| {
-            if fx.functional_currency == BASE_CURRENCY {
+            if fx.functional_currency == base_currency {
                 Some(RealBalance {
                     base_amount: if fx.functional_currency.as_str() == fx.fx_currency.as_str() {
                         None
@@ -233,9 +458,80 @@ async fn retried_request<'de, T: Deserialize<'de>>(url: &str) -> T {
 }
 
 async fn get_transactions() -> Vec<IbTransaction> {
-    let token = config::ib_flex_token();
-    let query_id = config::ib_flex_transactions_query_id();
-    let statement = fetch_flex_statement(token, query_id).await;
+    if let Some(dir) = config::ib_flex_statements_dir() {
+        return read_statements_dir(Path::new(&dir))
+            .into_iter()
+            .flat_map(get_statement_transactions)
+            .collect();
+    }
+    match (
+        config::ib_flex_token(),
+        config::ib_flex_transactions_query_id(),
+    ) {
+        (Some(token), Some(query_id)) => {
+            get_statement_transactions(fetch_flex_statement(token, query_id).await)
+        }
+        _ => {
+            warn!("IB_FLEX_TOKEN and IB_FLEX_TRANSACTIONS_QUERY_ID need to be set to get IB transactions");
+            vec![]
+        }
+    }
+}
+
+/// Transactions of a saved Flex statement, either the whole `FlexQueryResponse` as downloaded
+/// from IB or a single `FlexStatement`
+pub fn parse_flex_statement(xml: &str) -> Result<Vec<IbTransaction>, serde_xml_rs::Error> {
+    Ok(parse_statements(xml)?
+        .into_iter()
+        .flat_map(get_statement_transactions)
+        .collect())
+}
+
+fn parse_statements(xml: &str) -> Result<Vec<FlexStatement>, serde_xml_rs::Error> {
+    let response: FlexStatementGetResponse = from_str(xml)?;
+    match response.flex_statements {
+        Some(statements) => Ok(statements.items),
+        None => Ok(vec![from_str(xml)?]),
+    }
+}
+
+/// Statements of all XML files in the directory, ordered by file name. Files which can't be
+/// parsed are skipped.
+fn read_statements_dir(dir: &Path) -> Vec<FlexStatement> {
+    let mut paths: Vec<_> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| Some(e.ok()?.path()))
+            .filter(|p| p.extension().map_or(false, |e| e == "xml"))
+            .collect(),
+        Err(e) => {
+            warn!("Couldn't read {}: {}", dir.to_string_lossy(), e);
+            return vec![];
+        }
+    };
+    paths.sort();
+    paths
+        .iter()
+        .flat_map(|path| {
+            let statements = fs::read_to_string(path)
+                .map_err(|e| e.to_string())
+                .and_then(|xml| parse_statements(&xml).map_err(|e| e.to_string()));
+            let statements = statements.unwrap_or_else(|e| {
+                warn!("Couldn't parse {}: {}", path.to_string_lossy(), e);
+                vec![]
+            });
+            for statement in &statements {
+                info!(
+                    "Read Flex statement of {} from {}",
+                    statement.account_id,
+                    path.to_string_lossy()
+                );
+            }
+            statements
+        })
+        .collect()
+}
+
+fn get_statement_transactions(statement: FlexStatement) -> Vec<IbTransaction> {
     let trades = statement
         .trades
         .into_iter()
@@ -245,8 +541,42 @@ async fn get_transactions() -> Vec<IbTransaction> {
         .cash_transactions
         .into_iter()
         .flat_map(|t| t.items)
-        .map(IbTransaction::Cash);
-    trades.chain(cash).collect()
+        .map(|c| {
+            if c.cash_type == WITHHOLDING_TAX_TYPE {
+                IbTransaction::WithholdingTax(c)
+            } else {
+                IbTransaction::Cash(c)
+            }
+        });
+    let corporate_actions = statement
+        .corporate_actions
+        .into_iter()
+        .flat_map(|a| a.items)
+        .map(IbTransaction::CorporateAction);
+    let dividend_accruals = statement
+        .change_in_dividend_accruals
+        .into_iter()
+        .flat_map(|d| d.items)
+        .map(IbTransaction::DividendAccrual);
+    let transfers = statement
+        .transfers
+        .into_iter()
+        .flat_map(|t| t.items)
+        .map(IbTransaction::Transfer);
+    let interest_accruals = statement
+        .interest_accruals
+        .into_iter()
+        .flat_map(|i| i.items)
+        // Every currency is listed, even without any interest
+        .filter(|i| !i.interest_accrued.is_zero() || !i.accrual_reversal.is_zero())
+        .map(IbTransaction::InterestAccrual);
+    trades
+        .chain(cash)
+        .chain(corporate_actions)
+        .chain(dividend_accruals)
+        .chain(transfers)
+        .chain(interest_accruals)
+        .collect()
 }
 
 async fn fetch_flex_statement(token: String, query_id: String) -> FlexStatement {
@@ -301,6 +631,10 @@ impl ImportAccount for Ib {
     fn get_id(&self) -> &str {
         "ib"
     }
+
+    fn tracks_lots(&self) -> bool {
+        true
+    }
 }
 
 #[cfg(test)]
@@ -308,11 +642,17 @@ mod tests {
     use rust_decimal::{prelude::FromPrimitive, Decimal};
     use serde_xml_rs::from_reader;
 
-    use super::{get_transactions, IbTransaction, Trade};
+    use std::fs;
+
+    use super::{
+        get_statement_balances, get_statement_transactions, get_transactions, parse_flex_statement,
+        read_statements_dir, FlexStatement, IbTransaction, Trade,
+    };
     use crate::{
         ib::{get_balances, FlexStatementRequestResponse},
         model::{
             hledger_transaction::HledgerTransaction,
+            real_transaction::RealTransaction,
             rule::{RulePosting, RulePostingPrice},
         },
     };
@@ -329,6 +669,181 @@ mod tests {
         assert_eq!(response.reference_code, "1234567890");
     }
 
+    #[test]
+    fn deserialize_flex_statement_sections() {
+        let xml = r#"<FlexStatement accountId="U1234567" fromDate="20210401" toDate="20210430">
+<CashTransactions>
+<CashTransaction currency="USD" symbol="VT" description="VT CASH DIVIDEND" transactionID="101" dateTime="20210406;202000" amount="12.5" type="Dividends" />
+<CashTransaction currency="USD" symbol="VT" description="VT US TAX" transactionID="102" dateTime="20210406;202000" amount="-1.88" type="Withholding Tax" />
+</CashTransactions>
+<CorporateActions>
+<CorporateAction currency="USD" symbol="AAPL" description="AAPL SPLIT 4 FOR 1" transactionID="103" dateTime="20210412;202500" type="FS" quantity="30" proceeds="0" value="0" />
+</CorporateActions>
+<ChangeInDividendAccruals>
+<ChangeInDividendAccrual currency="USD" symbol="VT" reportDate="20210325" exDate="20210324" payDate="20210406" quantity="50" grossRate="0.25" grossAmount="12.5" tax="1.88" netAmount="10.62" code="Po" />
+</ChangeInDividendAccruals>
+<Transfers>
+<Transfer currency="EUR" symbol="EMIM" description="EMIM TRANSFER" transactionID="104" dateTime="20210415" type="ACATS" direction="IN" quantity="100" positionAmount="2900" cashTransfer="0" />
+</Transfers>
+<InterestAccruals>
+<InterestAccrualsCurrency currency="EUR" fromDate="20210401" toDate="20210430" startingAccrualBalance="0" interestAccrued="0" accrualReversal="0" endingAccrualBalance="0" />
+<InterestAccrualsCurrency currency="USD" fromDate="20210401" toDate="20210430" startingAccrualBalance="0.1" interestAccrued="0.35" accrualReversal="-0.1" endingAccrualBalance="0.35" />
+</InterestAccruals>
+</FlexStatement>
+"#;
+        let statement: FlexStatement = from_reader(xml.as_bytes()).unwrap();
+        let transactions = get_statement_transactions(statement);
+        let summary: Vec<(String, String, String)> = transactions
+            .iter()
+            .map(|t| {
+                (
+                    t.get_id().to_string(),
+                    t.get_default_amount_field_name().to_string(),
+                    t.get_date().to_string(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("101".into(), "amount".into(), "2021-04-06".into()),
+                ("102".into(), "amount".into(), "2021-04-06".into()),
+                ("103".into(), "quantity".into(), "2021-04-12".into()),
+                (
+                    "accrual:VT:20210324:Po".into(),
+                    "netAmount".into(),
+                    "2021-03-25".into()
+                ),
+                ("104".into(), "cashTransfer".into(), "2021-04-15".into()),
+                (
+                    "interest:USD:20210430".into(),
+                    "interestAccrued".into(),
+                    "2021-04-30".into()
+                ),
+            ]
+        );
+        assert!(matches!(transactions[0], IbTransaction::Cash(_)));
+        assert!(matches!(transactions[1], IbTransaction::WithholdingTax(_)));
+        assert_eq!(
+            transactions[1].get_field::<Decimal>("amount"),
+            Some(Decimal::new(-188, 2))
+        );
+    }
+
+    fn flex_query_response(from: &str, cash: &str, position: &str) -> String {
+        format!(
+            r#"<FlexQueryResponse queryName="Transactions" type="AF">
+<FlexStatements count="1">
+<FlexStatement accountId="U1234567" fromDate="{from}01" toDate="{from}28">
+<CashTransactions>
+<CashTransaction currency="EUR" description="DEPOSIT" transactionID="{from}" dateTime="{from}05" amount="{cash}" type="Deposits/Withdrawals" />
+</CashTransactions>
+<OpenPositions>
+<OpenPosition currency="EUR" symbol="EMIM" description="ISHARES CORE EM IMI ACC" position="{position}" markPrice="30" positionValue="3000" />
+</OpenPositions>
+</FlexStatement>
+</FlexStatements>
+</FlexQueryResponse>
+"#,
+            from = from,
+            cash = cash,
+            position = position
+        )
+    }
+
+    #[test]
+    fn saved_flex_statement() {
+        let transactions =
+            parse_flex_statement(&flex_query_response("202103", "500", "100")).unwrap();
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].get_id(), "202103");
+        assert_eq!(
+            transactions[0].get_field::<Decimal>("amount"),
+            Some(Decimal::new(500, 0))
+        );
+        assert!(parse_flex_statement("<html>Not a statement</html>").is_err());
+    }
+
+    #[test]
+    fn statements_dir() {
+        let dir = std::env::temp_dir().join(format!("statements_dir-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("2021-04.xml"),
+            flex_query_response("202104", "200", "110"),
+        )
+        .unwrap();
+        fs::write(
+            dir.join("2021-03.xml"),
+            flex_query_response("202103", "500", "100"),
+        )
+        .unwrap();
+        fs::write(dir.join("broken.xml"), "<FlexQueryResponse>").unwrap();
+        fs::write(dir.join("notes.txt"), "Not a statement").unwrap();
+
+        let mut statements = read_statements_dir(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(statements.len(), 2);
+
+        let balances = get_statement_balances(statements.pop().unwrap(), "EUR");
+        assert_eq!(balances[0].amount, Decimal::new(110, 0));
+        let ids: Vec<String> = statements
+            .into_iter()
+            .flat_map(get_statement_transactions)
+            .map(|t| t.get_id().to_string())
+            .collect();
+        assert_eq!(ids, vec!["202103"]);
+    }
+
+    #[test]
+    fn fractional_shares_and_options() {
+        let xml = r#"<FlexStatement accountId="U1234567" fromDate="20210401" toDate="20210430">
+<Trades>
+<Trade currency="USD" assetCategory="STK" symbol="VT" description="VANGUARD TOT WORLD STK ETF" transactionID="201" dateTime="20210401;100000" quantity="0.4567" tradePrice="100.5" tradeMoney="45.89835" ibCommission="-0.01" multiplier="1" />
+<Trade currency="USD" assetCategory="OPT" symbol="AAPL  210618C00130000" description="AAPL 18JUN21 130 C" transactionID="202" dateTime="20210402;100000" quantity="-2" tradePrice="3.25" tradeMoney="-650" ibCommission="-1.4" multiplier="100" underlyingSymbol="AAPL" expiry="20210618" strike="130" putCall="C" />
+</Trades>
+</FlexStatement>
+"#;
+        let statement: FlexStatement = from_reader(xml.as_bytes()).unwrap();
+        let transactions = get_statement_transactions(statement);
+        let investment = [RulePosting {
+            amount_field_name: Some("quantity".to_string()),
+            currency_field_name: Some("commodity".to_string()),
+            price: Some(RulePostingPrice {
+                amount_field_name: "contractPrice".to_string(),
+                currency_field_name: "currency".to_string(),
+            }),
+            account: "Assets:Investments".to_string(),
+            negate: false,
+            comment: None,
+            tags: vec![],
+        }];
+        let postings: Vec<_> = transactions
+            .iter()
+            .map(|t| t.get_postings("Assets:Cash:IB", &investment))
+            .collect();
+
+        let shares = &postings[0][1];
+        assert_eq!(shares.get_amount(), Some(Decimal::new(4567, 4)));
+        assert_eq!(shares.get_commodity(), Some("VT"));
+
+        let option = &postings[1][1];
+        assert_eq!(option.get_amount(), Some(Decimal::new(-2, 0)));
+        assert_eq!(option.get_commodity(), Some("AAPL210618C130"));
+        // The cost of the contracts matches the trade money
+        assert_eq!(option.get_unit_price(), Some((Decimal::new(325, 0), "USD")));
+        assert_eq!(postings[1][0].get_amount(), Some(Decimal::new(-650, 0)));
+
+        // Trades cached with empty contract fields get them too
+        let mut cached = serde_json::to_value(&transactions[1]).unwrap();
+        cached["commodity"] = "".into();
+        cached["contractPrice"] = "0".into();
+        let cached: IbTransaction = serde_json::from_value(cached).unwrap();
+        let option = &cached.get_postings("Assets:Cash:IB", &investment)[1];
+        assert_eq!(option.get_commodity(), Some("AAPL210618C130"));
+        assert_eq!(option.get_unit_price(), Some((Decimal::new(325, 0), "USD")));
+    }
+
     #[test]
     fn get_ib_postings() {
         let t = Trade {
@@ -337,10 +852,16 @@ mod tests {
             description: "ISHARES CORE EM IMI ACC".to_string(),
             transaction_id: "101876974".to_string(),
             date_time: "20210305;044915".to_string(),
-            quantity: 322,
+            quantity: Decimal::new(322, 0),
             trade_price: Decimal::from_f32(31.073).unwrap(),
             trade_money: Decimal::from_f32(10005.51).unwrap(),
             ib_commission: Decimal::from_f32(-10.00551).unwrap(),
+            asset_category: "STK".to_string(),
+            multiplier: Decimal::ONE,
+            underlying_symbol: String::new(),
+            expiry: String::new(),
+            strike: None,
+            put_call: String::new(),
         };
         let t = IbTransaction::Trade(t);
         let h = HledgerTransaction::new_with_postings(
@@ -357,6 +878,7 @@ mod tests {
                 account: "Investments".to_string(),
                 negate: false,
                 comment: None,
+                tags: vec![],
             }],
         );
         println!("{:#?}", h);
@@ -38,4 +38,9 @@ pub trait ImportAccount {
 
     // hledger account which should have their transactions considered for this ImportAccount
     fn get_hledger_account(&self) -> &str;
+
+    /// Whether sales of investments are split into the lots they close
+    fn tracks_lots(&self) -> bool {
+        false
+    }
 }
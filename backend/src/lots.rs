@@ -0,0 +1,412 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{Datelike, Duration, NaiveDate};
+use log::warn;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{
+    config,
+    model::hledger_transaction::{
+        HledgerTransaction, Posting, Price, LOT_DATE_TAG, SALE_PRICE_TAG,
+    },
+};
+
+pub const CAPITAL_GAINS_ACCOUNT: &str = "Income:CapitalGains";
+const DATE_FMT: &str = "%Y-%m-%d";
+// Lots held for longer than this are long term gains
+const LONG_TERM_DAYS: i64 = 365;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LotSelection {
+    /// Sell the oldest lots first
+    Fifo,
+    /// Sell the newest lots first
+    Lifo,
+}
+
+impl LotSelection {
+    pub fn from_config() -> Self {
+        if config::lifo_lots() {
+            LotSelection::Lifo
+        } else {
+            LotSelection::Fifo
+        }
+    }
+}
+
+/// Units of a commodity bought together
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lot {
+    pub date: NaiveDate,
+    pub quantity: Decimal,
+    /// Per unit
+    pub cost: Decimal,
+    pub cost_commodity: String,
+}
+
+/// The open lots of every commodity, oldest first
+#[derive(Debug)]
+pub struct Lots {
+    selection: LotSelection,
+    open: HashMap<String, Vec<Lot>>,
+}
+
+impl Lots {
+    /// Replay purchases and sales in the journal. Purchases are priced postings with a
+    /// positive quantity, sales close the lot in their lot date tag or else the next one in
+    /// the order of `selection`.
+    pub fn from_journal(
+        transactions: &[HledgerTransaction],
+        selection: LotSelection,
+        is_currency: impl Fn(&str) -> bool,
+    ) -> Self {
+        let mut lots = Self {
+            selection,
+            open: HashMap::new(),
+        };
+        let mut sorted: Vec<&HledgerTransaction> = transactions.iter().collect();
+        sorted.sort_by_key(|t| t.get_date(None));
+        for transaction in sorted {
+            for posting in &transaction.tpostings {
+                let (quantity, commodity, cost, cost_commodity) = match priced(posting) {
+                    Some(priced) if !is_currency(priced.1) => priced,
+                    _ => continue,
+                };
+                let date = posting
+                    .get_date()
+                    .unwrap_or_else(|| transaction.get_date(None));
+                if quantity.is_sign_positive() {
+                    lots.buy(commodity, date, quantity, cost, cost_commodity);
+                } else {
+                    let lot_date = posting
+                        .get_tag(LOT_DATE_TAG)
+                        .and_then(|d| NaiveDate::parse_from_str(d, DATE_FMT).ok());
+                    lots.take(commodity, -quantity, lot_date);
+                }
+            }
+        }
+        lots
+    }
+
+    fn buy(
+        &mut self,
+        commodity: &str,
+        date: NaiveDate,
+        quantity: Decimal,
+        cost: Decimal,
+        cost_commodity: &str,
+    ) {
+        let lots = self.open.entry(commodity.to_string()).or_default();
+        let i = lots.partition_point(|l| l.date <= date);
+        lots.insert(
+            i,
+            Lot {
+                date,
+                quantity,
+                cost,
+                cost_commodity: cost_commodity.to_string(),
+            },
+        );
+    }
+
+    /// Close the quantity, starting with the lot bought on `lot_date`. Returns the parts of
+    /// the lots which were closed, which may be less than the quantity.
+    fn take(
+        &mut self,
+        commodity: &str,
+        mut quantity: Decimal,
+        lot_date: Option<NaiveDate>,
+    ) -> Vec<Lot> {
+        let lots = match self.open.get_mut(commodity) {
+            Some(lots) => lots,
+            None => return vec![],
+        };
+        let mut order: Vec<usize> = (0..lots.len()).collect();
+        if self.selection == LotSelection::Lifo {
+            order.reverse();
+        }
+        if let Some(date) = lot_date {
+            order.sort_by_key(|&i| lots[i].date != date);
+        }
+
+        let mut taken = vec![];
+        for i in order {
+            if quantity <= Decimal::ZERO {
+                break;
+            }
+            let lot = &mut lots[i];
+            let part = quantity.min(lot.quantity);
+            lot.quantity -= part;
+            quantity -= part;
+            taken.push(Lot {
+                quantity: part,
+                ..lot.clone()
+            });
+        }
+        lots.retain(|l| !l.quantity.is_zero());
+        taken
+    }
+
+    /// Split the sales in newly generated transactions into one posting per lot at the lot's
+    /// cost and book the difference to the sale price to the capital gains account. Purchases
+    /// open new lots, so transactions are handled in date order.
+    pub fn book_sales<'a>(
+        &mut self,
+        transactions: impl IntoIterator<Item = &'a mut HledgerTransaction>,
+        is_currency: impl Fn(&str) -> bool,
+    ) {
+        let mut transactions: Vec<&mut HledgerTransaction> = transactions.into_iter().collect();
+        transactions.sort_by_key(|t| t.get_date(None));
+        for transaction in transactions {
+            let date = transaction.get_date(None);
+            let mut postings = vec![];
+            let mut gains = BTreeMap::<String, Decimal>::new();
+            for posting in transaction.tpostings.drain(..) {
+                let (quantity, commodity, price, price_commodity) = match priced(&posting) {
+                    Some(priced) if !is_currency(priced.1) => priced,
+                    _ => {
+                        postings.push(posting);
+                        continue;
+                    }
+                };
+                let (commodity, price_commodity) =
+                    (commodity.to_string(), price_commodity.to_string());
+                if quantity.is_sign_positive() {
+                    self.buy(&commodity, date, quantity, price, &price_commodity);
+                    postings.push(posting);
+                    continue;
+                }
+
+                let mut remaining = -quantity;
+                for lot in self.take(&commodity, remaining, None) {
+                    if lot.cost_commodity != price_commodity {
+                        warn!(
+                            "Lot of {} cost {} but was sold in {}",
+                            commodity, lot.cost_commodity, price_commodity
+                        );
+                    }
+                    remaining -= lot.quantity;
+                    *gains.entry(lot.cost_commodity.clone()).or_default() +=
+                        lot.quantity * (price - lot.cost);
+                    postings.push(
+                        posting
+                            .with_amount(
+                                &commodity,
+                                -lot.quantity,
+                                Some(Price::new(&lot.cost_commodity, lot.cost)),
+                            )
+                            .tag(LOT_DATE_TAG, &lot.date.format(DATE_FMT).to_string())
+                            .tag(SALE_PRICE_TAG, &price.to_string()),
+                    );
+                }
+                // Without earlier purchases in the journal there's no cost to book
+                if !remaining.is_zero() {
+                    warn!("No open lots for {} {}", remaining, commodity);
+                    postings.push(posting.with_amount(
+                        &commodity,
+                        -remaining,
+                        Some(Price::new(&price_commodity, price)),
+                    ));
+                }
+            }
+            for (commodity, gain) in gains {
+                if !gain.is_zero() {
+                    postings.push(Posting::new(
+                        CAPITAL_GAINS_ACCOUNT,
+                        &commodity,
+                        -gain,
+                        None,
+                        None,
+                    ));
+                }
+            }
+            transaction.tpostings = postings;
+        }
+    }
+}
+
+fn priced(posting: &Posting) -> Option<(Decimal, &str, Decimal, &str)> {
+    let (price, price_commodity) = posting.get_unit_price()?;
+    Some((
+        posting.get_amount()?,
+        posting.get_commodity()?,
+        price,
+        price_commodity,
+    ))
+}
+
+/// Part of a lot which was sold
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sale {
+    pub date: NaiveDate,
+    pub commodity: String,
+    pub quantity: Decimal,
+    pub lot_date: NaiveDate,
+    pub cost: Decimal,
+    pub sale_price: Decimal,
+    pub gain: Decimal,
+    pub long_term: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct YearGains {
+    pub year: i32,
+    pub commodity: String,
+    pub gains: Decimal,
+    pub losses: Decimal,
+    pub net: Decimal,
+    pub long_term_net: Decimal,
+    pub sales: Vec<Sale>,
+}
+
+/// Realised gains per tax year and cost commodity from the sales booked with lots
+pub fn realised_gains(transactions: &[HledgerTransaction]) -> Vec<YearGains> {
+    let mut years = BTreeMap::<(i32, String), Vec<Sale>>::new();
+    for transaction in transactions {
+        for posting in &transaction.tpostings {
+            let lot_date = match posting
+                .get_tag(LOT_DATE_TAG)
+                .and_then(|d| NaiveDate::parse_from_str(d, DATE_FMT).ok())
+            {
+                Some(lot_date) => lot_date,
+                None => continue,
+            };
+            let (quantity, commodity, cost, cost_commodity) = match priced(posting) {
+                Some(priced) => priced,
+                None => continue,
+            };
+            let sale_price = match posting.get_tag(SALE_PRICE_TAG).and_then(|p| p.parse().ok()) {
+                Some(sale_price) => sale_price,
+                None => continue,
+            };
+            let date = posting
+                .get_date()
+                .unwrap_or_else(|| transaction.get_date(None));
+            let quantity = -quantity;
+            years
+                .entry((date.year(), cost_commodity.to_string()))
+                .or_default()
+                .push(Sale {
+                    date,
+                    commodity: commodity.to_string(),
+                    quantity,
+                    lot_date,
+                    cost,
+                    sale_price,
+                    gain: quantity * (sale_price - cost),
+                    long_term: date - lot_date > Duration::days(LONG_TERM_DAYS),
+                });
+        }
+    }
+
+    years
+        .into_iter()
+        .map(|((year, commodity), mut sales)| {
+            sales.sort_by_key(|s| s.date);
+            let gains = sales.iter().map(|s| s.gain.max(Decimal::ZERO)).sum();
+            let losses = sales.iter().map(|s| s.gain.min(Decimal::ZERO)).sum();
+            let long_term_net = sales.iter().filter(|s| s.long_term).map(|s| s.gain).sum();
+            YearGains {
+                year,
+                commodity,
+                gains,
+                losses,
+                net: gains + losses,
+                long_term_net,
+                sales,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+
+    use super::{realised_gains, LotSelection, Lots, CAPITAL_GAINS_ACCOUNT};
+    use crate::model::hledger_transaction::{HledgerTransaction, Posting, Price};
+
+    fn trade(date: (i32, u32, u32), quantity: i64, price: i64) -> HledgerTransaction {
+        let mut postings = vec![
+            Posting::new(
+                "Assets:Investments:IB",
+                "EMIM",
+                Decimal::from(quantity),
+                Some(Price::new("EUR", Decimal::from(price))),
+                None,
+            ),
+            Posting::new(
+                "Assets:Investments:IB",
+                "EUR",
+                Decimal::from(-quantity * price),
+                None,
+                None,
+            ),
+        ];
+        HledgerTransaction::without_id("trade", NaiveDate::from_ymd(date.0, date.1, date.2))
+            .postings(&mut postings)
+    }
+
+    fn is_currency(commodity: &str) -> bool {
+        commodity == "EUR"
+    }
+
+    #[test]
+    fn sale_closes_oldest_lots() {
+        let journal = vec![
+            trade((2020, 1, 10), 10, 20),
+            trade((2021, 2, 1), 10, 30),
+            trade((2021, 3, 1), -4, 25),
+        ];
+        let mut lots = Lots::from_journal(&journal, LotSelection::Fifo, is_currency);
+        assert_eq!(lots.open["EMIM"][0].quantity, Decimal::from(6));
+
+        let mut sale = trade((2021, 6, 1), -8, 40);
+        lots.book_sales(vec![&mut sale], is_currency);
+        // 6 from the first lot, 2 from the second and the capital gain
+        let amounts: Vec<_> = sale
+            .get_priced_amounts()
+            .map(|(q, _, p, _)| (q, p))
+            .collect();
+        assert_eq!(
+            amounts,
+            vec![
+                (Decimal::from(-6), Decimal::from(40)),
+                (Decimal::from(-2), Decimal::from(40))
+            ]
+        );
+        assert_eq!(
+            sale.get_amount_with_commodity(CAPITAL_GAINS_ACCOUNT),
+            Some((Decimal::from(-140), "EUR"))
+        );
+        assert_eq!(lots.open["EMIM"][0].quantity, Decimal::from(8));
+
+        let mut journal = journal;
+        journal.push(sale);
+        let years = realised_gains(&journal);
+        assert_eq!(years.len(), 1);
+        assert_eq!(years[0].year, 2021);
+        assert_eq!(years[0].net, Decimal::from(140));
+        assert_eq!(years[0].long_term_net, Decimal::from(120));
+    }
+
+    #[test]
+    fn lifo_and_missing_lots() {
+        let journal = vec![trade((2021, 1, 1), 5, 20), trade((2021, 2, 1), 5, 30)];
+        let mut lots = Lots::from_journal(&journal, LotSelection::Lifo, is_currency);
+        let mut sale = trade((2021, 3, 1), -12, 25);
+        lots.book_sales(vec![&mut sale], is_currency);
+        let quantities: Vec<_> = sale.get_priced_amounts().map(|(q, ..)| q).collect();
+        assert_eq!(
+            quantities,
+            vec![Decimal::from(-5), Decimal::from(-5), Decimal::from(-2)]
+        );
+        // -5 * (25 - 30) + 5 * (25 - 20) = 0
+        assert_eq!(sale.get_amount_with_commodity(CAPITAL_GAINS_ACCOUNT), None);
+        assert!(lots.open["EMIM"].is_empty());
+    }
+}
@@ -1,20 +1,29 @@
-mod alpha_vantage;
 mod api;
 mod auth;
+mod budgets;
 mod config;
+mod csv_rules;
 mod db;
 mod file_utils;
+mod flows;
+mod forecast;
 mod git;
 mod hledger;
 mod http;
 mod ib;
 mod import_account;
+mod lots;
 mod model;
 mod n26;
+mod net_worth;
+mod portfolio;
+mod price_sources;
 mod prices;
 mod saltedge;
+mod subscriptions;
 mod templater;
 mod transactions;
+mod transfers;
 
 #[cfg(test)]
 mod test_statics;
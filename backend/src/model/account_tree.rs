@@ -0,0 +1,87 @@
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+/// An account with its amount per period, including all subaccounts
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountTree {
+    /// Full account name, e.g. `Expenses:Food:Groceries`
+    pub account: String,
+    pub amounts: Vec<Decimal>,
+    pub children: Vec<AccountTree>,
+}
+
+impl AccountTree {
+    /// Build the trees from flat hledger report rows, whose amounts exclude subaccounts.
+    /// Parent accounts without postings of their own are added as needed.
+    pub fn from_rows(rows: &[(String, Vec<Decimal>)]) -> Vec<AccountTree> {
+        let mut roots = vec![];
+        for (account, amounts) in rows {
+            let parts: Vec<&str> = account.split(':').collect();
+            insert(&mut roots, &parts, 1, amounts);
+        }
+        roots
+    }
+}
+
+fn insert(nodes: &mut Vec<AccountTree>, parts: &[&str], depth: usize, amounts: &[Decimal]) {
+    let account = parts[..depth].join(":");
+    let i = match nodes.iter().position(|n| n.account == account) {
+        Some(i) => i,
+        None => {
+            nodes.push(AccountTree {
+                account,
+                amounts: vec![Decimal::ZERO; amounts.len()],
+                children: vec![],
+            });
+            nodes.len() - 1
+        }
+    };
+    let node = &mut nodes[i];
+    for (total, amount) in node.amounts.iter_mut().zip(amounts) {
+        *total += amount;
+    }
+    if depth < parts.len() {
+        insert(&mut node.children, parts, depth + 1, amounts);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::AccountTree;
+
+    #[test]
+    fn parents_include_subaccounts() {
+        let rows = vec![
+            (
+                "Expenses:Food:Groceries".to_string(),
+                vec![Decimal::new(100, 0), Decimal::new(50, 0)],
+            ),
+            (
+                "Expenses:Food".to_string(),
+                vec![Decimal::new(10, 0), Decimal::ZERO],
+            ),
+            (
+                "Expenses:Rent".to_string(),
+                vec![Decimal::new(800, 0), Decimal::new(800, 0)],
+            ),
+        ];
+        let trees = AccountTree::from_rows(&rows);
+        assert_eq!(trees.len(), 1);
+        let expenses = &trees[0];
+        assert_eq!(expenses.account, "Expenses");
+        assert_eq!(
+            expenses.amounts,
+            vec![Decimal::new(910, 0), Decimal::new(850, 0)]
+        );
+        let food = &expenses.children[0];
+        assert_eq!(
+            food.amounts,
+            vec![Decimal::new(110, 0), Decimal::new(50, 0)]
+        );
+        assert_eq!(food.children[0].account, "Expenses:Food:Groceries");
+        assert_eq!(expenses.children[1].account, "Expenses:Rent");
+    }
+}
@@ -1,10 +1,26 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeSet, HashMap},
+};
+
+use chrono::NaiveDate;
 use rust_decimal::{prelude::ToPrimitive, Decimal};
 use serde::{Deserialize, Serialize, Serializer};
 
+use crate::prices::PriceTable;
+
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct BalancesResponse {
     pub balances: Vec<BalanceResponse>,
+    /// Commodity of the `*_base` amounts
+    pub base_currency: String,
+    /// Totals of the commodities with a base value
+    pub real_base: Decimal,
+    pub hledger_base: Decimal,
+    pub difference_base: Decimal,
+    /// Every commodity and the total are within the tolerance
+    pub in_sync: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -13,7 +29,75 @@ pub struct BalanceResponse {
     pub commodity: String,
     pub hledger: Decimal,
     pub real: Decimal,
-    pub real_euro: Option<Decimal>,
+    /// `real - hledger`
+    pub difference: Decimal,
+    /// The balances in the base currency, if there is a price for the commodity
+    pub real_base: Option<Decimal>,
+    pub hledger_base: Option<Decimal>,
+    pub difference_base: Option<Decimal>,
+    /// The difference is within the tolerance, in the base currency if possible
+    pub in_sync: bool,
+}
+
+impl BalancesResponse {
+    /// Compare the real balances of an account with its hledger balances, including
+    /// commodities only one of them has. Amounts are valued with the latest prices on or
+    /// before `date`, or the rate the importer valued the real balance with.
+    pub fn compare(
+        real: Vec<RealBalance>,
+        hledger: &HashMap<String, Decimal>,
+        prices: &PriceTable,
+        base_currency: &str,
+        date: NaiveDate,
+        tolerance: Decimal,
+    ) -> Self {
+        let mut real: HashMap<String, RealBalance> =
+            real.into_iter().map(|b| (b.commodity.clone(), b)).collect();
+        let commodities: BTreeSet<String> = real
+            .keys()
+            .chain(hledger.iter().filter(|(_, a)| !a.is_zero()).map(|(c, _)| c))
+            .cloned()
+            .collect();
+        let mut balances: Vec<BalanceResponse> = commodities
+            .into_iter()
+            .map(|commodity| {
+                let (real, base_amount) = match real.remove(&commodity) {
+                    Some(b) => (b.amount, b.base_amount),
+                    None => (Decimal::ZERO, None),
+                };
+                let hledger = hledger.get(&commodity).copied().unwrap_or_default();
+                let rate = prices
+                    .rate(&commodity, base_currency, date)
+                    .or_else(|| Some(base_amount? / real).filter(|_| !real.is_zero()));
+                let real_base = base_amount.or_else(|| Some(real * rate?));
+                let hledger_base = rate.map(|rate| hledger * rate);
+                let difference_base = real_base.zip(hledger_base).map(|(r, h)| r - h);
+                let difference = real - hledger;
+                BalanceResponse {
+                    in_sync: difference_base.unwrap_or(difference).abs() <= tolerance,
+                    commodity,
+                    hledger,
+                    real,
+                    difference,
+                    real_base,
+                    hledger_base,
+                    difference_base,
+                }
+            })
+            .collect();
+        balances.sort_by_key(|x| Reverse(x.real_base));
+        let real_base = balances.iter().filter_map(|b| b.real_base).sum();
+        let hledger_base = balances.iter().filter_map(|b| b.hledger_base).sum();
+        let difference_base: Decimal = balances.iter().filter_map(|b| b.difference_base).sum();
+        Self {
+            in_sync: balances.iter().all(|b| b.in_sync) && difference_base.abs() <= tolerance,
+            balances,
+            base_currency: base_currency.to_string(),
+            real_base,
+            hledger_base,
+            difference_base,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Default, Clone)]
@@ -36,3 +120,83 @@ where
         .ok_or_else(|| serde::ser::Error::custom("Couldn't convert Decimal to f64"))?;
     s.serialize_f64(v)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+
+    use super::{BalancesResponse, RealBalance};
+    use crate::prices::{Price, PriceTable};
+
+    fn real(commodity: &str, amount: i64, base_amount: Option<i64>) -> RealBalance {
+        RealBalance {
+            commodity: commodity.to_string(),
+            amount: Decimal::new(amount, 2),
+            base_amount: base_amount.map(|a| Decimal::new(a, 2)),
+        }
+    }
+
+    #[test]
+    fn compare_converted_balances() {
+        let date = NaiveDate::from_ymd(2021, 5, 1);
+        let prices = PriceTable::new(vec![Price {
+            date: NaiveDate::from_ymd(2021, 4, 30),
+            from_commodity: "EUR".to_string(),
+            to_commodity: "USD".to_string(),
+            amount: Decimal::new(125, 2),
+        }]);
+        let hledger: HashMap<String, Decimal> = vec![
+            ("USD".to_string(), Decimal::new(10005, 2)),
+            ("EUR".to_string(), Decimal::new(5000, 2)),
+            ("EMIM".to_string(), Decimal::new(300, 2)),
+            ("BTC".to_string(), Decimal::new(100, 2)),
+        ]
+        .into_iter()
+        .collect();
+        let balances = BalancesResponse::compare(
+            vec![
+                // Off by 0.05 USD = 0.04 EUR
+                real("USD", 10000, None),
+                real("EUR", 5000, None),
+                // Valued by the importer at 20 EUR per share
+                real("EMIM", 200, Some(4000)),
+            ],
+            &hledger,
+            &prices,
+            "EUR",
+            date,
+            Decimal::new(1, 1),
+        );
+
+        let commodities: Vec<&str> = balances
+            .balances
+            .iter()
+            .map(|b| b.commodity.as_str())
+            .collect();
+        assert_eq!(commodities, vec!["USD", "EUR", "EMIM", "BTC"]);
+
+        let usd = &balances.balances[0];
+        assert_eq!(usd.real_base, Some(Decimal::new(80, 0)));
+        assert_eq!(usd.difference_base, Some(Decimal::new(-4, 2)));
+        assert!(usd.in_sync);
+
+        let emim = &balances.balances[2];
+        assert_eq!(emim.hledger_base, Some(Decimal::new(60, 0)));
+        assert_eq!(emim.difference_base, Some(Decimal::new(-20, 0)));
+        assert!(!emim.in_sync);
+
+        // Only in the journal and without a price
+        let btc = &balances.balances[3];
+        assert_eq!(btc.real, Decimal::ZERO);
+        assert_eq!(btc.difference, Decimal::new(-1, 0));
+        assert_eq!(btc.real_base, None);
+        assert!(!btc.in_sync);
+
+        assert_eq!(balances.real_base, Decimal::new(170, 0));
+        assert_eq!(balances.difference_base, Decimal::new(-2004, 2));
+        assert!(!balances.in_sync);
+    }
+}
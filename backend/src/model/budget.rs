@@ -0,0 +1,38 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum BudgetPeriod {
+    Monthly,
+    Yearly,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Budget {
+    /// None for budgets read from periodic transactions in the journal
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<bson::oid::ObjectId>,
+    /// Also covers all subaccounts
+    pub account: String,
+    pub amount: Decimal,
+    pub commodity: String,
+    pub period: BudgetPeriod,
+}
+
+impl Budget {
+    /// Yearly budgets are spread evenly over the months
+    pub fn monthly_amount(&self) -> Decimal {
+        match self.period {
+            BudgetPeriod::Monthly => self.amount,
+            BudgetPeriod::Yearly => (self.amount / Decimal::from(12)).round_dp(2),
+        }
+    }
+
+    pub fn covers(&self, account: &str) -> bool {
+        account == self.account
+            || account
+                .strip_prefix(&self.account)
+                .map_or(false, |rest| rest.starts_with(':'))
+    }
+}
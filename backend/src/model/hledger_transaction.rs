@@ -6,6 +6,11 @@ use serde::{Deserialize, Serialize};
 
 use super::{real_transaction::RealTransaction, rule::RulePosting};
 
+/// Sales of a lot are booked at the lot's cost and tagged with the date the lot was bought
+pub const LOT_DATE_TAG: &str = "lot_date";
+/// The actual price per unit of a sale booked at the lot's cost
+pub const SALE_PRICE_TAG: &str = "sale_price";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct Quantity {
@@ -122,16 +127,79 @@ impl Posting {
         }
     }
 
+    /// Set a posting date, for postings which happened on a different day to the transaction
+    pub fn date(mut self, date: NaiveDate) -> Self {
+        self.pdate = Some(date);
+        self
+    }
+
+    /// Add a tag, which is written as part of the posting comment
+    pub fn tag(mut self, name: &str, value: &str) -> Self {
+        let value = append_tag(&mut self.pcomment, name, value);
+        self.ptags.push(vec![name.to_string(), value]);
+        self
+    }
+
+    /// The same posting with a different amount
+    pub fn with_amount(&self, commodity: &str, quantity: Decimal, price: Option<Price>) -> Self {
+        Self {
+            pamount: vec![Amount::new_priced(commodity, quantity, price)],
+            ..self.clone()
+        }
+    }
+
+    pub fn get_date(&self) -> Option<NaiveDate> {
+        self.pdate
+    }
+
+    pub fn get_tag(&self, name: &str) -> Option<&str> {
+        self.ptags
+            .iter()
+            .find(|t| t.first().map(String::as_str) == Some(name))
+            .and_then(|t| t.get(1))
+            .map(String::as_str)
+    }
+
     fn get_id(&self) -> Option<&str> {
         get_uuid_from_tags(&self.ptags)
     }
 
-    fn get_amount(&self) -> Option<Decimal> {
+    fn has_tag(&self, name: &str, value: Option<&str>) -> bool {
+        tags_contain(&self.ptags, name, value)
+    }
+
+    pub fn get_amount(&self) -> Option<Decimal> {
         match self.pamount.len() {
             1 => Some((&self.pamount[0].aquantity).into()),
             _ => None,
         }
     }
+
+    pub fn get_commodity(&self) -> Option<&str> {
+        match self.pamount.len() {
+            1 => Some(&self.pamount[0].acommodity),
+            _ => None,
+        }
+    }
+
+    /// Price per unit and its commodity of a single priced amount
+    pub fn get_unit_price(&self) -> Option<(Decimal, &str)> {
+        let amount = match &self.pamount[..] {
+            [amount] => amount,
+            _ => return None,
+        };
+        match amount.aprice.as_deref()? {
+            Price::UnitPrice(price) => Some(((&price.aquantity).into(), &price.acommodity)),
+            Price::TotalPrice(price) => {
+                let quantity = Decimal::from(&amount.aquantity).abs();
+                if quantity.is_zero() {
+                    return None;
+                }
+                let total = Decimal::from(&price.aquantity);
+                Some((total / quantity, &price.acommodity))
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -159,13 +227,18 @@ pub struct HledgerTransaction {
 
 impl HledgerTransaction {
     pub fn new(description: &str, date: NaiveDate, id: &str) -> Self {
+        Self::without_id(description, date).tag("uuid", id)
+    }
+
+    /// For transactions made up of several real transactions, whose ids are on the postings
+    pub fn without_id(description: &str, date: NaiveDate) -> Self {
         Self {
             tdescription: description.to_string(),
             tdate: date,
-            ttags: vec![vec!["uuid".to_string(), id.to_string()]],
+            ttags: vec![],
             tpostings: Vec::<Posting>::new(),
             tcode: String::new(),
-            tcomment: format!("uuid:{}", id),
+            tcomment: String::new(),
             tprecedingcomment: String::new(),
             tdate2: None,
             tstatus: String::from("Unmarked"),
@@ -202,6 +275,20 @@ impl HledgerTransaction {
         .postings(&mut real_transaction.get_postings(hledger_account, postings))
     }
 
+    /// Add a tag, which is written as part of the transaction comment
+    pub fn tag(mut self, name: &str, value: &str) -> Self {
+        let value = append_tag(&mut self.tcomment, name, value);
+        self.ttags.push(vec![name.to_string(), value]);
+        self
+    }
+
+    /// Does the transaction or any of its postings have the tag? Names and values are compared
+    /// ignoring case like hledger's `tag:` query, a value of None matches any value.
+    pub fn has_tag(&self, name: &str, value: Option<&str>) -> bool {
+        tags_contain(&self.ttags, name, value)
+            || self.tpostings.iter().any(|p| p.has_tag(name, value))
+    }
+
     /// Add posting
     pub fn _posting(mut self, posting: Posting) -> Self {
         self.tpostings.push(posting);
@@ -244,6 +331,47 @@ impl HledgerTransaction {
         get_uuid_from_tags(&self.ttags)
     }
 
+    /// The description up to the note, see https://hledger.org/journal.html#payee-and-note
+    pub fn get_payee(&self) -> &str {
+        self.tdescription
+            .split('|')
+            .next()
+            .unwrap_or_default()
+            .trim()
+    }
+
+    /// Account, date, amount and commodity of every posting with a single amount
+    pub fn get_posting_amounts(&self) -> impl Iterator<Item = (&str, NaiveDate, Decimal, &str)> {
+        self.tpostings.iter().filter_map(move |p| {
+            Some((
+                p.paccount.as_str(),
+                p.pdate.unwrap_or(self.tdate),
+                p.get_amount()?,
+                p.get_commodity()?,
+            ))
+        })
+    }
+
+    /// Quantity, commodity, unit price and price commodity of every posting with a priced
+    /// amount. Sales booked at a lot's cost return the actual sale price.
+    pub fn get_priced_amounts(&self) -> impl Iterator<Item = (Decimal, &str, Decimal, &str)> {
+        self.tpostings.iter().filter_map(|p| {
+            let (price, price_commodity) = p.get_unit_price()?;
+            let price = p
+                .get_tag(SALE_PRICE_TAG)
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(price);
+            Some((p.get_amount()?, p.get_commodity()?, price, price_commodity))
+        })
+    }
+
+    /// Amount and commodity of the first posting to the account with a single amount
+    pub fn get_amount_with_commodity(&self, account: &str) -> Option<(Decimal, &str)> {
+        self.get_postings(account)
+            .into_iter()
+            .find_map(|p| Some((p.get_amount()?, p.get_commodity()?)))
+    }
+
     pub fn has_account(&self, account: &str) -> bool {
         !self.get_postings(account).is_empty()
     }
@@ -274,6 +402,33 @@ impl HledgerTransaction {
     }
 }
 
+/// Split `name:value` into name and value. Tags without a colon have an empty value.
+pub fn split_tag(tag: &str) -> (&str, &str) {
+    match tag.split_once(':') {
+        Some((name, value)) => (name.trim(), value.trim()),
+        None => (tag.trim(), ""),
+    }
+}
+
+// Returns the value as written, as commas and newlines would end the tag value early
+fn append_tag(comment: &mut String, name: &str, value: &str) -> String {
+    let value = value.replace(&[',', '\n'][..], " ").trim().to_string();
+    if !comment.is_empty() {
+        comment.push_str(", ");
+    }
+    comment.push_str(&format!("{}:{}", name, value));
+    value
+}
+
+fn tags_contain(tags: &[Vec<String>], name: &str, value: Option<&str>) -> bool {
+    tags.iter().any(|t| match (t.as_slice(), value) {
+        ([n, ..], _) if !n.eq_ignore_ascii_case(name) => false,
+        ([_, tv, ..], Some(v)) => tv.eq_ignore_ascii_case(v),
+        ([_, ..], None) => true,
+        _ => false,
+    })
+}
+
 fn get_uuid_from_tags(tags: &[Vec<String>]) -> Option<&str> {
     tags.iter()
         .find(|t| t[0] == "uuid")
@@ -282,6 +437,7 @@ fn get_uuid_from_tags(tags: &[Vec<String>]) -> Option<&str> {
 
 #[cfg(test)]
 mod tests {
+    use chrono::NaiveDate;
     use rust_decimal::{prelude::FromPrimitive, Decimal};
 
     use super::{Amount, HledgerTransaction, Quantity};
@@ -305,6 +461,17 @@ mod tests {
         }
     }
 
+    #[test]
+    fn has_tag() {
+        let transaction = HledgerTransaction::without_id("test", NaiveDate::from_ymd(2021, 3, 1))
+            .tag("trip", "Rome 2021");
+        assert!(transaction.has_tag("trip", None));
+        assert!(transaction.has_tag("Trip", Some("rome 2021")));
+        // Exact like the anchored hledger query
+        assert!(!transaction.has_tag("trip", Some("Rome")));
+        assert!(!transaction.has_tag("tri", None));
+    }
+
     #[test]
     fn deserialize() {
         let json = r#"{
@@ -1,6 +1,8 @@
 use serde::Serialize;
 
-use super::{aligned_data::AlignedData, hledger_transaction::HledgerTransaction};
+use super::{
+    account_tree::AccountTree, aligned_data::AlignedData, hledger_transaction::HledgerTransaction,
+};
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -8,4 +10,14 @@ pub struct IncomeStatementResponse {
     pub data: AlignedData,
     pub top_revenues: Vec<Vec<HledgerTransaction>>,
     pub top_expenses: Vec<Vec<HledgerTransaction>>,
+    /// Income and expense accounts down to the requested depth
+    pub accounts: Vec<AccountTree>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetWorthResponse {
+    pub data: AlignedData,
+    /// Asset and liability accounts down to the requested depth
+    pub accounts: Vec<AccountTree>,
 }
@@ -1,11 +1,14 @@
+pub mod account_tree;
 pub mod aligned_data;
 pub mod balance;
+pub mod budget;
 pub mod hledger_transaction;
 pub mod income_statement;
 pub mod n26_accounts;
 pub mod n26_transaction;
 pub mod real_transaction;
 pub mod rule;
+pub mod rule_revision;
 pub mod saltedge_account;
 pub mod saltedge_transaction;
 pub mod token_data;
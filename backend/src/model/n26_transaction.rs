@@ -35,4 +35,8 @@ impl RealTransaction for N26Transaction {
     fn get_default_currency_field_name(&self) -> &str {
         "currencyCode"
     }
+
+    fn get_payee_field_name(&self) -> Option<&str> {
+        Some("partnerName")
+    }
 }
@@ -5,7 +5,7 @@ use rust_decimal::Decimal;
 use serde::{de::DeserializeOwned, Serialize};
 
 use super::{
-    hledger_transaction::{Posting, Price},
+    hledger_transaction::{split_tag, Posting, Price},
     rule::RulePosting,
 };
 
@@ -15,6 +15,15 @@ pub trait RealTransaction: Serialize + DeserializeOwned + Send + Sync + Debug {
     fn get_default_amount_field_name(&self) -> &str;
     fn get_default_currency_field_name(&self) -> &str;
 
+    /// Field with the name of the other party, if the importer has one
+    fn get_payee_field_name(&self) -> Option<&str> {
+        None
+    }
+
+    fn get_payee(&self) -> Option<String> {
+        self.get_field(self.get_payee_field_name()?)
+    }
+
     fn get_postings(&self, hledger_account: &str, postings: &[RulePosting]) -> Vec<Posting> {
         let mut result: Vec<Posting> = vec![];
         if postings.len() < 2 {
@@ -26,6 +35,7 @@ pub trait RealTransaction: Serialize + DeserializeOwned + Send + Sync + Debug {
                 comment: None,
                 account: hledger_account.to_string(),
                 negate: false,
+                tags: vec![],
             }) {
                 result.push(p);
             }
@@ -88,13 +98,17 @@ pub trait RealTransaction: Serialize + DeserializeOwned + Send + Sync + Debug {
         let amount = if rule_posting.negate { -amount } else { amount };
         let commodity = self.get_currency(rule_posting)?;
         let price = self.get_price(rule_posting);
-        Some(Posting::new(
+        let posting = Posting::new(
             &rule_posting.account,
             &commodity,
             amount,
             price,
             rule_posting.comment.as_deref(),
-        ))
+        );
+        Some(rule_posting.tags.iter().fold(posting, |posting, tag| {
+            let (name, value) = split_tag(tag);
+            posting.tag(name, value)
+        }))
     }
 
     fn get_price(&self, rule_posting: &RulePosting) -> Option<Price> {
@@ -1,9 +1,18 @@
+use std::collections::BTreeMap;
+
 use regex::Regex;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-use super::{hledger_transaction::HledgerTransaction, real_transaction::RealTransaction};
+use super::{
+    hledger_transaction::{split_tag, HledgerTransaction},
+    real_transaction::RealTransaction,
+};
 use crate::templater::Templater;
 
+const RULE_TAG: &str = "rule";
+const RULE_REVISION_TAG: &str = "rule_revision";
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct RulePostingPrice {
@@ -27,6 +36,9 @@ pub struct RulePosting {
     pub negate: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<String>,
+    /// Tags to add to the posting as `name:value`, where the value may be a template
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -39,10 +51,20 @@ pub struct Rule {
     pub importer_id: String,
     pub rule_name: String,
     pub match_field_name: String,
+    /// Named capture groups are available in templates and added to the transaction as tags
     #[serde(with = "serde_regex")]
     pub match_field_regex: Regex,
+    /// The payee, or the whole description if there's no note
     pub description_template: String,
+    /// Written after the payee, separated by a pipe as hledger expects
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note_template: Option<String>,
+    /// Tags to add to the transaction as `name:value`, where the value may be a template
+    pub tags: Vec<String>,
+    /// The account and comment of each posting may be templates, like the description
     pub postings: Vec<RulePosting>,
+    /// Incremented every time the rule is saved
+    pub revision: u32,
 }
 
 impl Default for Rule {
@@ -55,18 +77,43 @@ impl Default for Rule {
             match_field_name: Default::default(),
             match_field_regex: Regex::new("$^").unwrap(),
             description_template: Default::default(),
+            note_template: Default::default(),
+            tags: Default::default(),
             postings: Default::default(),
+            revision: Default::default(),
         }
     }
 }
 
 impl Rule {
     pub fn matches(&self, real_transaction: &impl RealTransaction) -> bool {
-        let value = real_transaction.to_json_value();
-        if let Some(field) = value.get(&self.match_field_name) {
-            return self.match_field_regex.is_match(&field.to_string());
+        match self.match_field_text(&real_transaction.to_json_value()) {
+            Some(text) => self.match_field_regex.is_match(&text),
+            None => false,
         }
-        false
+    }
+
+    /// Values of the named capture groups in match_field_regex, or None if the rule doesn't match
+    pub fn captures(
+        &self,
+        real_transaction: &impl RealTransaction,
+    ) -> Option<BTreeMap<String, String>> {
+        let text = self.match_field_text(&real_transaction.to_json_value())?;
+        let captures = self.match_field_regex.captures(&text)?;
+        Some(
+            self.match_field_regex
+                .capture_names()
+                .flatten()
+                .filter_map(|name| {
+                    Some((name.to_string(), captures.name(name)?.as_str().to_string()))
+                })
+                .collect(),
+        )
+    }
+
+    // Fields are matched as JSON, so strings keep their quotes like they always have
+    fn match_field_text(&self, value: &Value) -> Option<String> {
+        Some(value.get(&self.match_field_name)?.to_string())
     }
 
     // Map fields in real transaction to new hledger transaction
@@ -76,21 +123,44 @@ impl Rule {
         hledger_account: &str,
         real_transaction: &impl RealTransaction,
     ) -> Option<HledgerTransaction> {
-        if !self.matches(real_transaction) {
-            return None;
+        let captures = self.captures(real_transaction)?;
+        // Named captures are available to templates alongside the transaction's own fields
+        let mut data = real_transaction.to_json_value();
+        if let Some(fields) = data.as_object_mut() {
+            for (name, value) in &captures {
+                fields.insert(name.clone(), Value::String(value.clone()));
+            }
         }
-        let description = templater
-            .render_description_from_rule(self, real_transaction)
-            .ok()?;
+        let mut description = templater.render_description_from_rule(self, &data).ok()?;
+        if let Some(note) = &self.note_template {
+            let note = templater.render_if_template(note, &data).ok()?;
+            if !note.is_empty() {
+                description = format!("{} | {}", description, note);
+            }
+        }
+        let postings = templater.render_postings(&self.postings, &data).ok()?;
 
-        Some(
-            HledgerTransaction::new(
-                &description,
-                real_transaction.get_date(),
-                &real_transaction.get_id(),
-            )
-            .postings(&mut real_transaction.get_postings(hledger_account, &self.postings)),
+        let mut transaction = HledgerTransaction::new(
+            &description,
+            real_transaction.get_date(),
+            &real_transaction.get_id(),
         )
+        .postings(&mut real_transaction.get_postings(hledger_account, &postings));
+        // Record which revision of the rule generated the transaction
+        if let Some(id) = self.id {
+            transaction = transaction
+                .tag(RULE_TAG, &id.to_hex())
+                .tag(RULE_REVISION_TAG, &self.revision.to_string());
+        }
+        for (name, value) in &captures {
+            transaction = transaction.tag(name, value);
+        }
+        for tag in &self.tags {
+            let tag = templater.render_if_template(tag, &data).ok()?;
+            let (name, value) = split_tag(&tag);
+            transaction = transaction.tag(name, value);
+        }
+        Some(transaction)
     }
 }
 
@@ -101,7 +171,7 @@ mod tests {
 
     use super::*;
     use crate::{
-        model::rule::Rule,
+        model::{n26_transaction::N26Transaction, rule::Rule},
         test_statics::{ASSET_ACCOUNT, REAL, RULES},
     };
 
@@ -121,6 +191,81 @@ mod tests {
         assert_eq!(t.tpostings[1].paccount, "Expenses:Personal:Entertainment");
     }
 
+    #[test]
+    fn apply_rule_tags_revision() {
+        let rule = Rule {
+            id: Some(bson::oid::ObjectId::new()),
+            revision: 4,
+            ..RULES[0].clone()
+        };
+        let mut templater = Templater::new();
+        templater.register_rule(&rule).unwrap();
+        let t = rule.apply(&templater, ASSET_ACCOUNT, &REAL[0]).unwrap();
+        assert_eq!(t.ttags[0][0], "uuid");
+        assert_eq!(t.ttags[1], vec!["rule", &rule.id.unwrap().to_hex()]);
+        assert_eq!(t.ttags[2], vec!["rule_revision", "4"]);
+    }
+
+    #[test]
+    fn apply_rule_captures() {
+        let rule = Rule {
+            rule_name: "Captures".to_string(),
+            match_field_name: "partnerName".to_string(),
+            match_field_regex: Regex::new("^\"(?P<merchant>\\w+) Mktp (?P<country>[A-Z]{2})")
+                .unwrap(),
+            description_template: "{{merchant}} order".to_string(),
+            postings: vec![RulePosting {
+                account: "Expenses:Shopping:{{country}}".to_string(),
+                negate: true,
+                ..RulePosting::default()
+            }],
+            ..Rule::default()
+        };
+        let mut real = REAL[0].to_json_value();
+        real["partnerName"] = "Amazon Mktp DE, Luxembourg".into();
+        let real: N26Transaction = serde_json::from_value(real).unwrap();
+        let mut templater = Templater::new();
+        templater.register_rule(&rule).unwrap();
+        let t = rule.apply(&templater, ASSET_ACCOUNT, &real).unwrap();
+        assert_eq!(t.tdescription, "Amazon order");
+        assert_eq!(t.tpostings[1].paccount, "Expenses:Shopping:DE");
+        assert_eq!(t.ttags[1], vec!["country", "DE"]);
+        assert_eq!(t.ttags[2], vec!["merchant", "Amazon"]);
+    }
+
+    #[test]
+    fn match_quoted_string_field() {
+        // Stored rules may be anchored on the JSON quotes of string fields
+        let rule = Rule {
+            match_field_name: "partnerName".to_string(),
+            match_field_regex: Regex::new("^\"Amazon\"$").unwrap(),
+            ..Rule::default()
+        };
+        assert!(rule.matches(&REAL[0]));
+        assert!(!rule.matches(&REAL[1]));
+    }
+
+    #[test]
+    fn apply_rule_note_and_tags() {
+        let mut rule = RULES[0].clone();
+        rule.note_template = Some("{{{referenceText}}}".to_string());
+        rule.description_template = "Amazon".to_string();
+        rule.tags = vec![
+            "project:kitchen".to_string(),
+            "trip:{{currencyCode}}".to_string(),
+        ];
+        rule.postings[1].tags = vec!["reimbursable".to_string()];
+        let mut templater = Templater::new();
+        templater.register_rule(&rule).unwrap();
+        let t = rule.apply(&templater, ASSET_ACCOUNT, &REAL[0]).unwrap();
+        assert_eq!(t.tdescription, "Amazon | Buy item 1");
+        assert_eq!(t.ttags[1], vec!["project", "kitchen"]);
+        assert_eq!(t.ttags[2], vec!["trip", "EUR"]);
+        assert!(t.has_tag("trip", Some("eur")));
+        assert!(t.has_tag("reimbursable", None));
+        assert!(!t.has_tag("trip", Some("USD")));
+    }
+
     #[test]
     fn apply_rule_no_match() {
         let rule = Rule {
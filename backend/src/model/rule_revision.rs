@@ -0,0 +1,135 @@
+use bson::oid::ObjectId;
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::rule::Rule;
+
+// Fields which change on every revision and so aren't interesting in a diff
+const IGNORED_FIELDS: &[&str] = &["_id", "revision"];
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum RuleChange {
+    Created,
+    Updated,
+    Deleted,
+    Restored,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldChange {
+    pub field: String,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleRevision {
+    pub rule_id: ObjectId,
+    pub revision: u32,
+    pub change: RuleChange,
+    pub author: Option<String>,
+    pub timestamp: NaiveDateTime,
+    /// The rule as it was after this change. For deletions, the rule which was deleted
+    pub rule: Rule,
+    pub diff: Vec<FieldChange>,
+}
+
+impl RuleRevision {
+    pub fn new(
+        rule: &Rule,
+        previous: Option<&Rule>,
+        change: RuleChange,
+        author: Option<&str>,
+    ) -> Self {
+        let diff = if change == RuleChange::Deleted {
+            diff(previous, None)
+        } else {
+            diff(previous, Some(rule))
+        };
+        Self {
+            rule_id: rule.id.unwrap_or_default(),
+            revision: rule.revision,
+            change,
+            author: author.map(str::to_string),
+            timestamp: Utc::now().naive_utc(),
+            rule: rule.clone(),
+            diff,
+        }
+    }
+}
+
+fn diff(before: Option<&Rule>, after: Option<&Rule>) -> Vec<FieldChange> {
+    let to_map = |rule: Option<&Rule>| match rule.map(serde_json::to_value) {
+        Some(Ok(Value::Object(map))) => map,
+        _ => serde_json::Map::new(),
+    };
+    let before = to_map(before);
+    let after = to_map(after);
+
+    let mut fields: Vec<&String> = before.keys().chain(after.keys()).collect();
+    fields.sort();
+    fields.dedup();
+    fields
+        .into_iter()
+        .filter(|f| !IGNORED_FIELDS.contains(&f.as_str()))
+        .filter(|f| before.get(*f) != after.get(*f))
+        .map(|f| FieldChange {
+            field: f.clone(),
+            before: before.get(f).cloned(),
+            after: after.get(f).cloned(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+    use serde_json::json;
+
+    use super::{RuleChange, RuleRevision};
+    use crate::model::rule::Rule;
+
+    #[test]
+    fn diff_updated_fields() {
+        let before = Rule {
+            rule_name: "Amazon".to_string(),
+            match_field_regex: Regex::new("amazon").unwrap(),
+            revision: 1,
+            ..Rule::default()
+        };
+        let after = Rule {
+            rule_name: "Amazon".to_string(),
+            match_field_regex: Regex::new("(?i)amazon").unwrap(),
+            priority: 3,
+            revision: 2,
+            ..Rule::default()
+        };
+        let revision = RuleRevision::new(&after, Some(&before), RuleChange::Updated, Some("sam"));
+        assert_eq!(revision.revision, 2);
+        assert_eq!(revision.author.as_deref(), Some("sam"));
+        assert_eq!(revision.diff.len(), 2);
+        assert_eq!(revision.diff[0].field, "matchFieldRegex");
+        assert_eq!(revision.diff[0].before, Some(json!("amazon")));
+        assert_eq!(revision.diff[0].after, Some(json!("(?i)amazon")));
+        assert_eq!(revision.diff[1].field, "priority");
+    }
+
+    #[test]
+    fn diff_deleted() {
+        let rule = Rule {
+            rule_name: "Amazon".to_string(),
+            ..Rule::default()
+        };
+        let revision = RuleRevision::new(&rule, Some(&rule), RuleChange::Deleted, None);
+        let name = revision
+            .diff
+            .iter()
+            .find(|c| c.field == "ruleName")
+            .unwrap();
+        assert_eq!(name.before, Some(json!("Amazon")));
+        assert_eq!(name.after, None);
+    }
+}
@@ -53,6 +53,10 @@ impl RealTransaction for SaltEdgeTransaction {
     fn get_default_currency_field_name(&self) -> &str {
         "currency_code"
     }
+
+    fn get_payee_field_name(&self) -> Option<&str> {
+        Some("description")
+    }
 }
 
 #[cfg(test)]
@@ -46,6 +46,14 @@ impl RealTransaction for SourceTransaction {
             SourceTransaction::Ib(t) => t.get_default_currency_field_name(),
         }
     }
+
+    fn get_payee_field_name(&self) -> Option<&str> {
+        match self {
+            SourceTransaction::N26(t) => t.get_payee_field_name(),
+            SourceTransaction::SaltEdge(t) => t.get_payee_field_name(),
+            SourceTransaction::Ib(t) => t.get_payee_field_name(),
+        }
+    }
 }
 
 #[derive(Deserialize)]
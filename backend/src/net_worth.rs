@@ -0,0 +1,438 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+};
+
+use chrono::NaiveDate;
+use log::{info, warn};
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    budgets, config, file_utils,
+    model::{aligned_data::AlignedData, hledger_transaction::HledgerTransaction},
+    prices::PriceTable,
+};
+
+const OTHER: &str = "other";
+
+/// What an account holds and where, from the tags of its account directive or accounts.yml.
+/// Subaccounts inherit every field their own metadata leaves out.
+#[derive(Debug, Deserialize, Default, Clone, PartialEq)]
+pub struct AccountMetadata {
+    pub institution: Option<String>,
+    pub asset_class: Option<String>,
+    pub liquidity: Option<String>,
+}
+
+impl AccountMetadata {
+    fn merge(&mut self, other: AccountMetadata) {
+        self.institution = other.institution.or_else(|| self.institution.take());
+        self.asset_class = other.asset_class.or_else(|| self.asset_class.take());
+        self.liquidity = other.liquidity.or_else(|| self.liquidity.take());
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Dimension {
+    Institution,
+    AssetClass,
+    Liquidity,
+}
+
+impl Default for Dimension {
+    fn default() -> Self {
+        Dimension::AssetClass
+    }
+}
+
+/// The months of a net worth breakdown and what to split it by
+#[derive(Debug, Clone, Copy)]
+pub struct BreakdownOptions {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub dimension: Dimension,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetWorthBreakdown {
+    /// The series of `data` after the dates, e.g. the institutions
+    pub groups: Vec<String>,
+    pub data: AlignedData,
+    /// Commodities left out as there's no price for them
+    pub unvalued: Vec<String>,
+}
+
+/// Metadata of the account directives in the journal, overridden by accounts.yml, e.g.
+///
+/// ```yaml
+/// assets:ib:
+///   institution: Interactive Brokers
+///   asset_class: securities
+///   liquidity: liquid
+/// ```
+pub fn read_account_metadata() -> HashMap<String, AccountMetadata> {
+    if config::journal_path().is_none() {
+        return HashMap::new();
+    }
+    let mut metadata = HashMap::new();
+    read_journal_directives(&file_utils::get_default_ledger_file(), &mut metadata);
+    let path = match file_utils::get_account_metadata_file() {
+        Some(path) if path.exists() => path,
+        _ => return metadata,
+    };
+    let overrides: HashMap<String, AccountMetadata> = match fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|yaml| serde_yaml::from_str(&yaml).map_err(|e| e.to_string()))
+    {
+        Ok(overrides) => overrides,
+        Err(e) => {
+            warn!("Couldn't read {}: {}", path.to_string_lossy(), e);
+            return metadata;
+        }
+    };
+    info!("Read account metadata from {}", path.to_string_lossy());
+    for (account, m) in overrides {
+        metadata.entry(account).or_default().merge(m);
+    }
+    metadata
+}
+
+// Follows `include` directives, but not globs
+fn read_journal_directives(path: &Path, metadata: &mut HashMap<String, AccountMetadata>) {
+    let journal = match fs::read_to_string(path) {
+        Ok(journal) => journal,
+        Err(e) => {
+            warn!("Couldn't read {}: {}", path.to_string_lossy(), e);
+            return;
+        }
+    };
+    for (account, m) in parse_account_directives(&journal) {
+        metadata.entry(account).or_default().merge(m);
+    }
+    for line in journal.lines() {
+        if let Some(include) = line.strip_prefix("include ") {
+            let include = include.split(';').next().unwrap_or_default().trim();
+            if !include.contains('*') {
+                let dir = path.parent().unwrap_or_else(|| Path::new("."));
+                read_journal_directives(&dir.join(include), metadata);
+            }
+        }
+    }
+}
+
+/// `account assets:ing  ; institution: ING, asset_class: cash, liquidity: liquid`. Other
+/// tags, like `type:`, are ignored.
+pub fn parse_account_directives(journal: &str) -> Vec<(String, AccountMetadata)> {
+    journal
+        .lines()
+        .filter_map(|line| {
+            let directive = line.strip_prefix("account ")?;
+            let (account, comment) = match directive.split_once(';') {
+                Some((account, comment)) => (account, comment),
+                None => (directive, ""),
+            };
+            // hledger ends the account name at two spaces
+            let account = account.trim().split("  ").next().unwrap_or_default();
+            let mut metadata = AccountMetadata::default();
+            for tag in comment.split(',') {
+                let (name, value) = match tag.split_once(':') {
+                    Some((name, value)) if !value.trim().is_empty() => {
+                        (name.trim(), Some(value.trim().to_string()))
+                    }
+                    _ => continue,
+                };
+                match name {
+                    "institution" => metadata.institution = value,
+                    "asset_class" => metadata.asset_class = value,
+                    "liquidity" => metadata.liquidity = value,
+                    _ => {}
+                }
+            }
+            Some((account.to_string(), metadata))
+        })
+        .collect()
+}
+
+/// Metadata of the account with the fields it leaves out taken from its parents
+fn account_metadata(metadata: &HashMap<String, AccountMetadata>, account: &str) -> AccountMetadata {
+    let parts: Vec<&str> = account.split(':').collect();
+    let mut result = AccountMetadata::default();
+    for depth in 1..=parts.len() {
+        if let Some(m) = metadata.get(&parts[..depth].join(":")) {
+            result.merge(m.clone());
+        }
+    }
+    result
+}
+
+/// Group of a balance without metadata: the institution defaults to the second level of the
+/// account, e.g. `ib` for `assets:ib:cash`, and the asset class to cash or securities by the
+/// commodity
+fn group(
+    metadata: &AccountMetadata,
+    dimension: Dimension,
+    account: &str,
+    is_currency: bool,
+) -> String {
+    let fallback = || match dimension {
+        Dimension::Institution => account.split(':').nth(1).unwrap_or(OTHER).to_string(),
+        Dimension::AssetClass if is_liability(account) => "liabilities".to_string(),
+        Dimension::AssetClass if is_currency => "cash".to_string(),
+        Dimension::AssetClass => "securities".to_string(),
+        Dimension::Liquidity => OTHER.to_string(),
+    };
+    match dimension {
+        Dimension::Institution => metadata.institution.clone(),
+        Dimension::AssetClass => metadata.asset_class.clone(),
+        Dimension::Liquidity => metadata.liquidity.clone(),
+    }
+    .unwrap_or_else(fallback)
+}
+
+pub fn is_asset_or_liability(account: &str) -> bool {
+    let top = account.split(':').next().unwrap_or_default().to_lowercase();
+    top == "assets" || top == "liabilities"
+}
+
+fn is_liability(account: &str) -> bool {
+    account.to_lowercase().starts_with("liabilities")
+}
+
+/// An asset or liability balance valued in the base currency
+#[derive(Debug)]
+pub struct ValuedBalance<'a> {
+    pub account: &'a str,
+    pub commodity: &'a str,
+    pub value: Decimal,
+}
+
+/// Balances of the asset and liability accounts in every commodity, like the IB positions, on
+/// every date. They are valued in the base currency with the latest price on the day. The
+/// commodities without a price are left out and returned separately.
+pub fn value_balances<'a>(
+    transactions: &'a [HledgerTransaction],
+    prices: &PriceTable,
+    base_currency: &str,
+    dates: &[NaiveDate],
+) -> (Vec<Vec<ValuedBalance<'a>>>, Vec<String>) {
+    let mut postings: Vec<(&str, NaiveDate, Decimal, &str)> = transactions
+        .iter()
+        .flat_map(|t| t.get_posting_amounts())
+        .filter(|(account, ..)| is_asset_or_liability(account))
+        .collect();
+    postings.sort_by_key(|&(_, date, ..)| date);
+
+    let mut balances = BTreeMap::<(&str, &str), Decimal>::new();
+    let mut valued = vec![];
+    let mut unvalued = vec![];
+    let mut postings = postings.into_iter().peekable();
+    for &date in dates {
+        while let Some((account, _, amount, commodity)) =
+            postings.next_if(|&(_, posting_date, ..)| posting_date <= date)
+        {
+            *balances.entry((account, commodity)).or_default() += amount;
+        }
+        let mut on_date = vec![];
+        for (&(account, commodity), &amount) in &balances {
+            if amount.is_zero() {
+                continue;
+            }
+            match prices.convert(amount, commodity, base_currency, date) {
+                Some(value) => on_date.push(ValuedBalance {
+                    account,
+                    commodity,
+                    value,
+                }),
+                None if !unvalued.iter().any(|c| c == commodity) => {
+                    unvalued.push(commodity.to_string())
+                }
+                None => {}
+            }
+        }
+        valued.push(on_date);
+    }
+    unvalued.sort();
+    (valued, unvalued)
+}
+
+/// Net worth at the end of every month of `options` split by its dimension
+pub fn net_worth_breakdown(
+    transactions: &[HledgerTransaction],
+    metadata: &HashMap<String, AccountMetadata>,
+    prices: &PriceTable,
+    base_currency: &str,
+    is_currency: impl Fn(&str) -> bool,
+    options: &BreakdownOptions,
+) -> NetWorthBreakdown {
+    let dates = month_ends(options.from, options.to);
+    let (valued, unvalued) = value_balances(transactions, prices, base_currency, &dates);
+    let mut groups = BTreeMap::<String, Vec<Decimal>>::new();
+    for (i, balances) in valued.iter().enumerate() {
+        for balance in balances {
+            let name = group(
+                &account_metadata(metadata, balance.account),
+                options.dimension,
+                balance.account,
+                is_currency(balance.commodity),
+            );
+            groups
+                .entry(name)
+                .or_insert_with(|| vec![Decimal::ZERO; dates.len()])[i] += balance.value;
+        }
+    }
+
+    NetWorthBreakdown {
+        data: AlignedData {
+            x_values: timestamps(&dates),
+            y_values: groups.values().map(|values| numbers(values)).collect(),
+        },
+        groups: groups.into_keys().collect(),
+        unvalued,
+    }
+}
+
+/// Last days of every month from `from` to `to`, ending with `to` itself
+pub fn month_ends(from: NaiveDate, to: NaiveDate) -> Vec<NaiveDate> {
+    budgets::months(from, to)
+        .iter()
+        .skip(1)
+        .map(|m| m.pred())
+        .chain(std::iter::once(to))
+        .collect()
+}
+
+pub fn timestamps(dates: &[NaiveDate]) -> Vec<serde_json::Number> {
+    dates
+        .iter()
+        .map(|d| d.and_hms(0, 0, 0).timestamp().into())
+        .collect()
+}
+
+pub fn numbers(values: &[Decimal]) -> Vec<serde_json::Number> {
+    values
+        .iter()
+        .map(|v| serde_json::Number::from_f64(v.round_dp(2).to_f64().unwrap()).unwrap())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+
+    use super::{
+        net_worth_breakdown, parse_account_directives, AccountMetadata, BreakdownOptions, Dimension,
+    };
+    use crate::{
+        model::hledger_transaction::HledgerTransaction,
+        prices::{Price, PriceTable},
+        test_statics::transaction,
+    };
+
+    const JOURNAL: &str = "account assets:ib  ; institution: Interactive Brokers, liquidity: liquid
+account assets:ib:cash  ; asset_class: cash
+account assets:house    ; type: A, asset_class: real estate, liquidity: illiquid
+account expenses
+";
+
+    /// A transaction on the 10th of `month`
+    fn monthly(month: u32, postings: &[(&str, &str, i64)]) -> HledgerTransaction {
+        transaction(NaiveDate::from_ymd(2021, month, 10), postings)
+    }
+
+    #[test]
+    fn account_directives() {
+        let metadata = parse_account_directives(JOURNAL);
+        assert_eq!(metadata.len(), 4);
+        assert_eq!(
+            metadata[0],
+            (
+                "assets:ib".to_string(),
+                AccountMetadata {
+                    institution: Some("Interactive Brokers".to_string()),
+                    asset_class: None,
+                    liquidity: Some("liquid".to_string()),
+                }
+            )
+        );
+        assert_eq!(metadata[2].1.asset_class.as_deref(), Some("real estate"));
+        assert_eq!(metadata[3].1, AccountMetadata::default());
+    }
+
+    #[test]
+    fn breakdown() {
+        let metadata: HashMap<String, AccountMetadata> =
+            parse_account_directives(JOURNAL).into_iter().collect();
+        let transactions = vec![
+            monthly(
+                1,
+                &[("assets:ib:cash", "EUR", 1000), ("income", "EUR", -1000)],
+            ),
+            monthly(
+                2,
+                &[
+                    ("assets:ib:cash", "EUR", -500),
+                    ("assets:ib:emim", "EMIM", 20),
+                ],
+            ),
+            monthly(2, &[("assets:n26", "USD", 100), ("income", "USD", -100)]),
+        ];
+        let prices = PriceTable::new(vec![
+            Price {
+                date: NaiveDate::from_ymd(2021, 2, 10),
+                from_commodity: "EMIM".to_string(),
+                to_commodity: "EUR".to_string(),
+                amount: Decimal::from(25),
+            },
+            Price {
+                date: NaiveDate::from_ymd(2021, 3, 1),
+                from_commodity: "EMIM".to_string(),
+                to_commodity: "EUR".to_string(),
+                amount: Decimal::from(30),
+            },
+        ]);
+        let from = NaiveDate::from_ymd(2021, 1, 1);
+        let to = NaiveDate::from_ymd(2021, 3, 15);
+        let is_currency = |c: &str| c == "EUR" || c == "USD";
+
+        let report = net_worth_breakdown(
+            &transactions,
+            &metadata,
+            &prices,
+            "EUR",
+            is_currency,
+            &BreakdownOptions {
+                from,
+                to,
+                dimension: Dimension::AssetClass,
+            },
+        );
+        assert_eq!(report.groups, vec!["cash", "securities"]);
+        // Without a price the USD balance is left out
+        assert_eq!(report.unvalued, vec!["USD"]);
+        assert_eq!(
+            serde_json::to_string(&report.data).unwrap(),
+            "[[1612051200,1614470400,1615766400],[1000.0,500.0,500.0],[0.0,500.0,600.0]]"
+        );
+
+        let report = net_worth_breakdown(
+            &transactions,
+            &metadata,
+            &prices,
+            "EUR",
+            is_currency,
+            &BreakdownOptions {
+                from,
+                to,
+                dimension: Dimension::Institution,
+            },
+        );
+        assert_eq!(report.groups, vec!["Interactive Brokers"]);
+    }
+}
@@ -0,0 +1,550 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use chrono::NaiveDate;
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    model::hledger_transaction::HledgerTransaction,
+    prices::{Price, PriceTable},
+};
+
+const MAX_XIRR_ITERATIONS: usize = 100;
+const XIRR_PRECISION: f64 = 1e-9;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CostMethod {
+    /// Sales use up the oldest lots first
+    Fifo,
+    /// Every unit costs the average of all purchases
+    Average,
+}
+
+impl Default for CostMethod {
+    fn default() -> Self {
+        CostMethod::Fifo
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventKind {
+    /// Negative quantities are sales. The price is per unit.
+    Trade {
+        quantity: Decimal,
+        price: Decimal,
+    },
+    Dividend,
+    Fee,
+}
+
+/// Something that happened to a holding, with amounts in the base currency
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub date: NaiveDate,
+    /// None for dividends and fees which can't be matched to a symbol
+    pub symbol: Option<String>,
+    pub kind: EventKind,
+    /// Cash received for dividends, paid for fees
+    pub amount: Decimal,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Performance {
+    pub cost_basis: Decimal,
+    pub market_value: Decimal,
+    pub unrealised_gain: Decimal,
+    pub realised_gain: Decimal,
+    pub dividends: Decimal,
+    pub fees: Decimal,
+    /// Return independent of when money was added or taken out, over the whole period
+    pub time_weighted_return: Option<f64>,
+    /// Annualised internal rate of return (XIRR) of the cash flows
+    pub money_weighted_return: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Holding {
+    pub symbol: String,
+    pub quantity: Decimal,
+    pub market_price: Option<Decimal>,
+    #[serde(flatten)]
+    pub performance: Performance,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PortfolioReport {
+    pub commodity: String,
+    pub holdings: Vec<Holding>,
+    pub total: Performance,
+}
+
+/// Add the prices of the trades in the journal to the price table, so holdings without a
+/// market price are valued at the last trade
+pub fn add_trade_prices(
+    transactions: &[HledgerTransaction],
+    prices: &mut PriceTable,
+    base_currency: &str,
+    is_currency: impl Fn(&str) -> bool,
+) {
+    for transaction in transactions {
+        let date = transaction.get_date(None);
+        for (_, symbol, price, price_commodity) in transaction.get_priced_amounts() {
+            if !is_currency(symbol) {
+                add_trade_price(prices, date, symbol, price, price_commodity, base_currency);
+            }
+        }
+    }
+}
+
+/// The trade price in the base currency, which is also added to the price table
+fn add_trade_price(
+    prices: &mut PriceTable,
+    date: NaiveDate,
+    symbol: &str,
+    price: Decimal,
+    price_commodity: &str,
+    base_currency: &str,
+) -> Option<Decimal> {
+    let price = prices.convert(price, price_commodity, base_currency, date)?;
+    prices.add(Price {
+        date,
+        from_commodity: symbol.to_string(),
+        to_commodity: base_currency.to_string(),
+        amount: price,
+    });
+    Some(price)
+}
+
+/// Trades, dividends and fees from the journal transactions of the investment account.
+/// Trades are priced postings in a commodity which isn't a currency. Dividends are postings
+/// to a dividend income account and are matched to a symbol mentioned in the description.
+/// Trade prices are added to the price table, so holdings without a market price are valued
+/// at the last trade.
+pub fn get_events(
+    transactions: &[HledgerTransaction],
+    prices: &mut PriceTable,
+    base_currency: &str,
+    is_currency: impl Fn(&str) -> bool,
+) -> Vec<Event> {
+    let mut events = vec![];
+    let mut dividends = vec![];
+    for transaction in transactions {
+        let date = transaction.get_date(None);
+        let mut trade_symbol = None;
+        for (quantity, symbol, price, price_commodity) in transaction.get_priced_amounts() {
+            if is_currency(symbol) {
+                continue;
+            }
+            let price = match add_trade_price(
+                prices,
+                date,
+                symbol,
+                price,
+                price_commodity,
+                base_currency,
+            ) {
+                Some(price) => price,
+                None => continue,
+            };
+            trade_symbol = Some(symbol.to_string());
+            events.push(Event {
+                date,
+                symbol: trade_symbol.clone(),
+                kind: EventKind::Trade { quantity, price },
+                amount: Decimal::ZERO,
+            });
+        }
+        for (account, date, amount, commodity) in transaction.get_posting_amounts() {
+            let amount = match prices.convert(amount, commodity, base_currency, date) {
+                Some(amount) => amount,
+                None => continue,
+            };
+            if has_word(account, &["dividend", "dividends"]) && amount.is_sign_negative() {
+                dividends.push((transaction.tdescription.as_str(), date, -amount));
+            } else if has_word(account, &["fee", "fees", "commission", "commissions"])
+                && amount.is_sign_positive()
+            {
+                events.push(Event {
+                    date,
+                    symbol: trade_symbol.clone(),
+                    kind: EventKind::Fee,
+                    amount,
+                });
+            }
+        }
+    }
+
+    let symbols: Vec<String> = events.iter().filter_map(|e| e.symbol.clone()).collect();
+    for (description, date, amount) in dividends {
+        events.push(Event {
+            date,
+            symbol: symbols
+                .iter()
+                .find(|s| mentions_symbol(description, s))
+                .cloned(),
+            kind: EventKind::Dividend,
+            amount,
+        });
+    }
+    events.sort_by_key(|e| e.date);
+    events
+}
+
+// Whole words of the account segments, so `Expenses:Bank Fees` is a fee and
+// `Expenses:Coffee` isn't
+fn has_word(account: &str, words: &[&str]) -> bool {
+    account
+        .split(|c: char| c == ':' || c.is_whitespace())
+        .any(|word| words.contains(&word.to_lowercase().as_str()))
+}
+
+// IB descriptions look like `EMIM(IE00BKM4GZ66) Cash Dividend USD 0.12 per Share`
+fn mentions_symbol(description: &str, symbol: &str) -> bool {
+    description
+        .split(|c: char| !c.is_alphanumeric() && c != '.')
+        .any(|word| word == symbol)
+}
+
+/// Performance of every symbol and of the whole portfolio up to `today`
+pub fn portfolio_report(
+    events: &[Event],
+    prices: &PriceTable,
+    base_currency: &str,
+    method: CostMethod,
+    today: NaiveDate,
+) -> PortfolioReport {
+    let mut by_symbol = BTreeMap::<&str, Vec<&Event>>::new();
+    for event in events {
+        if let Some(symbol) = &event.symbol {
+            by_symbol.entry(symbol).or_default().push(event);
+        }
+    }
+
+    let price = |symbol: &str, date| prices.rate(symbol, base_currency, date);
+    let mut holdings = vec![];
+    let mut total = Performance::default();
+    for (&symbol, events) in &by_symbol {
+        let (quantity, mut performance) = symbol_performance(events, method);
+        let market_price = price(symbol, today);
+        performance.market_value = quantity * market_price.unwrap_or_default();
+        performance.unrealised_gain = performance.market_value - performance.cost_basis;
+        let value_at = |date| held(events, date) * price(symbol, date).unwrap_or_default();
+        let flows = invested(events);
+        performance.time_weighted_return = time_weighted_return(&flows, value_at, today);
+        performance.money_weighted_return = xirr(&flows, performance.market_value, today);
+
+        total.cost_basis += performance.cost_basis;
+        total.market_value += performance.market_value;
+        total.realised_gain += performance.realised_gain;
+        total.dividends += performance.dividends;
+        total.fees += performance.fees;
+        holdings.push(Holding {
+            symbol: symbol.to_string(),
+            quantity,
+            market_price,
+            performance,
+        });
+    }
+
+    // Dividends and fees which don't belong to a symbol only count towards the total
+    for event in events.iter().filter(|e| e.symbol.is_none()) {
+        match event.kind {
+            EventKind::Dividend => total.dividends += event.amount,
+            EventKind::Fee => total.fees += event.amount,
+            EventKind::Trade { .. } => {}
+        }
+    }
+    total.unrealised_gain = total.market_value - total.cost_basis;
+    let all: Vec<&Event> = events.iter().collect();
+    let flows = invested(&all);
+    let value_at = |date| {
+        by_symbol
+            .iter()
+            .map(|(symbol, events)| held(events, date) * price(symbol, date).unwrap_or_default())
+            .sum()
+    };
+    total.time_weighted_return = time_weighted_return(&flows, value_at, today);
+    total.money_weighted_return = xirr(&flows, total.market_value, today);
+
+    PortfolioReport {
+        commodity: base_currency.to_string(),
+        holdings,
+        total,
+    }
+}
+
+/// Remaining quantity and the cost basis, realised gains, dividends and fees of one symbol.
+/// Fees aren't part of the cost basis.
+fn symbol_performance(events: &[&Event], method: CostMethod) -> (Decimal, Performance) {
+    let mut performance = Performance::default();
+    // Quantity and price per unit
+    let mut lots = VecDeque::<(Decimal, Decimal)>::new();
+    for event in events {
+        match event.kind {
+            EventKind::Trade { quantity, price } if quantity.is_sign_positive() => {
+                lots.push_back((quantity, price));
+            }
+            EventKind::Trade { quantity, price } => {
+                let sold = -quantity;
+                let cost = match method {
+                    CostMethod::Fifo => take_fifo(&mut lots, sold),
+                    CostMethod::Average => take_average(&mut lots, sold),
+                };
+                performance.realised_gain += sold * price - cost;
+            }
+            EventKind::Dividend => performance.dividends += event.amount,
+            EventKind::Fee => performance.fees += event.amount,
+        }
+    }
+    let quantity = lots.iter().map(|(q, _)| q).sum();
+    performance.cost_basis = lots.iter().map(|(q, p)| q * p).sum();
+    (quantity, performance)
+}
+
+/// Quantity held at the end of the date
+fn held(events: &[&Event], date: NaiveDate) -> Decimal {
+    events
+        .iter()
+        .filter(|e| e.date <= date)
+        .filter_map(|e| match e.kind {
+            EventKind::Trade { quantity, .. } => Some(quantity),
+            _ => None,
+        })
+        .sum()
+}
+
+/// Cost of the sold units, taken from the oldest lots
+fn take_fifo(lots: &mut VecDeque<(Decimal, Decimal)>, mut sold: Decimal) -> Decimal {
+    let mut cost = Decimal::ZERO;
+    while sold > Decimal::ZERO {
+        let (quantity, price) = match lots.front_mut() {
+            Some(lot) => lot,
+            None => break,
+        };
+        let taken = sold.min(*quantity);
+        cost += taken * *price;
+        *quantity -= taken;
+        sold -= taken;
+        if quantity.is_zero() {
+            lots.pop_front();
+        }
+    }
+    cost
+}
+
+/// Cost of the sold units at the average price, leaving a single lot
+fn take_average(lots: &mut VecDeque<(Decimal, Decimal)>, sold: Decimal) -> Decimal {
+    let quantity: Decimal = lots.iter().map(|(q, _)| q).sum();
+    if quantity.is_zero() {
+        return Decimal::ZERO;
+    }
+    let price = lots.iter().map(|(q, p)| q * p).sum::<Decimal>() / quantity;
+    let sold = sold.min(quantity);
+    lots.clear();
+    if quantity > sold {
+        lots.push_back((quantity - sold, price));
+    }
+    sold * price
+}
+
+/// Money put into the holdings on every date. Sales and dividends take money out.
+fn invested(events: &[&Event]) -> Vec<(NaiveDate, f64)> {
+    let mut flows = BTreeMap::<NaiveDate, Decimal>::new();
+    for event in events {
+        let amount = match event.kind {
+            EventKind::Trade { quantity, price } => quantity * price,
+            EventKind::Dividend => -event.amount,
+            EventKind::Fee => event.amount,
+        };
+        *flows.entry(event.date).or_default() += amount;
+    }
+    flows
+        .into_iter()
+        .map(|(date, amount)| (date, amount.to_f64().unwrap_or_default()))
+        .collect()
+}
+
+/// Chain the returns between the dates money was added or taken out
+fn time_weighted_return(
+    flows: &[(NaiveDate, f64)],
+    value_at: impl Fn(NaiveDate) -> Decimal,
+    end: NaiveDate,
+) -> Option<f64> {
+    let value_at = |date| value_at(date).to_f64().unwrap_or_default();
+    let mut growth = 1.0;
+    let mut measured = false;
+    let mut previous = 0.0;
+    for &(date, flow) in flows.iter().filter(|(date, _)| *date <= end) {
+        let value = value_at(date);
+        if previous > 0.0 {
+            growth *= (value - flow) / previous;
+            measured = true;
+        }
+        previous = value;
+    }
+    if previous > 0.0 {
+        growth *= value_at(end) / previous;
+        measured = true;
+    }
+    if measured {
+        Some(growth - 1.0)
+    } else {
+        None
+    }
+}
+
+/// Annual rate at which the invested money and the final value have the same present value
+fn xirr(invested: &[(NaiveDate, f64)], final_value: Decimal, end: NaiveDate) -> Option<f64> {
+    let start = invested.first()?.0;
+    let mut flows: Vec<(f64, f64)> = invested
+        .iter()
+        .map(|&(date, amount)| (date, -amount))
+        .chain(std::iter::once((end, final_value.to_f64()?)))
+        .map(|(date, amount)| ((date - start).num_days() as f64 / 365.0, amount))
+        .collect();
+    flows.retain(|(_, amount)| *amount != 0.0);
+    if !flows.iter().any(|(_, a)| *a > 0.0) || !flows.iter().any(|(_, a)| *a < 0.0) {
+        return None;
+    }
+    let npv = |rate: f64| -> f64 {
+        flows
+            .iter()
+            .map(|(years, amount)| amount / (1.0 + rate).powf(*years))
+            .sum()
+    };
+    // Bisection, since the net present value falls as the rate rises
+    let (mut low, mut high) = (-0.9999, 10.0);
+    if npv(low).signum() == npv(high).signum() {
+        return None;
+    }
+    for _ in 0..MAX_XIRR_ITERATIONS {
+        let mid = (low + high) / 2.0;
+        if npv(mid) > 0.0 {
+            low = mid;
+        } else {
+            high = mid;
+        }
+        if high - low < XIRR_PRECISION {
+            break;
+        }
+    }
+    Some((low + high) / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+
+    use super::{has_word, portfolio_report, xirr, CostMethod, Event, EventKind};
+    use crate::prices::{Price, PriceTable};
+
+    fn date(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd(2021, month, day)
+    }
+
+    fn trade(month: u32, quantity: i64, price: i64) -> Event {
+        Event {
+            date: date(month, 1),
+            symbol: Some("EMIM".to_string()),
+            kind: EventKind::Trade {
+                quantity: Decimal::from(quantity),
+                price: Decimal::from(price),
+            },
+            amount: Decimal::ZERO,
+        }
+    }
+
+    fn prices(prices: &[(u32, i64)]) -> PriceTable {
+        PriceTable::new(prices.iter().map(|&(month, price)| Price {
+            date: date(month, 1),
+            from_commodity: "EMIM".to_string(),
+            to_commodity: "EUR".to_string(),
+            amount: Decimal::from(price),
+        }))
+    }
+
+    #[test]
+    fn fifo_and_average_cost() {
+        let events = vec![
+            trade(1, 10, 20),
+            trade(2, 10, 30),
+            trade(3, -5, 35),
+            Event {
+                date: date(4, 1),
+                symbol: Some("EMIM".to_string()),
+                kind: EventKind::Dividend,
+                amount: Decimal::from(3),
+            },
+            Event {
+                date: date(4, 1),
+                symbol: None,
+                kind: EventKind::Fee,
+                amount: Decimal::from(1),
+            },
+        ];
+        let today = date(6, 30);
+
+        let prices = prices(&[(6, 40)]);
+        let report = portfolio_report(&events, &prices, "EUR", CostMethod::Fifo, today);
+        let h = &report.holdings[0];
+        assert_eq!(h.quantity, Decimal::from(15));
+        assert_eq!(h.market_price, Some(Decimal::from(40)));
+        // 5 left at 20 and 10 at 30
+        assert_eq!(h.performance.cost_basis, Decimal::from(400));
+        assert_eq!(h.performance.realised_gain, Decimal::from(75));
+        assert_eq!(h.performance.unrealised_gain, Decimal::from(200));
+        assert_eq!(report.total.dividends, Decimal::from(3));
+        assert_eq!(report.total.fees, Decimal::from(1));
+
+        let report = portfolio_report(&events, &prices, "EUR", CostMethod::Average, today);
+        let h = &report.holdings[0];
+        assert_eq!(h.performance.cost_basis, Decimal::from(375));
+        assert_eq!(h.performance.realised_gain, Decimal::from(50));
+        assert!(report.total.money_weighted_return.unwrap() > 0.0);
+    }
+
+    #[test]
+    fn time_weighted_ignores_deposits() {
+        // Doubles in value, then the same amount is bought again at the new price
+        let events = vec![trade(1, 10, 10), trade(6, 10, 20)];
+        let prices = prices(&[(1, 10), (6, 20)]);
+        let report = portfolio_report(&events, &prices, "EUR", CostMethod::Fifo, date(12, 31));
+        let twr = report.total.time_weighted_return.unwrap();
+        assert!((twr - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn fee_and_dividend_accounts() {
+        let fees = ["fee", "fees", "commission", "commissions"];
+        assert!(has_word("Expenses:Fees:IB", &fees));
+        assert!(has_word("Expenses:Bank Fees", &fees));
+        assert!(!has_word("Expenses:Coffee", &fees));
+        assert!(has_word("Income:Dividends", &["dividend", "dividends"]));
+    }
+
+    #[test]
+    fn without_investments() {
+        assert!(xirr(&[], Decimal::ZERO, date(6, 1)).is_none());
+        let report = portfolio_report(
+            &[],
+            &PriceTable::default(),
+            "EUR",
+            CostMethod::Fifo,
+            date(6, 1),
+        );
+        assert!(report.holdings.is_empty());
+        assert_eq!(report.total.money_weighted_return, None);
+    }
+
+    #[test]
+    fn xirr_of_a_year() {
+        let end = NaiveDate::from_ymd(2022, 1, 1);
+        let rate = xirr(&[(date(1, 1), 100.0)], Decimal::from(110), end);
+        assert!((rate.unwrap() - 0.1).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,322 @@
+use std::{collections::HashMap, time::Duration};
+
+use actix::clock::sleep;
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use log::info;
+use rust_decimal::Decimal;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{CommodityKind, Error, Granularity, PriceSource, Result};
+use crate::{config, prices::Price};
+
+const BASE_URL: &str = "https://www.alphavantage.co/query";
+const DATE_FMT: &str = "%Y-%m-%d";
+const MAX_RETRIES: u32 = 3;
+const RETRY_DELAY: u64 = 10;
+// Stocks are looked up on XETRA, which quotes them in EUR
+const STOCK_EXCHANGE_SUFFIX: &str = ".DE";
+const STOCK_EXCHANGE_CURRENCY: &str = "EUR";
+
+/// Alpha Vantage answers with status 200 and one of these when something is wrong
+#[derive(Debug, Deserialize)]
+struct ErrorHelper {
+    /// E.g. for unknown symbols
+    #[serde(rename = "Error Message")]
+    error_message: Option<String>,
+    /// Too many requests
+    #[serde(rename = "Note", alias = "Information")]
+    note: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MatchesHelper {
+    #[serde(rename = "bestMatches")]
+    best_matches: Vec<SearchHelper>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchHelper {
+    #[serde(rename = "1. symbol")]
+    symbol: String,
+    #[serde(rename = "2. name")]
+    name: String,
+    #[serde(rename = "3. type")]
+    symbol_type: String,
+    #[serde(rename = "4. region")]
+    regiob: String,
+    #[serde(rename = "5. marketOpen")]
+    market_open: String,
+    #[serde(rename = "6. marketClose")]
+    market_close: String,
+    #[serde(rename = "7. timezone")]
+    timezone: String,
+    #[serde(rename = "8. currency")]
+    currency: String,
+    #[serde(rename = "9. matchScore")]
+    match_score: String,
+}
+
+/// The values of a day or week, e.g. `4. close`. Cryptocurrencies have their close in the
+/// market's currency as `4a. close (<market>)`.
+#[derive(Debug, Deserialize)]
+struct EntryHelper(HashMap<String, String>);
+
+impl EntryHelper {
+    fn close(&self, to_commodity: &str) -> Option<Decimal> {
+        self.0
+            .get(&format!("4a. close ({})", to_commodity))
+            .or_else(|| self.0.get("4. close"))?
+            .parse()
+            .ok()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TimeSeriesHelper {
+    #[serde(rename = "Error Message")]
+    error: Option<String>,
+    #[serde(rename = "Meta Data")]
+    metadata: Option<HashMap<String, String>>,
+    #[serde(
+        alias = "Weekly Time Series",
+        alias = "Time Series FX (Weekly)",
+        alias = "Time Series (Digital Currency Weekly)",
+        alias = "Time Series (Daily)",
+        alias = "Time Series FX (Daily)",
+        alias = "Time Series (Digital Currency Daily)"
+    )]
+    time_series: HashMap<String, EntryHelper>,
+}
+
+impl TimeSeriesHelper {
+    fn into_prices(self, from_commodity: &str, to_commodity: &str) -> Vec<Price> {
+        self.time_series
+            .into_iter()
+            .filter_map(|(date_str, entry)| {
+                Some(Price {
+                    date: NaiveDate::parse_from_str(&date_str, DATE_FMT).ok()?,
+                    from_commodity: from_commodity.to_string(),
+                    to_commodity: to_commodity.to_string(),
+                    amount: entry.close(to_commodity)?,
+                })
+            })
+            .collect()
+    }
+}
+
+pub struct AlphaVantage {
+    http_client: reqwest::Client,
+}
+
+impl AlphaVantage {
+    pub fn new() -> Self {
+        Self {
+            http_client: reqwest::Client::new(),
+        }
+    }
+
+    pub async fn fetch_stocks(
+        &self,
+        from_commodity: &str,
+        to_commodity: &str,
+        granularity: Granularity,
+    ) -> Result<Vec<Price>> {
+        // Leave other currencies to the next source instead of mislabelling EUR prices
+        if to_commodity != STOCK_EXCHANGE_CURRENCY {
+            return Err(Error::NotFound);
+        }
+        let request_symbol = format!("{}{}", from_commodity, STOCK_EXCHANGE_SUFFIX);
+
+        info!(
+            "Looking up {:?} prices for stock {}->{} using Alpha Vantage...",
+            granularity, request_symbol, to_commodity
+        );
+
+        let function = match granularity {
+            Granularity::Daily => "TIME_SERIES_DAILY",
+            Granularity::Weekly => "TIME_SERIES_WEEKLY",
+        };
+        let time_series: TimeSeriesHelper = self
+            .alpha_vantage_request(
+                function,
+                &[("symbol", request_symbol.as_str()), ("outputsize", "full")],
+            )
+            .await?;
+
+        Ok(time_series.into_prices(from_commodity, to_commodity))
+    }
+
+    pub async fn fetch_forex(
+        &self,
+        from_commodity: &str,
+        to_commodity: &str,
+        granularity: Granularity,
+    ) -> Result<Vec<Price>> {
+        info!(
+            "Looking up {:?} prices for forex {}->{} using Alpha Vantage...",
+            granularity, from_commodity, to_commodity
+        );
+
+        let function = match granularity {
+            Granularity::Daily => "FX_DAILY",
+            Granularity::Weekly => "FX_WEEKLY",
+        };
+        let time_series: TimeSeriesHelper = self
+            .alpha_vantage_request(
+                function,
+                &[
+                    ("from_symbol", from_commodity),
+                    ("to_symbol", to_commodity),
+                    ("outputsize", "full"),
+                ],
+            )
+            .await?;
+
+        Ok(time_series.into_prices(from_commodity, to_commodity))
+    }
+
+    pub async fn fetch_crypto(
+        &self,
+        from_commodity: &str,
+        to_commodity: &str,
+        granularity: Granularity,
+    ) -> Result<Vec<Price>> {
+        info!(
+            "Looking up {:?} prices for crypto {}->{} using Alpha Vantage...",
+            granularity, from_commodity, to_commodity
+        );
+
+        let function = match granularity {
+            Granularity::Daily => "DIGITAL_CURRENCY_DAILY",
+            Granularity::Weekly => "DIGITAL_CURRENCY_WEEKLY",
+        };
+        let time_series: TimeSeriesHelper = self
+            .alpha_vantage_request(
+                function,
+                &[("symbol", from_commodity), ("market", to_commodity)],
+            )
+            .await?;
+
+        Ok(time_series.into_prices(from_commodity, to_commodity))
+    }
+
+    async fn alpha_vantage_request<Q: Serialize + ?Sized, T: DeserializeOwned>(
+        &self,
+        function: &str,
+        query: &Q,
+    ) -> Result<T> {
+        let mut retries = MAX_RETRIES;
+        loop {
+            match self.alpha_vantage_request_internal(function, query).await {
+                Err(Error::Throttled) if retries > 0 => {
+                    info!(
+                        "Alpha Vantage request throttled. Waiting {} seconds...",
+                        RETRY_DELAY
+                    );
+                    retries -= 1;
+                    sleep(Duration::from_secs(RETRY_DELAY)).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn alpha_vantage_request_internal<Q: Serialize + ?Sized, T: DeserializeOwned>(
+        &self,
+        function: &str,
+        query: &Q,
+    ) -> Result<T> {
+        let api_key =
+            config::alpha_vantage_key().ok_or(Error::NotConfigured("ALPHA_VANTAGE_KEY"))?;
+        let response = self
+            .http_client
+            .get(BASE_URL)
+            .query(&[("function", function), ("apikey", &api_key)])
+            .query(query)
+            .send()
+            .await?;
+        response.error_for_status_ref()?;
+        let full = response.bytes().await?;
+        if let Ok(helper) = serde_json::from_slice::<ErrorHelper>(&full) {
+            if helper.error_message.is_some() {
+                return Err(Error::NotFound);
+            }
+            if helper.note.is_some() {
+                return Err(Error::Throttled);
+            }
+        }
+        serde_json::from_slice(&full).map_err(|e| Error::Parse(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl PriceSource for AlphaVantage {
+    fn name(&self) -> &str {
+        "alphavantage"
+    }
+
+    async fn fetch(
+        &self,
+        from: &str,
+        to: &str,
+        kind: CommodityKind,
+        granularity: Granularity,
+    ) -> Result<Vec<Price>> {
+        match kind {
+            CommodityKind::Currency => self.fetch_forex(from, to, granularity).await,
+            CommodityKind::Cryptocurrency => self.fetch_crypto(from, to, granularity).await,
+            CommodityKind::Security => self.fetch_stocks(from, to, granularity).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+
+    use super::{AlphaVantage, TimeSeriesHelper};
+    use crate::price_sources::{Error, Granularity};
+
+    #[test]
+    fn crypto_close_in_market_currency() {
+        let json = r#"{
+            "Meta Data": {"2. Digital Currency Code": "BTC", "4. Market Code": "USD"},
+            "Time Series (Digital Currency Weekly)": {
+                "2021-03-07": {
+                    "1a. open (USD)": "45000.00",
+                    "4a. close (USD)": "48000.50",
+                    "4b. close (USD)": "48000.50",
+                    "5. volume": "100.0"
+                }
+            }
+        }"#;
+        let time_series: TimeSeriesHelper = serde_json::from_str(json).unwrap();
+        let prices = time_series.into_prices("BTC", "USD");
+        assert_eq!(prices.len(), 1);
+        assert_eq!(prices[0].date, NaiveDate::from_ymd(2021, 3, 7));
+        assert_eq!(prices[0].to_commodity, "USD");
+        assert_eq!(prices[0].amount, Decimal::new(4800050, 2));
+    }
+
+    #[test]
+    fn stock_close() {
+        let json = r#"{
+            "Weekly Time Series": {
+                "2021-03-05": {"1. open": "60.00", "4. close": "61.25", "5. volume": "1000"}
+            }
+        }"#;
+        let time_series: TimeSeriesHelper = serde_json::from_str(json).unwrap();
+        let prices = time_series.into_prices("EMIM", "EUR");
+        assert_eq!(prices[0].amount, Decimal::new(6125, 2));
+    }
+
+    #[actix_rt::test]
+    async fn stocks_only_in_euros() {
+        let prices = AlphaVantage::new()
+            .fetch_stocks("EMIM", "USD", Granularity::Weekly)
+            .await;
+        assert!(matches!(prices, Err(Error::NotFound)));
+    }
+}
@@ -0,0 +1,148 @@
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use super::{CommodityKind, Error, Granularity, PriceSource, Result};
+use crate::prices::Price;
+
+const HISTORY_URL: &str = "https://www.ecb.europa.eu/stats/eurofxref/eurofxref-hist.xml";
+const DATE_FMT: &str = "%Y-%m-%d";
+const ECB_CURRENCY: &str = "EUR";
+
+#[derive(Debug, Deserialize)]
+struct Envelope {
+    #[serde(rename = "Cube")]
+    cube: Days,
+}
+
+#[derive(Debug, Deserialize)]
+struct Days {
+    #[serde(rename = "Cube", default)]
+    days: Vec<Day>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Day {
+    time: String,
+    #[serde(rename = "Cube", default)]
+    rates: Vec<Rate>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Rate {
+    currency: String,
+    /// Units of the currency per euro
+    rate: Decimal,
+}
+
+/// Daily euro foreign exchange reference rates of the European Central Bank. Pairs without
+/// the euro are crossed through it.
+pub struct Ecb {
+    http_client: reqwest::Client,
+}
+
+impl Ecb {
+    pub fn new() -> Self {
+        Self {
+            http_client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl PriceSource for Ecb {
+    fn name(&self) -> &str {
+        "ecb"
+    }
+
+    // Always daily, weekly prices are thinned out by PriceSources
+    async fn fetch(
+        &self,
+        from: &str,
+        to: &str,
+        kind: CommodityKind,
+        _granularity: Granularity,
+    ) -> Result<Vec<Price>> {
+        if kind != CommodityKind::Currency {
+            return Err(Error::NotFound);
+        }
+        let response = self.http_client.get(HISTORY_URL).send().await?;
+        response.error_for_status_ref()?;
+        let xml = response.text().await?;
+        parse_reference_rates(&xml, from, to)
+    }
+}
+
+fn parse_reference_rates(xml: &str, from: &str, to: &str) -> Result<Vec<Price>> {
+    let envelope: Envelope =
+        serde_xml_rs::from_str(xml).map_err(|e| Error::Parse(e.to_string()))?;
+    let per_euro = |day: &Day, currency: &str| {
+        if currency == ECB_CURRENCY {
+            return Some(Decimal::ONE);
+        }
+        day.rates
+            .iter()
+            .find(|r| r.currency == currency)
+            .map(|r| r.rate)
+            .filter(|r| !r.is_zero())
+    };
+    let prices: Vec<Price> = envelope
+        .cube
+        .days
+        .iter()
+        .filter_map(|day| {
+            Some(Price {
+                date: NaiveDate::parse_from_str(&day.time, DATE_FMT).ok()?,
+                from_commodity: from.to_string(),
+                to_commodity: to.to_string(),
+                amount: (per_euro(day, to)? / per_euro(day, from)?).round_dp(6),
+            })
+        })
+        .collect();
+    if prices.is_empty() {
+        return Err(Error::NotFound);
+    }
+    Ok(prices)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+
+    use super::parse_reference_rates;
+
+    const RATES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gesmes:Envelope xmlns:gesmes="http://www.gesmes.org/xml/2002-08-01" xmlns="http://www.ecb.int/vocabulary/2002-08-01/eurofxref">
+<gesmes:subject>Reference rates</gesmes:subject>
+<gesmes:Sender>
+<gesmes:name>European Central Bank</gesmes:name>
+</gesmes:Sender>
+<Cube>
+<Cube time="2021-04-30">
+<Cube currency="USD" rate="1.2"/>
+<Cube currency="GBP" rate="0.8"/>
+</Cube>
+<Cube time="2021-04-29">
+<Cube currency="USD" rate="1.25"/>
+</Cube>
+</Cube>
+</gesmes:Envelope>
+"#;
+
+    #[test]
+    fn reference_rates() {
+        let prices = parse_reference_rates(RATES, "USD", "EUR").unwrap();
+        assert_eq!(prices.len(), 2);
+        assert_eq!(prices[0].date, NaiveDate::from_ymd(2021, 4, 30));
+        assert_eq!(prices[1].amount, Decimal::new(8, 1));
+
+        // Crossed through the euro, on days with both rates
+        let prices = parse_reference_rates(RATES, "GBP", "USD").unwrap();
+        assert_eq!(prices.len(), 1);
+        assert_eq!(prices[0].amount, Decimal::new(15, 1));
+
+        assert!(parse_reference_rates(RATES, "CHF", "EUR").is_err());
+    }
+}
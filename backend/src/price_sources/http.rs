@@ -0,0 +1,53 @@
+use async_trait::async_trait;
+use reqwest::StatusCode;
+
+use super::{CommodityKind, Error, Granularity, PriceSource, Result};
+use crate::prices::Price;
+
+/// Any service answering `GET <url>?from=<commodity>&to=<commodity>` with a JSON list of
+/// prices, like a self-hosted price cache or a local stand-in in tests
+pub struct HttpSource {
+    url: String,
+    http_client: reqwest::Client,
+}
+
+impl HttpSource {
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            http_client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl PriceSource for HttpSource {
+    fn name(&self) -> &str {
+        "http"
+    }
+
+    async fn fetch(
+        &self,
+        from: &str,
+        to: &str,
+        _kind: CommodityKind,
+        _granularity: Granularity,
+    ) -> Result<Vec<Price>> {
+        let response = self
+            .http_client
+            .get(&self.url)
+            .query(&[("from", from), ("to", to)])
+            .send()
+            .await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => return Err(Error::NotFound),
+            StatusCode::TOO_MANY_REQUESTS => return Err(Error::Throttled),
+            _ => response.error_for_status_ref()?,
+        };
+        let prices: Vec<Price> = response.json().await?;
+        Ok(prices
+            .into_iter()
+            .filter(|p| p.from_commodity == from && p.to_commodity == to)
+            .collect())
+    }
+}
@@ -0,0 +1,366 @@
+mod alpha_vantage;
+mod ecb;
+mod file;
+mod http;
+
+use std::{collections::HashMap, fmt::Display};
+
+use async_trait::async_trait;
+use chrono::{Datelike, Duration, NaiveDate};
+use log::{info, warn};
+
+pub use self::{alpha_vantage::AlphaVantage, ecb::Ecb, file::FileSource, http::HttpSource};
+use crate::{config, prices::Price};
+
+const DEFAULT_SOURCES: &str = "alphavantage";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommodityKind {
+    Currency,
+    Cryptocurrency,
+    /// Stocks, ETFs and everything else
+    Security,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Granularity {
+    Daily,
+    /// One price per week, on the last day with a price
+    Weekly,
+}
+
+impl Granularity {
+    pub fn from_config() -> Self {
+        if config::daily_prices() {
+            Granularity::Daily
+        } else {
+            Granularity::Weekly
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The source doesn't have prices for the pair
+    NotFound,
+    /// Too many requests, even after waiting
+    Throttled,
+    /// An env var the source needs is missing
+    NotConfigured(&'static str),
+    Http(reqwest::Error),
+    Io(std::io::Error),
+    Parse(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NotFound => write!(f, "no prices found"),
+            Error::Throttled => write!(f, "throttled"),
+            Error::NotConfigured(var) => write!(f, "{} is not set", var),
+            Error::Http(e) => write!(f, "{}", e),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Parse(e) => write!(f, "couldn't parse prices: {}", e),
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[async_trait]
+pub trait PriceSource: Send + Sync {
+    /// Name used to configure the source order
+    fn name(&self) -> &str;
+
+    /// All known prices of `from` in `to`, at least as often as the granularity
+    async fn fetch(
+        &self,
+        from: &str,
+        to: &str,
+        kind: CommodityKind,
+        granularity: Granularity,
+    ) -> Result<Vec<Price>>;
+}
+
+/// Price sources tried in order until one has prices for the pair
+pub struct PriceSources {
+    sources: Vec<Box<dyn PriceSource>>,
+    default_order: Vec<String>,
+    by_commodity: HashMap<String, Vec<String>>,
+}
+
+impl PriceSources {
+    pub fn new(
+        sources: Vec<Box<dyn PriceSource>>,
+        default_order: Vec<String>,
+        by_commodity: HashMap<String, Vec<String>>,
+    ) -> Self {
+        Self {
+            sources,
+            default_order,
+            by_commodity,
+        }
+    }
+
+    /// Sources in the order of PRICE_SOURCES, overridden per commodity by
+    /// PRICE_SOURCES_BY_COMMODITY
+    pub fn from_config() -> Self {
+        let mut sources: Vec<Box<dyn PriceSource>> =
+            vec![Box::new(AlphaVantage::new()), Box::new(Ecb::new())];
+        if let Some(path) = config::prices_source_file() {
+            sources.push(Box::new(FileSource::new(path)));
+        }
+        if let Some(url) = config::prices_source_url() {
+            sources.push(Box::new(HttpSource::new(url)));
+        }
+        let default_order = config::price_sources().unwrap_or_else(|| DEFAULT_SOURCES.to_string());
+        Self::new(
+            sources,
+            parse_source_order(&default_order),
+            parse_commodity_sources(&config::price_sources_by_commodity().unwrap_or_default()),
+        )
+    }
+
+    /// Prices after `since` from the first source which has any. Sources which lack the pair,
+    /// are throttled or fail are skipped with a warning. Weekly prices cover the whole week of
+    /// `since`, so the price of a week that wasn't over yet gets replaced.
+    pub async fn fetch(
+        &self,
+        from: &str,
+        to: &str,
+        kind: CommodityKind,
+        granularity: Granularity,
+        since: Option<NaiveDate>,
+    ) -> Vec<Price> {
+        let since = match granularity {
+            Granularity::Daily => since,
+            Granularity::Weekly => since.map(|date| {
+                date - Duration::days(date.weekday().num_days_from_monday() as i64 + 1)
+            }),
+        };
+        let order = self.by_commodity.get(from).unwrap_or(&self.default_order);
+        for name in order {
+            let source = match self.sources.iter().find(|s| s.name() == name) {
+                Some(source) => source,
+                None => {
+                    warn!("Unknown price source {}", name);
+                    continue;
+                }
+            };
+            match source.fetch(from, to, kind, granularity).await {
+                Ok(prices) if !prices.is_empty() => {
+                    let mut prices: Vec<Price> = prices
+                        .into_iter()
+                        .filter(|p| Some(p.date) > since)
+                        .collect();
+                    if granularity == Granularity::Weekly {
+                        prices = last_of_week(prices);
+                    }
+                    info!(
+                        "Got {} prices for {}->{} from {}",
+                        prices.len(),
+                        from,
+                        to,
+                        name
+                    );
+                    return prices;
+                }
+                Ok(_) => warn!("{} has no prices for {}->{}", name, from, to),
+                Err(e) => warn!("{} couldn't get prices for {}->{}: {}", name, from, to, e),
+            }
+        }
+        warn!("No price source has prices for {}->{}", from, to);
+        vec![]
+    }
+}
+
+/// Only the last price of every week
+fn last_of_week(mut prices: Vec<Price>) -> Vec<Price> {
+    prices.sort_by_key(|p| p.date);
+    let mut weekly: Vec<Price> = vec![];
+    for price in prices {
+        match weekly.last_mut() {
+            Some(last) if last.date.iso_week() == price.date.iso_week() => *last = price,
+            _ => weekly.push(price),
+        }
+    }
+    weekly
+}
+
+/// Comma separated source names, e.g. `ecb,alphavantage`
+fn parse_source_order(order: &str) -> Vec<String> {
+    order
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Semicolon separated commodities with their sources, e.g. `BTC=file;EMIM=http,alphavantage`
+fn parse_commodity_sources(config: &str) -> HashMap<String, Vec<String>> {
+    config
+        .split(';')
+        .filter_map(|entry| entry.split_once('='))
+        .map(|(commodity, order)| (commodity.trim().to_string(), parse_source_order(order)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        fs,
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+
+    use super::{
+        parse_commodity_sources, CommodityKind, FileSource, Granularity, HttpSource, PriceSource,
+        PriceSources,
+    };
+
+    /// Answers every request with the response for the first matching `from` query parameter
+    fn serve(responses: &'static [(&'static str, &'static str, &'static str)]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/prices", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = [0; 1024];
+                let n = stream.read(&mut request).unwrap();
+                let request = String::from_utf8_lossy(&request[..n]);
+                let (status, body) = responses
+                    .iter()
+                    .find(|(from, _, _)| request.contains(&format!("from={}&", from)))
+                    .map(|&(_, status, body)| (status, body))
+                    .unwrap_or(("404 Not Found", ""));
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn commodity_sources() {
+        let sources = parse_commodity_sources("BTC=file; EMIM=http, alphavantage;broken");
+        assert_eq!(sources["BTC"], vec!["file"]);
+        assert_eq!(sources["EMIM"], vec!["http", "alphavantage"]);
+        assert_eq!(sources.len(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn falls_back_to_next_source() {
+        let url = serve(&[
+            (
+                "BTC",
+                "200 OK",
+                r#"[
+                    {"date":"2021-03-01","from_commodity":"BTC","to_commodity":"EUR","amount":40000},
+                    {"date":"2021-03-03","from_commodity":"BTC","to_commodity":"EUR","amount":41000},
+                    {"date":"2021-03-08","from_commodity":"BTC","to_commodity":"EUR","amount":42000}
+                ]"#,
+            ),
+            ("EMIM", "429 Too Many Requests", ""),
+        ]);
+        let path = std::env::temp_dir().join(format!(
+            "falls_back_to_next_source-{}.csv",
+            std::process::id()
+        ));
+        fs::write(
+            &path,
+            "date,from_commodity,to_commodity,amount\n2021-03-05,EMIM,EUR,30.5\n2021-03-05,VT,USD,95\n",
+        )
+        .unwrap();
+        let sources = PriceSources::new(
+            vec![
+                Box::new(HttpSource::new(url)),
+                Box::new(FileSource::new(path.to_string_lossy().to_string())),
+            ],
+            vec!["http".to_string(), "file".to_string()],
+            HashMap::new(),
+        );
+
+        let btc = sources
+            .fetch(
+                "BTC",
+                "EUR",
+                CommodityKind::Cryptocurrency,
+                Granularity::Weekly,
+                None,
+            )
+            .await;
+        let amounts: Vec<Decimal> = btc.iter().map(|p| p.amount).collect();
+        assert_eq!(
+            amounts,
+            vec![Decimal::new(41000, 0), Decimal::new(42000, 0)]
+        );
+
+        let btc = sources
+            .fetch(
+                "BTC",
+                "EUR",
+                CommodityKind::Cryptocurrency,
+                Granularity::Daily,
+                Some(NaiveDate::from_ymd(2021, 3, 3)),
+            )
+            .await;
+        assert_eq!(btc.len(), 1);
+        assert_eq!(btc[0].date, NaiveDate::from_ymd(2021, 3, 8));
+
+        // Throttled by the HTTP source
+        let emim = sources
+            .fetch(
+                "EMIM",
+                "EUR",
+                CommodityKind::Security,
+                Granularity::Daily,
+                None,
+            )
+            .await;
+        assert_eq!(emim[0].date, NaiveDate::from_ymd(2021, 3, 5));
+        assert_eq!(emim[0].amount, Decimal::new(305, 1));
+
+        // Neither source has it
+        assert!(sources
+            .fetch(
+                "USD",
+                "EUR",
+                CommodityKind::Currency,
+                Granularity::Daily,
+                None
+            )
+            .await
+            .is_empty());
+
+        let file = FileSource::new(path.to_string_lossy().to_string());
+        assert!(file
+            .fetch("VT", "EUR", CommodityKind::Security, Granularity::Daily)
+            .await
+            .is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -7,12 +7,17 @@ use std::{
     sync::Arc,
 };
 
-use chrono::NaiveDate;
+use chrono::{Datelike, NaiveDate};
+use futures::lock::Mutex;
 use io::BufRead;
+use log::info;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
-use crate::{alpha_vantage::AlphaVantage, file_utils};
+use crate::{
+    config, file_utils,
+    price_sources::{CommodityKind, Granularity, PriceSources},
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Price {
@@ -32,7 +37,8 @@ impl PartialEq for Price {
 }
 
 const DATE_FMT: &str = "%Y/%m/%d";
-const BASE_CURRENCY: &str = "EUR";
+/// hledger also accepts these in the journal
+const OTHER_DATE_FMTS: [&str; 2] = ["%Y-%m-%d", "%Y.%m.%d"];
 
 impl Display for Price {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -55,15 +61,20 @@ impl FromStr for Price {
             return Err("Empty input string".into());
         }
 
-        let parts: Vec<&str> = s.split(' ').collect();
-        if parts.len() < 5 {
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        if parts.len() < 5 || parts[0] != "P" {
             return Err("Expected 5 elements in price string".into());
         }
 
         let from_commodity = parse_commodity(parts[2]);
         let to_commodity = parse_commodity(parts[4]);
         let amount = parts[3].parse::<Decimal>()?;
-        let date = NaiveDate::parse_from_str(parts[1], DATE_FMT)?;
+        let date = NaiveDate::parse_from_str(parts[1], DATE_FMT).or_else(|e| {
+            OTHER_DATE_FMTS
+                .iter()
+                .find_map(|fmt| NaiveDate::parse_from_str(parts[1], fmt).ok())
+                .ok_or(e)
+        })?;
         Ok(Price {
             date,
             from_commodity,
@@ -73,73 +84,125 @@ impl FromStr for Price {
     }
 }
 
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FreshnessStatus {
+    /// No price in the base currency at all
+    Missing,
+    /// The latest price is older than the maximum age
+    Stale,
+}
+
+/// A commodity whose valuation in the base currency would be wrong or missing
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceFreshness {
+    pub commodity: String,
+    pub latest: Option<NaiveDate>,
+    pub age_days: Option<i64>,
+    pub status: FreshnessStatus,
+}
+
 pub struct Prices {
-    alpha_vantage: Arc<AlphaVantage>,
+    sources: Arc<PriceSources>,
+    granularity: Granularity,
+    base_currency: String,
     currencies: HashSet<String>,
     cryptocurrencies: HashSet<String>,
+    /// Held while updating the prices file, so scheduled and requested updates don't race
+    update_lock: Mutex<()>,
 }
 
 impl Prices {
-    pub fn new(alpha_vantage: Arc<AlphaVantage>) -> Self {
+    pub fn new(sources: Arc<PriceSources>) -> Self {
         let currencies_json = include_str!("currencies.json");
         let cryptocurrencies_json = include_str!("cryptocurrencies.json");
         Self {
-            alpha_vantage,
+            sources,
+            granularity: Granularity::from_config(),
+            base_currency: config::base_currency(),
             currencies: Self::load_currencies_from_disk(currencies_json),
             cryptocurrencies: Self::load_currencies_from_disk(cryptocurrencies_json),
+            update_lock: Mutex::new(()),
         }
     }
 
     // Disk operations
 
-    pub async fn update_prices<'a, I, S>(&self, commodities: I)
+    pub async fn update_prices<'a, I, S>(&self, commodities: I) -> io::Result<()>
     where
         I: IntoIterator<Item = &'a S>,
         S: AsRef<str> + 'a,
     {
-        let mut prices = Prices::read_prices();
+        let _guard = self.update_lock.lock().await;
+        let mut lines = Prices::read_lines()?;
+        let latest = latest_dates(lines.iter().filter_map(|l| l.parse::<Price>().ok()));
+        let start_date = NaiveDate::from_ymd(2016, 1, 1);
+        let base = self.base_currency.as_str();
+        let mut prices = vec![];
         for commodity in commodities.into_iter().map(AsRef::as_ref) {
-            if commodity == BASE_CURRENCY {
+            if commodity == base {
                 continue;
             }
 
-            let mut fetched_prices = self.fetch_prices(commodity, BASE_CURRENCY).await;
+            // Only fetch what's missing
+            let since = latest
+                .get(&(commodity.to_string(), base.to_string()))
+                .map_or(start_date, |&date| date.max(start_date));
+            let mut fetched_prices = self.fetch_prices(commodity, base, since).await;
             prices.append(&mut fetched_prices);
         }
-        prices.sort_by(|a, b| {
-            a.date
-                .partial_cmp(&b.date)
-                .unwrap()
-                .then(a.from_commodity.partial_cmp(&b.from_commodity).unwrap())
+        info!("Adding {} prices", prices.len());
+        if self.granularity == Granularity::Weekly {
+            drop_replaced_weeks(&mut lines, &latest, &prices);
+        }
+        append_prices(&mut lines, prices);
+
+        Prices::write_lines(&lines)
+    }
+
+    /// Commodities without a recent enough price in the base currency. The maximum age is
+    /// PRICE_MAX_AGE_DAYS or else a few days more than the update granularity.
+    pub fn stale_prices(&self, commodities: &[String], today: NaiveDate) -> Vec<PriceFreshness> {
+        let max_age_days = config::price_max_age_days().unwrap_or(match self.granularity {
+            // Allow for weekends and holidays
+            Granularity::Daily => 4,
+            Granularity::Weekly => 10,
         });
+        stale_prices(
+            commodities,
+            Prices::read_prices(),
+            &self.base_currency,
+            today,
+            max_age_days,
+        )
+    }
 
-        let start_date = NaiveDate::from_ymd(2016, 1, 1);
-        prices.retain(|p| p.date > start_date);
-        prices.dedup();
+    pub fn base_currency(&self) -> &str {
+        &self.base_currency
+    }
 
-        Prices::write_prices(prices.iter()).unwrap();
+    pub fn read_prices() -> Vec<Price> {
+        Prices::read_lines()
+            .unwrap_or_else(|e| panic!("Couldn't read prices file: {}", e))
+            .iter()
+            .filter_map(|line| line.parse::<Price>().ok())
+            .collect()
     }
 
-    fn read_prices() -> Vec<Price> {
+    /// All lines of the prices file, including comments and other directives
+    fn read_lines() -> io::Result<Vec<String>> {
         let filename = file_utils::get_prices_file().unwrap();
-        let file = File::open(filename).unwrap();
-        io::BufReader::new(file)
-            .lines()
-            .filter_map(|line| line.ok().map(|line| line.parse::<Price>().ok()).flatten())
-            .collect()
+        match File::open(filename) {
+            Ok(file) => io::BufReader::new(file).lines().collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(e),
+        }
     }
 
-    fn write_prices<'a, T>(prices: T) -> io::Result<()>
-    where
-        T: IntoIterator<Item = &'a Price>,
-    {
-        let prices_string = prices
-            .into_iter()
-            .map(|p| p.to_string())
-            .collect::<Vec<String>>()
-            .join("\n")
-            // hledger requires newline at the end of a document
-            + "\n";
+    fn write_lines(lines: &[String]) -> io::Result<()> {
+        // hledger requires newline at the end of a document
+        let prices_string = lines.join("\n") + "\n";
         let filename = file_utils::get_prices_file().unwrap();
         fs::write(filename, prices_string)
     }
@@ -151,26 +214,31 @@ impl Prices {
     }
 
     // Web operations
-    async fn fetch_prices(&self, from_commodity: &str, to_commodity: &str) -> Vec<Price> {
-        if self.is_currency(from_commodity) {
-            self.alpha_vantage
-                .fetch_weekly_forex(from_commodity, to_commodity)
-                .await
-                .unwrap()
+    async fn fetch_prices(
+        &self,
+        from_commodity: &str,
+        to_commodity: &str,
+        since: NaiveDate,
+    ) -> Vec<Price> {
+        let kind = if self.is_currency(from_commodity) {
+            CommodityKind::Currency
         } else if self.is_cryptocurrency(from_commodity) {
-            self.alpha_vantage
-                .fetch_weekly_crypto(from_commodity, to_commodity)
-                .await
-                .unwrap()
+            CommodityKind::Cryptocurrency
         } else {
-            self.alpha_vantage
-                .fetch_weekly_stocks(from_commodity, to_commodity)
-                .await
-                .unwrap()
-        }
+            CommodityKind::Security
+        };
+        self.sources
+            .fetch(
+                from_commodity,
+                to_commodity,
+                kind,
+                self.granularity,
+                Some(since),
+            )
+            .await
     }
 
-    fn is_currency(&self, commodity: &str) -> bool {
+    pub fn is_currency(&self, commodity: &str) -> bool {
         self.currencies.iter().any(|c| c == commodity)
     }
 
@@ -179,6 +247,137 @@ impl Prices {
     }
 }
 
+/// Prices by commodity pair, for looking up the rate on a date
+#[derive(Debug, Default)]
+pub struct PriceTable {
+    rates: HashMap<(String, String), Vec<(NaiveDate, Decimal)>>,
+}
+
+impl PriceTable {
+    pub fn new(prices: impl IntoIterator<Item = Price>) -> Self {
+        let mut table = Self::default();
+        for price in prices {
+            table.add(price);
+        }
+        table
+    }
+
+    pub fn add(&mut self, price: Price) {
+        let rates = self
+            .rates
+            .entry((price.from_commodity, price.to_commodity))
+            .or_default();
+        let i = rates.partition_point(|(date, _)| *date <= price.date);
+        rates.insert(i, (price.date, price.amount));
+    }
+
+    /// The latest rate on or before the date, using the inverse pair if needed
+    pub fn rate(&self, from: &str, to: &str, date: NaiveDate) -> Option<Decimal> {
+        if from == to {
+            return Some(Decimal::ONE);
+        }
+        self.latest(from, to, date).or_else(|| {
+            self.latest(to, from, date)
+                .filter(|rate| !rate.is_zero())
+                .map(|rate| Decimal::ONE / rate)
+        })
+    }
+
+    pub fn convert(
+        &self,
+        amount: Decimal,
+        from: &str,
+        to: &str,
+        date: NaiveDate,
+    ) -> Option<Decimal> {
+        Some(amount * self.rate(from, to, date)?)
+    }
+
+    fn latest(&self, from: &str, to: &str, date: NaiveDate) -> Option<Decimal> {
+        let rates = self.rates.get(&(from.to_string(), to.to_string()))?;
+        let i = rates.partition_point(|(d, _)| *d <= date);
+        Some(rates.get(i.checked_sub(1)?)?.1)
+    }
+}
+
+/// Date of the latest price of every commodity pair
+fn latest_dates(prices: impl IntoIterator<Item = Price>) -> HashMap<(String, String), NaiveDate> {
+    let mut latest = HashMap::<(String, String), NaiveDate>::new();
+    for price in prices {
+        let date = latest
+            .entry((price.from_commodity, price.to_commodity))
+            .or_insert(price.date);
+        *date = price.date.max(*date);
+    }
+    latest
+}
+
+fn stale_prices(
+    commodities: &[String],
+    prices: Vec<Price>,
+    base: &str,
+    today: NaiveDate,
+    max_age_days: i64,
+) -> Vec<PriceFreshness> {
+    let latest = latest_dates(prices);
+    let mut stale: Vec<PriceFreshness> = commodities
+        .iter()
+        .filter(|c| *c != base)
+        .filter_map(|commodity| {
+            let pair = |from: &str, to: &str| latest.get(&(from.to_string(), to.to_string()));
+            // hledger can also use the inverse of a price
+            let date = pair(commodity, base).max(pair(base, commodity)).copied();
+            let age_days = date.map(|d| (today - d).num_days());
+            let status = match age_days {
+                None => FreshnessStatus::Missing,
+                Some(age) if age > max_age_days => FreshnessStatus::Stale,
+                Some(_) => return None,
+            };
+            Some(PriceFreshness {
+                commodity: commodity.clone(),
+                latest: date,
+                age_days,
+                status,
+            })
+        })
+        .collect();
+    stale.sort_by(|a, b| a.latest.cmp(&b.latest).then(a.commodity.cmp(&b.commodity)));
+    stale
+}
+
+/// Add the prices after the existing lines, so manual prices and comments are kept
+fn append_prices(lines: &mut Vec<String>, mut prices: Vec<Price>) {
+    prices.sort_by(|a, b| {
+        a.date
+            .cmp(&b.date)
+            .then_with(|| a.from_commodity.cmp(&b.from_commodity))
+    });
+    prices.dedup();
+    lines.extend(prices.iter().map(|p| p.to_string()));
+}
+
+/// Drop the previous latest price of a pair if a new price is in the same week, as that's the
+/// price an earlier update stored before the week was over. Other prices of the week, like
+/// manual ones, are kept.
+fn drop_replaced_weeks(
+    lines: &mut Vec<String>,
+    latest: &HashMap<(String, String), NaiveDate>,
+    prices: &[Price],
+) {
+    lines.retain(|line| match line.parse::<Price>() {
+        Ok(old) => {
+            let pair = (old.from_commodity.clone(), old.to_commodity.clone());
+            latest.get(&pair) != Some(&old.date)
+                || !prices.iter().any(|new| {
+                    new.from_commodity == old.from_commodity
+                        && new.to_commodity == old.to_commodity
+                        && new.date.iso_week() == old.date.iso_week()
+                })
+        }
+        Err(_) => true,
+    });
+}
+
 fn format_commodity(commodity: &str) -> String {
     if commodity.chars().any(|c| c.is_numeric()) {
         return format!("\"{}\"", commodity);
@@ -195,7 +394,10 @@ mod tests {
     use chrono::NaiveDate;
     use rust_decimal::Decimal;
 
-    use super::{Price, Prices};
+    use super::{
+        append_prices, drop_replaced_weeks, latest_dates, stale_prices, FreshnessStatus, Price,
+        PriceTable, Prices,
+    };
 
     #[test]
     fn price_to_string() {
@@ -230,15 +432,123 @@ mod tests {
         assert_eq!(price.amount, Decimal::new(10000, 2));
     }
 
+    #[test]
+    fn price_table_rates() {
+        let table = PriceTable::new(
+            ["P 2021/01/01 USD 0.80 EUR", "P 2021/02/01 USD 0.50 EUR"]
+                .iter()
+                .map(|p| p.parse::<Price>().unwrap()),
+        );
+        let date = NaiveDate::from_ymd(2021, 1, 15);
+        assert_eq!(table.rate("USD", "EUR", date), Some(Decimal::new(80, 2)));
+        assert_eq!(
+            table.rate("EUR", "USD", NaiveDate::from_ymd(2021, 3, 1)),
+            Some(Decimal::new(2, 0))
+        );
+        assert_eq!(
+            table.rate("USD", "EUR", NaiveDate::from_ymd(2020, 1, 1)),
+            None
+        );
+        assert_eq!(table.rate("EUR", "EUR", date), Some(Decimal::ONE));
+    }
+
+    #[test]
+    fn append_keeps_manual_prices() {
+        let mut lines: Vec<String> = "; Manual prices
+P 2021-03-01\tX010  100 EUR
+P 2021/03/05 USD 0.84 EUR
+P 2021/03/12 USD 0.83 EUR
+D 1,000.00 EUR"
+            .lines()
+            .map(String::from)
+            .collect();
+        let latest = latest_dates(lines.iter().filter_map(|l| l.parse::<Price>().ok()));
+        assert_eq!(
+            latest[&("X010".to_string(), "EUR".to_string())],
+            NaiveDate::from_ymd(2021, 3, 1)
+        );
+        assert_eq!(
+            latest[&("USD".to_string(), "EUR".to_string())],
+            NaiveDate::from_ymd(2021, 3, 12)
+        );
+
+        let prices = ["P 2021/03/19 USD 0.82 EUR", "P 2021/03/19 BTC 50000 EUR"]
+            .iter()
+            .map(|p| p.parse::<Price>().unwrap())
+            .collect();
+        append_prices(&mut lines, prices);
+        assert_eq!(lines.len(), 7);
+        assert_eq!(lines[1], "P 2021-03-01\tX010  100 EUR");
+        assert_eq!(lines[4], "D 1,000.00 EUR");
+        assert_eq!(lines[5], "P 2021/03/19 BTC 50000 EUR");
+        assert_eq!(lines[6], "P 2021/03/19 USD 0.82 EUR");
+    }
+
+    #[test]
+    fn replace_partial_week() {
+        // Wednesday's price was stored before the week was over, Monday's is a manual one
+        let mut lines: Vec<String> = "P 2021/03/05 USD 0.84 EUR
+P 2021-03-08\tUSD  0.835 EUR
+P 2021/03/10 USD 0.83 EUR
+P 2021/03/10 BTC 50000 EUR"
+            .lines()
+            .map(String::from)
+            .collect();
+        assert!(lines[1].parse::<Price>().is_ok());
+        let latest = latest_dates(lines.iter().filter_map(|l| l.parse::<Price>().ok()));
+        let prices: Vec<Price> = ["P 2021/03/12 USD 0.82 EUR", "P 2021/03/19 USD 0.81 EUR"]
+            .iter()
+            .map(|p| p.parse::<Price>().unwrap())
+            .collect();
+        drop_replaced_weeks(&mut lines, &latest, &prices);
+        append_prices(&mut lines, prices);
+        assert_eq!(
+            lines,
+            vec![
+                "P 2021/03/05 USD 0.84 EUR",
+                "P 2021-03-08\tUSD  0.835 EUR",
+                "P 2021/03/10 BTC 50000 EUR",
+                "P 2021/03/12 USD 0.82 EUR",
+                "P 2021/03/19 USD 0.81 EUR"
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_and_stale_prices() {
+        let prices = [
+            "P 2021/03/01 USD 0.84 EUR",
+            "P 2021/03/12 EUR 1.08 CHF",
+            "P 2021/02/01 EMIM 28 EUR",
+            "P 2021/03/12 EMIM 29 EUR",
+        ]
+        .iter()
+        .map(|p| p.parse::<Price>().unwrap())
+        .collect();
+        let commodities: Vec<String> = ["EUR", "USD", "CHF", "EMIM", "BTC"]
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        let stale = stale_prices(
+            &commodities,
+            prices,
+            "EUR",
+            NaiveDate::from_ymd(2021, 3, 15),
+            10,
+        );
+        assert_eq!(stale.len(), 2);
+        assert_eq!(stale[0].commodity, "BTC");
+        assert_eq!(stale[0].status, FreshnessStatus::Missing);
+        assert_eq!(stale[1].commodity, "USD");
+        assert_eq!(stale[1].status, FreshnessStatus::Stale);
+        assert_eq!(stale[1].age_days, Some(14));
+    }
+
     #[test]
     #[ignore = "writes to prices.ledger file"]
     fn read_write_prices_file() {
-        let initial_prices = Prices::read_prices();
-        Prices::write_prices(initial_prices.iter()).unwrap();
-        let next_prices = Prices::read_prices();
-        assert!(initial_prices
-            .iter()
-            .zip(next_prices.iter())
-            .all(|(a, b)| a == b));
+        let initial_lines = Prices::read_lines().unwrap();
+        Prices::write_lines(&initial_lines).unwrap();
+        assert_eq!(Prices::read_lines().unwrap(), initial_lines);
     }
 }
@@ -0,0 +1,373 @@
+use std::collections::{BTreeMap, HashSet};
+
+use chrono::{Datelike, Duration, NaiveDate};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{
+    import_account::ImportAccount,
+    model::{
+        hledger_transaction::HledgerTransaction, real_transaction::RealTransaction,
+        rule::RulePosting,
+    },
+};
+
+const EXPENSE_ACCOUNT: &str = "Expenses";
+// Fewer payments than this are just coincidence
+const MIN_PAYMENTS: usize = 3;
+// Share of intervals and amounts which must fit the pattern, so one late or odd payment is ok
+const MIN_REGULAR_SHARE: f64 = 0.75;
+// How far an amount may be from the median and still count as the same subscription
+const AMOUNT_TOLERANCE: Decimal = Decimal::from_parts(5, 0, 0, false, 1);
+// How far the latest amount may be from the previous average before it's an alert
+const AMOUNT_JUMP_THRESHOLD: Decimal = Decimal::from_parts(2, 0, 0, false, 1);
+
+/// A single outgoing payment, from the journal or from a real transaction not yet recorded
+#[derive(Debug, Clone)]
+pub struct Payment {
+    pub payee: String,
+    pub date: NaiveDate,
+    /// Always positive
+    pub amount: Decimal,
+    pub commodity: String,
+}
+
+impl Payment {
+    pub fn from_hledger(transaction: &HledgerTransaction) -> Option<Self> {
+        let (amount, commodity) = transaction.get_amount_with_commodity(EXPENSE_ACCOUNT)?;
+        if amount <= Decimal::ZERO {
+            return None;
+        }
+        Some(Self {
+            payee: transaction.get_payee().to_string(),
+            date: transaction.get_date(None),
+            amount,
+            commodity: commodity.to_string(),
+        })
+    }
+
+    pub fn from_real(real: &impl RealTransaction) -> Option<Self> {
+        let posting = RulePosting::default();
+        let amount = real.get_amount(&posting)?;
+        if amount >= Decimal::ZERO {
+            return None;
+        }
+        Some(Self {
+            payee: real.get_payee()?,
+            date: real.get_date(),
+            amount: -amount,
+            commodity: real.get_currency(&posting)?,
+        })
+    }
+}
+
+/// Payments from real transactions which haven't been recorded in hledger yet
+pub fn get_unrecorded_payments<T: ImportAccount>(
+    import_account: &T,
+    hledger_transactions: &[HledgerTransaction],
+    real_transactions: &[T::RealTransactionType],
+) -> Vec<Payment> {
+    let hledger_account = import_account.get_hledger_account();
+    let hledger_ids: HashSet<&str> = hledger_transactions
+        .iter()
+        .flat_map(|t| t.get_all_ids(hledger_account))
+        .collect();
+    real_transactions
+        .iter()
+        .filter(|real| !hledger_ids.contains(&*real.get_id()))
+        .filter_map(Payment::from_real)
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Cadence {
+    Weekly,
+    Monthly,
+    Quarterly,
+    Yearly,
+}
+
+impl Cadence {
+    const ALL: [Cadence; 4] = [
+        Cadence::Weekly,
+        Cadence::Monthly,
+        Cadence::Quarterly,
+        Cadence::Yearly,
+    ];
+
+    /// Range of days between two payments which still counts as this cadence
+    fn days(&self) -> (i64, i64) {
+        match self {
+            Cadence::Weekly => (6, 8),
+            Cadence::Monthly => (26, 35),
+            Cadence::Quarterly => (84, 98),
+            Cadence::Yearly => (350, 380),
+        }
+    }
+
+    /// How late a payment may be before it's considered missing
+    fn grace(&self) -> Duration {
+        Duration::days(match self {
+            Cadence::Weekly => 3,
+            Cadence::Monthly => 7,
+            Cadence::Quarterly => 14,
+            Cadence::Yearly => 30,
+        })
+    }
+
+    fn from_days(days: i64) -> Option<Self> {
+        Self::ALL.iter().copied().find(|c| c.fits(days))
+    }
+
+    fn fits(&self, days: i64) -> bool {
+        let (min, max) = self.days();
+        (min..=max).contains(&days)
+    }
+
+    pub fn next(&self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Cadence::Weekly => date + Duration::days(7),
+            Cadence::Monthly => add_months(date, 1),
+            Cadence::Quarterly => add_months(date, 3),
+            Cadence::Yearly => add_months(date, 12),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceChange {
+    pub date: NaiveDate,
+    pub previous: Decimal,
+    pub amount: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Alert {
+    /// The payment expected on this date hasn't happened
+    #[serde(rename_all = "camelCase")]
+    Missing { expected: NaiveDate },
+    /// The latest payment differs a lot from the ones before
+    #[serde(rename_all = "camelCase")]
+    AmountJump {
+        date: NaiveDate,
+        previous_average: Decimal,
+        amount: Decimal,
+    },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Subscription {
+    pub payee: String,
+    pub commodity: String,
+    pub cadence: Cadence,
+    pub payments: usize,
+    pub first_payment: NaiveDate,
+    pub last_payment: NaiveDate,
+    pub next_expected: NaiveDate,
+    pub average_amount: Decimal,
+    pub last_amount: Decimal,
+    pub price_changes: Vec<PriceChange>,
+    /// False once two expected payments have been missed
+    pub active: bool,
+    pub alerts: Vec<Alert>,
+}
+
+/// Find payments to the same payee with similar amounts at a regular interval.
+/// Active subscriptions come first.
+pub fn detect_subscriptions(payments: Vec<Payment>, today: NaiveDate) -> Vec<Subscription> {
+    let mut groups = BTreeMap::<(String, String), Vec<Payment>>::new();
+    for payment in payments {
+        let key = (normalize_payee(&payment.payee), payment.commodity.clone());
+        groups.entry(key).or_default().push(payment);
+    }
+
+    let mut subscriptions: Vec<Subscription> = groups
+        .into_values()
+        .filter_map(|mut payments| {
+            payments.sort_by_key(|p| p.date);
+            detect_subscription(&payments, today)
+        })
+        .collect();
+    subscriptions.sort_by_key(|s| !s.active);
+    subscriptions
+}
+
+fn detect_subscription(payments: &[Payment], today: NaiveDate) -> Option<Subscription> {
+    if payments.len() < MIN_PAYMENTS {
+        return None;
+    }
+
+    let intervals: Vec<i64> = payments
+        .windows(2)
+        .map(|w| (w[1].date - w[0].date).num_days())
+        .collect();
+    let cadence = Cadence::from_days(median(&intervals))?;
+    let regular = intervals.iter().filter(|&&d| cadence.fits(d)).count();
+    if !is_majority(regular, intervals.len()) {
+        return None;
+    }
+
+    let amounts: Vec<Decimal> = payments.iter().map(|p| p.amount).collect();
+    let median_amount = median(&amounts);
+    let tolerance = median_amount * AMOUNT_TOLERANCE;
+    let similar = amounts
+        .iter()
+        .filter(|&&a| (a - median_amount).abs() <= tolerance)
+        .count();
+    if !is_majority(similar, amounts.len()) {
+        return None;
+    }
+
+    let first = payments.first()?;
+    let last = payments.last()?;
+    let next_expected = cadence.next(last.date);
+    let overdue = today > next_expected + cadence.grace();
+    let active = today <= cadence.next(next_expected) + cadence.grace();
+
+    let mut alerts = vec![];
+    if overdue && active {
+        alerts.push(Alert::Missing {
+            expected: next_expected,
+        });
+    }
+    let previous = &amounts[..amounts.len() - 1];
+    let previous_average = average(previous);
+    let threshold = previous_average * AMOUNT_JUMP_THRESHOLD;
+    if (last.amount - previous_average).abs() > threshold {
+        alerts.push(Alert::AmountJump {
+            date: last.date,
+            previous_average,
+            amount: last.amount,
+        });
+    }
+
+    let price_changes = payments
+        .windows(2)
+        .filter(|w| w[0].amount != w[1].amount)
+        .map(|w| PriceChange {
+            date: w[1].date,
+            previous: w[0].amount,
+            amount: w[1].amount,
+        })
+        .collect();
+
+    Some(Subscription {
+        payee: last.payee.clone(),
+        commodity: last.commodity.clone(),
+        cadence,
+        payments: payments.len(),
+        first_payment: first.date,
+        last_payment: last.date,
+        next_expected,
+        average_amount: average(&amounts),
+        last_amount: last.amount,
+        price_changes,
+        active,
+        alerts,
+    })
+}
+
+fn normalize_payee(payee: &str) -> String {
+    payee
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn is_majority(count: usize, total: usize) -> bool {
+    count as f64 >= total as f64 * MIN_REGULAR_SHARE
+}
+
+fn median<T: Copy + Ord>(values: &[T]) -> T {
+    let mut sorted = values.to_vec();
+    sorted.sort_unstable();
+    sorted[sorted.len() / 2]
+}
+
+fn average(values: &[Decimal]) -> Decimal {
+    let sum: Decimal = values.iter().sum();
+    (sum / Decimal::from(values.len())).round_dp(2)
+}
+
+// Clamped to the end of shorter months
+pub fn add_months(date: NaiveDate, months: u32) -> NaiveDate {
+    let month0 = date.month0() + months;
+    let year = date.year() + (month0 / 12) as i32;
+    let month = month0 % 12 + 1;
+    (0..4)
+        .find_map(|i| NaiveDate::from_ymd_opt(year, month, date.day() - i))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+
+    use super::{add_months, detect_subscriptions, Alert, Cadence, Payment};
+
+    fn payment(payee: &str, date: (i32, u32, u32), cents: i64) -> Payment {
+        Payment {
+            payee: payee.to_string(),
+            date: NaiveDate::from_ymd(date.0, date.1, date.2),
+            amount: Decimal::new(cents, 2),
+            commodity: "EUR".to_string(),
+        }
+    }
+
+    #[test]
+    fn monthly_with_price_change() {
+        let payments = vec![
+            payment("Netflix", (2021, 1, 15), 1199),
+            payment("NETFLIX ", (2021, 2, 15), 1199),
+            payment("Netflix", (2021, 3, 16), 1199),
+            payment("Netflix", (2021, 4, 15), 1599),
+            // Not regular
+            payment("Supermarket", (2021, 1, 2), 3000),
+            payment("Supermarket", (2021, 1, 5), 2000),
+            payment("Supermarket", (2021, 3, 20), 5000),
+        ];
+        let subscriptions = detect_subscriptions(payments, NaiveDate::from_ymd(2021, 4, 20));
+        assert_eq!(subscriptions.len(), 1);
+        let s = &subscriptions[0];
+        assert_eq!(s.cadence, Cadence::Monthly);
+        assert_eq!(s.payments, 4);
+        assert_eq!(s.next_expected, NaiveDate::from_ymd(2021, 5, 15));
+        assert_eq!(s.average_amount, Decimal::new(1299, 2));
+        assert_eq!(s.price_changes.len(), 1);
+        assert_eq!(s.price_changes[0].amount, Decimal::new(1599, 2));
+        assert!(s.active);
+        assert!(matches!(s.alerts[..], [Alert::AmountJump { .. }]));
+    }
+
+    #[test]
+    fn missing_payment() {
+        let payments = vec![
+            payment("Gym", (2021, 1, 1), 2000),
+            payment("Gym", (2021, 2, 1), 2000),
+            payment("Gym", (2021, 3, 1), 2000),
+        ];
+        let subscriptions =
+            detect_subscriptions(payments.clone(), NaiveDate::from_ymd(2021, 4, 12));
+        assert!(matches!(
+            subscriptions[0].alerts[..],
+            [Alert::Missing { expected }] if expected == NaiveDate::from_ymd(2021, 4, 1)
+        ));
+
+        // Two missed payments means it's been cancelled
+        let subscriptions = detect_subscriptions(payments, NaiveDate::from_ymd(2021, 6, 1));
+        assert!(!subscriptions[0].active);
+        assert!(subscriptions[0].alerts.is_empty());
+    }
+
+    #[test]
+    fn end_of_month() {
+        let date = NaiveDate::from_ymd(2021, 1, 31);
+        assert_eq!(add_months(date, 1), NaiveDate::from_ymd(2021, 2, 28));
+        assert_eq!(add_months(date, 12), NaiveDate::from_ymd(2022, 1, 31));
+    }
+}
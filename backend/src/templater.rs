@@ -1,8 +1,22 @@
-use handlebars::{Handlebars, RenderError, TemplateError};
-use log::error;
+use std::{collections::HashMap, fs};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use handlebars::{
+    handlebars_helper, Context, Handlebars, Helper, HelperDef, HelperResult, Output, RenderContext,
+    RenderError, TemplateError,
+};
+use log::{error, info};
+use regex::Regex;
 use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    config, file_utils,
+    model::rule::{Rule, RulePosting},
+};
 
-use crate::model::rule::Rule;
+/// Named tables mapping a key (e.g. a merchant city) to a value (e.g. an account segment)
+pub type LookupTables = HashMap<String, HashMap<String, String>>;
 
 pub struct Templater<'a> {
     handlebars: Handlebars<'a>,
@@ -10,13 +24,26 @@ pub struct Templater<'a> {
 
 impl<'a> Templater<'a> {
     pub fn new() -> Self {
-        Self {
-            handlebars: Handlebars::new(),
+        let mut handlebars = Handlebars::new();
+        handlebars.register_helper("title", Box::new(title));
+        handlebars.register_helper("capture", Box::new(capture));
+        handlebars.register_helper("substr", Box::new(substr));
+        handlebars.register_helper("date", Box::new(date));
+        handlebars.register_helper("table", Box::new(TableHelper::default()));
+        Self { handlebars }
+    }
+
+    /// Also loads the lookup tables from the journal repo, if there are any
+    pub fn with_lookup_tables() -> Self {
+        let mut templater = Self::new();
+        if let Some(tables) = read_lookup_tables() {
+            templater.set_lookup_tables(tables);
         }
+        templater
     }
 
     pub fn from_rules<'r, I: IntoIterator<Item = &'r Rule>>(rules: I) -> Self {
-        let mut templater = Self::new();
+        let mut templater = Self::with_lookup_tables();
         for rule in rules {
             templater
                 .register_rule(rule)
@@ -25,6 +52,11 @@ impl<'a> Templater<'a> {
         templater
     }
 
+    pub fn set_lookup_tables(&mut self, tables: LookupTables) {
+        self.handlebars
+            .register_helper("table", Box::new(TableHelper { tables }));
+    }
+
     pub fn register_rule(&mut self, rule: &Rule) -> Result<(), TemplateError> {
         if self.handlebars.has_template(&rule.rule_name) {
             return Ok(());
@@ -55,10 +87,248 @@ impl<'a> Templater<'a> {
     {
         self.handlebars.render_template(template_string, data)
     }
+
+    /// Render the account, comment and tags of a posting, which may all be templates
+    pub fn render_posting<T>(
+        &self,
+        posting: &RulePosting,
+        data: &T,
+    ) -> Result<RulePosting, RenderError>
+    where
+        T: Serialize,
+    {
+        let comment = match &posting.comment {
+            Some(c) => Some(self.render_if_template(c, data)?),
+            None => None,
+        };
+        let tags = posting
+            .tags
+            .iter()
+            .map(|t| self.render_if_template(t, data))
+            .collect::<Result<_, _>>()?;
+        Ok(RulePosting {
+            account: self.render_if_template(&posting.account, data)?,
+            comment,
+            tags,
+            ..posting.clone()
+        })
+    }
+
+    pub fn render_postings<T>(
+        &self,
+        postings: &[RulePosting],
+        data: &T,
+    ) -> Result<Vec<RulePosting>, RenderError>
+    where
+        T: Serialize,
+    {
+        postings
+            .iter()
+            .map(|p| self.render_posting(p, data))
+            .collect()
+    }
+
+    /// Most accounts and tags are plain strings, so don't bother compiling those
+    pub fn render_if_template<T>(
+        &self,
+        template_string: &str,
+        data: &T,
+    ) -> Result<String, RenderError>
+    where
+        T: Serialize,
+    {
+        if !template_string.contains("{{") {
+            return Ok(template_string.to_string());
+        }
+        self.handlebars.render_template(template_string, data)
+    }
+}
+
+fn read_lookup_tables() -> Option<LookupTables> {
+    // Without a configured journal there's nowhere to look
+    config::journal_path()?;
+    let path = file_utils::get_lookup_tables_file()?;
+    let yaml = fs::read_to_string(&path).ok()?;
+    match serde_yaml::from_str(&yaml) {
+        Ok(tables) => {
+            info!("Loaded lookup tables from {}", path.to_string_lossy());
+            Some(tables)
+        }
+        Err(e) => {
+            error!("Error reading lookup tables: {}", e);
+            None
+        }
+    }
+}
+
+// {{title merchantName}}: "REWE markt gmbh" -> "Rewe Markt Gmbh"
+handlebars_helper!(title: |s: str| {
+    s.split(' ')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars.flat_map(char::to_lowercase)).collect(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+});
+
+// {{capture referenceText "Hotel (\\w+)"}} renders the first capture group, or the whole match
+// if there are no groups. An explicit group can be given as a third parameter. Backslashes need
+// to be escaped as handlebars string literals follow JSON rules.
+fn capture(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let value = param_to_string(h, 0)?;
+    let pattern = param_str(h, 1)?;
+    let regex = Regex::new(pattern)
+        .map_err(|e| RenderError::new(format!("Invalid regex in capture helper: {}", e)))?;
+    let group = match h.param(2).and_then(|p| p.value().as_u64()) {
+        Some(group) => group as usize,
+        None if regex.captures_len() > 1 => 1,
+        None => 0,
+    };
+    if let Some(m) = regex.captures(&value).and_then(|c| c.get(group)) {
+        out.write(m.as_str())?;
+    }
+    Ok(())
+}
+
+// {{substr iban 0 4}}: characters from start, optionally limited to a length. A negative start
+// counts from the end.
+fn substr(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let value = param_to_string(h, 0)?;
+    let chars: Vec<char> = value.chars().collect();
+    let start = h
+        .param(1)
+        .and_then(|p| p.value().as_i64())
+        .ok_or_else(|| RenderError::new("substr helper needs a start index"))?;
+    let start = if start < 0 {
+        chars.len().saturating_sub(start.unsigned_abs() as usize)
+    } else {
+        (start as usize).min(chars.len())
+    };
+    let end = match h.param(2).and_then(|p| p.value().as_u64()) {
+        Some(len) => (start + len as usize).min(chars.len()),
+        None => chars.len(),
+    };
+    out.write(&chars[start..end].iter().collect::<String>())?;
+    Ok(())
+}
+
+// {{date visibleTS "%d.%m.%Y"}}: reformat a date, which is either a unix timestamp in
+// milliseconds or a string. Defaults to ISO format.
+fn date(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let value = h
+        .param(0)
+        .map(|p| p.value())
+        .ok_or_else(|| RenderError::new("date helper needs a date"))?;
+    let format = h
+        .param(1)
+        .and_then(|p| p.value().as_str())
+        .unwrap_or("%Y-%m-%d");
+    let date =
+        parse_date(value).ok_or_else(|| RenderError::new(format!("Can't parse date {}", value)))?;
+    out.write(&date.format(format).to_string())?;
+    Ok(())
+}
+
+fn parse_date(value: &Value) -> Option<NaiveDateTime> {
+    match value {
+        Value::Number(n) => {
+            let millis = n.as_i64()?;
+            NaiveDateTime::from_timestamp_opt(
+                millis.div_euclid(1000),
+                (millis.rem_euclid(1000) * 1_000_000) as u32,
+            )
+        }
+        Value::String(s) => DateTime::parse_from_rfc3339(s)
+            .map(|d| d.naive_local())
+            .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S"))
+            .or_else(|_| NaiveDate::parse_from_str(s, "%Y-%m-%d").map(|d| d.and_hms(0, 0, 0)))
+            .or_else(|_| NaiveDate::parse_from_str(s, "%Y%m%d").map(|d| d.and_hms(0, 0, 0)))
+            .ok(),
+        _ => None,
+    }
+}
+
+// {{table "cities" merchantCity "Other"}}: look up a value in one of the lookup tables, with
+// an optional fallback if the key isn't in the table
+#[derive(Default)]
+struct TableHelper {
+    tables: LookupTables,
+}
+
+impl HelperDef for TableHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let table_name = param_str(h, 0)?;
+        let key = param_to_string(h, 1)?;
+        let table = self
+            .tables
+            .get(table_name)
+            .ok_or_else(|| RenderError::new(format!("No lookup table {}", table_name)))?;
+        let value = table
+            .get(&key)
+            .map(String::as_str)
+            .or_else(|| h.param(2).and_then(|p| p.value().as_str()))
+            .ok_or_else(|| {
+                RenderError::new(format!("{} not found in lookup table {}", key, table_name))
+            })?;
+        out.write(value)?;
+        Ok(())
+    }
+}
+
+fn param_str<'a>(h: &'a Helper, index: usize) -> Result<&'a str, RenderError> {
+    h.param(index)
+        .and_then(|p| p.value().as_str())
+        .ok_or_else(|| {
+            RenderError::new(format!(
+                "{} helper needs a string as parameter {}",
+                h.name(),
+                index + 1
+            ))
+        })
+}
+
+// Numbers and the like are stringified, so they can be used like strings in helpers
+fn param_to_string(h: &Helper, index: usize) -> Result<String, RenderError> {
+    match h.param(index).map(|p| p.value()) {
+        Some(Value::String(s)) => Ok(s.clone()),
+        Some(Value::Null) | None => Ok(String::new()),
+        Some(v) => Ok(v.to_string()),
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use serde_json::json;
+
     use super::*;
     use crate::test_statics::{REAL, RULES};
 
@@ -80,4 +350,61 @@ mod tests {
             .unwrap();
         assert_eq!(&desc, "Transaction with -219.56 EUR");
     }
+
+    #[test]
+    fn helpers() {
+        let templater = Templater::new();
+        let data = json!({
+            "name": "REWE markt gmbh",
+            "text": "Hotel Berlin 2 nights",
+            "iban": "DE89370400440532013000",
+            "ts": 1597276800000i64,
+            "day": "2020-08-13",
+        });
+        let render = |t| templater.render_description(t, &data).unwrap();
+        assert_eq!(render("{{title name}}"), "Rewe Markt Gmbh");
+        assert_eq!(render(r#"{{capture text "Hotel (\\w+)"}}"#), "Berlin");
+        assert_eq!(render(r#"{{capture text "(\\d) (nights)" 2}}"#), "nights");
+        assert_eq!(render(r#"{{capture text "Flight (\\w+)"}}"#), "");
+        assert_eq!(render("{{substr iban 0 4}}"), "DE89");
+        assert_eq!(render("{{substr iban -4}}"), "3000");
+        assert_eq!(render(r#"{{date ts "%d.%m.%Y"}}"#), "13.08.2020");
+        assert_eq!(render(r#"{{date day "%b %Y"}}"#), "Aug 2020");
+    }
+
+    #[test]
+    fn lookup_tables() {
+        let mut templater = Templater::new();
+        let cities = vec![("Berlin".to_string(), "Germany".to_string())];
+        templater.set_lookup_tables(
+            vec![("countries".to_string(), cities.into_iter().collect())]
+                .into_iter()
+                .collect(),
+        );
+        let render = |t, city| templater.render_description(t, &json!({ "city": city }));
+        assert_eq!(
+            render(r#"{{table "countries" city}}"#, "Berlin").unwrap(),
+            "Germany"
+        );
+        assert_eq!(
+            render(r#"{{table "countries" city "Other"}}"#, "Paris").unwrap(),
+            "Other"
+        );
+        assert!(render(r#"{{table "countries" city}}"#, "Paris").is_err());
+    }
+
+    #[test]
+    fn render_posting() {
+        let templater = Templater::new();
+        let posting = RulePosting {
+            account: r#"Expenses:Travel:{{capture text "Hotel (\\w+)"}}"#.to_string(),
+            comment: Some("{{text}} & more".to_string()),
+            ..RulePosting::default()
+        };
+        let rendered = templater
+            .render_posting(&posting, &json!({ "text": "Hotel Berlin" }))
+            .unwrap();
+        assert_eq!(rendered.account, "Expenses:Travel:Berlin");
+        assert_eq!(rendered.comment.as_deref(), Some("Hotel Berlin & more"));
+    }
 }
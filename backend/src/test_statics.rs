@@ -1,8 +1,10 @@
+use chrono::NaiveDate;
 use lazy_static::lazy_static;
 use regex::Regex;
+use rust_decimal::Decimal;
 
 use crate::model::{
-    hledger_transaction::HledgerTransaction,
+    hledger_transaction::{HledgerTransaction, Posting},
     n26_transaction::N26Transaction,
     rule::{Rule, RulePosting},
 };
@@ -22,6 +24,7 @@ lazy_static! {
                 account: ASSET_ACCOUNT.to_string(),
                 negate: false,
                 comment: None,
+                tags: vec![],
             },
             RulePosting {
                 amount_field_name: Some("amount".to_string()),
@@ -30,6 +33,7 @@ lazy_static! {
                 account: EXPENSE_ACCOUNT.to_string(),
                 negate: true,
                 comment: None,
+                tags: vec![],
             }
         ],
         description_template: "Test {{{partnerName}}} with {{{referenceText}}}".to_string(),
@@ -76,6 +80,7 @@ lazy_static! {
                 account: ASSET_ACCOUNT.to_string(),
                 negate: false,
                 comment: None,
+                tags: vec![],
             },
             RulePosting {
                 amount_field_name: Some("amount".to_string()),
@@ -84,7 +89,19 @@ lazy_static! {
                 account: EXPENSE_ACCOUNT.to_string(),
                 negate: true,
                 comment: None,
+                tags: vec![],
             },
         ],
     )];
 }
+
+/// A journal transaction with a posting of `amount` `commodity` for every entry
+pub fn transaction(date: NaiveDate, postings: &[(&str, &str, i64)]) -> HledgerTransaction {
+    let mut postings = postings
+        .iter()
+        .map(|&(account, commodity, amount)| {
+            Posting::new(account, commodity, Decimal::from(amount), None, None)
+        })
+        .collect();
+    HledgerTransaction::without_id("test", date).postings(&mut postings)
+}
@@ -0,0 +1,215 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{
+    import_account::ImportAccount,
+    model::{
+        hledger_transaction::{HledgerTransaction, Posting},
+        real_transaction::RealTransaction,
+        rule::RulePosting,
+    },
+};
+
+pub const DEFAULT_WINDOW_DAYS: i64 = 3;
+
+/// One side of a possible transfer, in a form which can be compared across importers
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferLeg {
+    pub importer_id: String,
+    pub hledger_account: String,
+    pub id: String,
+    pub date: NaiveDate,
+    pub amount: Decimal,
+    pub commodity: String,
+    pub real_transaction: serde_json::Value,
+}
+
+impl TransferLeg {
+    pub fn new(import_account: &impl ImportAccount, real: &impl RealTransaction) -> Option<Self> {
+        // Use the default amount and currency fields
+        let posting = RulePosting::default();
+        Some(Self {
+            importer_id: import_account.get_id().to_string(),
+            hledger_account: import_account.get_hledger_account().to_string(),
+            id: real.get_id().to_string(),
+            date: real.get_date(),
+            amount: real.get_amount(&posting)?,
+            commodity: real.get_currency(&posting)?,
+            real_transaction: real.to_json_value(),
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Transfer {
+    pub from: TransferLeg,
+    pub to: TransferLeg,
+    pub hledger_transaction: HledgerTransaction,
+}
+
+impl Transfer {
+    fn new(from: TransferLeg, to: TransferLeg) -> Self {
+        let description = format!(
+            "Transfer from {} to {}",
+            from.hledger_account, to.hledger_account
+        );
+        let mut to_posting =
+            Posting::new(&to.hledger_account, &to.commodity, to.amount, None, None)
+                .tag("uuid", &to.id);
+        if to.date != from.date {
+            to_posting = to_posting.date(to.date);
+        }
+        let hledger_transaction =
+            HledgerTransaction::without_id(&description, from.date).postings(&mut vec![
+                Posting::new(
+                    &from.hledger_account,
+                    &from.commodity,
+                    from.amount,
+                    None,
+                    None,
+                )
+                .tag("uuid", &from.id),
+                to_posting,
+            ]);
+        Self {
+            from,
+            to,
+            hledger_transaction,
+        }
+    }
+}
+
+/// Real transactions of the importer which haven't been recorded in hledger yet
+pub fn get_transfer_legs<T: ImportAccount>(
+    import_account: &T,
+    hledger_transactions: &[HledgerTransaction],
+    real_transactions: &[T::RealTransactionType],
+) -> Vec<TransferLeg> {
+    let hledger_account = import_account.get_hledger_account();
+    let hledger_ids: HashSet<&str> = hledger_transactions
+        .iter()
+        .flat_map(|t| t.get_all_ids(hledger_account))
+        .collect();
+    real_transactions
+        .iter()
+        .filter(|real| !hledger_ids.contains(&*real.get_id()))
+        .filter_map(|real| TransferLeg::new(import_account, real))
+        .collect()
+}
+
+/// Pair outgoing and incoming legs of different importers with the same amount and commodity
+/// which are at most `window_days` apart. Legs closest in time are paired first and every leg
+/// is used at most once.
+pub fn find_transfers(legs: Vec<TransferLeg>, window_days: i64) -> Vec<Transfer> {
+    // Only legs with the same commodity and absolute amount can possibly be paired
+    let mut groups = HashMap::<(&str, Decimal), Vec<usize>>::new();
+    for (i, leg) in legs.iter().enumerate() {
+        if !leg.amount.is_zero() {
+            groups
+                .entry((&leg.commodity, leg.amount.abs().normalize()))
+                .or_default()
+                .push(i);
+        }
+    }
+
+    let mut candidates = vec![];
+    for indices in groups.values() {
+        for &from in indices.iter().filter(|&&i| legs[i].amount < Decimal::ZERO) {
+            for &to in indices.iter().filter(|&&i| legs[i].amount > Decimal::ZERO) {
+                if legs[from].importer_id == legs[to].importer_id {
+                    continue;
+                }
+                let days = (legs[to].date - legs[from].date).num_days().abs();
+                if days <= window_days {
+                    candidates.push((days, legs[from].date, from, to));
+                }
+            }
+        }
+    }
+    candidates.sort_unstable();
+
+    let mut used = HashSet::new();
+    let mut pairs = vec![];
+    for (_, _, from, to) in candidates {
+        if used.contains(&from) || used.contains(&to) {
+            continue;
+        }
+        used.insert(from);
+        used.insert(to);
+        pairs.push((from, to));
+    }
+
+    let mut legs: Vec<Option<TransferLeg>> = legs.into_iter().map(Some).collect();
+    let mut transfers: Vec<Transfer> = pairs
+        .into_iter()
+        .filter_map(|(from, to)| Some(Transfer::new(legs[from].take()?, legs[to].take()?)))
+        .collect();
+    transfers.sort_by_key(|t| t.from.date);
+    transfers
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+    use serde_json::Value;
+
+    use super::{find_transfers, TransferLeg};
+
+    fn leg(importer_id: &str, id: &str, day: u32, amount: i64) -> TransferLeg {
+        TransferLeg {
+            importer_id: importer_id.to_string(),
+            hledger_account: format!("Assets:Cash:{}", importer_id),
+            id: id.to_string(),
+            date: NaiveDate::from_ymd(2021, 3, day),
+            amount: Decimal::new(amount, 2),
+            commodity: "EUR".to_string(),
+            real_transaction: Value::Null,
+        }
+    }
+
+    #[test]
+    fn pairs_closest_legs_across_importers() {
+        let legs = vec![
+            leg("n26", "a", 1, -50000),
+            leg("ing", "b", 2, 50000),
+            // Same importer, so never a transfer
+            leg("n26", "c", 1, 50000),
+            // Too far away
+            leg("ing", "d", 20, 12000),
+            leg("n26", "e", 10, -12000),
+            // Closer than b, so paired with a instead
+            leg("ing", "f", 1, 50000),
+        ];
+        let transfers = find_transfers(legs, 3);
+        assert_eq!(transfers.len(), 1);
+        let t = &transfers[0];
+        assert_eq!(t.from.id, "a");
+        assert_eq!(t.to.id, "f");
+
+        let h = &t.hledger_transaction;
+        assert_eq!(h.get_id(), None);
+        assert_eq!(h.get_all_ids("Assets:Cash:n26").collect::<Vec<_>>(), ["a"]);
+        assert_eq!(h.get_all_ids("Assets:Cash:ing").collect::<Vec<_>>(), ["f"]);
+        assert_eq!(
+            h.get_amount(Some("f"), "Assets:Cash:ing"),
+            Some(Decimal::new(50000, 2))
+        );
+    }
+
+    #[test]
+    fn posting_date_of_later_leg() {
+        let transfers = find_transfers(vec![leg("n26", "a", 1, -100), leg("ing", "b", 3, 100)], 3);
+        let h = &transfers[0].hledger_transaction;
+        assert_eq!(h.get_date(None), NaiveDate::from_ymd(2021, 3, 1));
+        assert_eq!(
+            h.get_date(Some("Assets:Cash:ing")),
+            NaiveDate::from_ymd(2021, 3, 3)
+        );
+    }
+}
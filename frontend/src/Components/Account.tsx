@@ -4,7 +4,7 @@ import { Button, Icon, Loader, Popup, Table, TableCell } from "semantic-ui-react
 import { Balance, Balances } from "../Models/Balance";
 import { ImportAccount } from "../Models/ImportAccount";
 import { getBalance } from "../Utils/BackendRequester";
-import { asCurrency, asEuro } from "../Utils/TextUtils";
+import { asCurrency } from "../Utils/TextUtils";
 
 interface Props {
   account: ImportAccount;
@@ -44,6 +44,8 @@ export const AccountComponent: React.FC<Props> = ({ account, onUpdate }) => {
     updateBalance(false);
   }, [updateBalance]);
 
+  const baseCurrency = balance?.baseCurrency ?? "EUR";
+
   const cells = (balance?: Balance) => {
     if (failure) {
       return (
@@ -54,24 +56,23 @@ export const AccountComponent: React.FC<Props> = ({ account, onUpdate }) => {
       );
     }
     if (balance) {
-      const diff = balance.real - balance.hledger;
-      const inSync = Math.abs(diff) < 0.1;
-
       return (
         <React.Fragment>
           <Table.Cell key="commodity">{balance.commodity}</Table.Cell>
           <Table.Cell key="value">
-            {balance.realEuro ? asEuro(balance.realEuro) : asCurrency(balance.real, balance.commodity)}
+            {balance.realBase !== undefined ? asCurrency(balance.realBase, baseCurrency) : asCurrency(balance.real, balance.commodity)}
           </Table.Cell>
           <Table.Cell key="real">{asCurrency(balance.real, balance.commodity)}</Table.Cell>
           <Table.Cell key="hledger">{asCurrency(balance.hledger, balance.commodity)}</Table.Cell>
-          <Table.Cell key="sync" textAlign="center" negative={!inSync} positive={inSync}>
-            {inSync ? (
+          <Table.Cell key="sync" textAlign="center" negative={!balance.inSync} positive={balance.inSync}>
+            {balance.inSync ? (
               <Icon name="check" color="green" />
             ) : (
               <span>
                 <Icon name="exclamation" />
-                {asEuro(diff)}
+                {balance.differenceBase !== undefined && balance.commodity !== baseCurrency
+                  ? `${asCurrency(balance.difference, balance.commodity)} (${asCurrency(balance.differenceBase, baseCurrency)})`
+                  : asCurrency(balance.difference, balance.commodity)}
               </span>
             )}
           </Table.Cell>
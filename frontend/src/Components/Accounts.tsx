@@ -1,25 +1,23 @@
 import React, { useCallback, useState } from "react";
-import { Grid, Header, Table } from "semantic-ui-react";
+import { Grid, Header, Icon, Table } from "semantic-ui-react";
 import { Balances } from "../Models/Balance";
 import { ImportAccounts } from "../Models/ImportAccount";
-import { asEuro } from "../Utils/TextUtils";
+import { asCurrency } from "../Utils/TextUtils";
 import { AccountComponent } from "./Account";
 
 export const AccountsComponent: React.FC = () => {
-  const [total, setTotal] = useState<Record<string, number>>({});
-  const onAccountUpdate = useCallback(
-    (accountId: string, balance: Balances) =>
-      setTotal((prevState) => ({
-        ...prevState,
-        [accountId]: balance.balances.reduce((total, b) => {
-          total += b.realEuro ?? b.real;
-          return total;
-        }, 0),
-      })),
-    []
-  );
+  const [totals, setTotals] = useState<Record<string, Balances>>({});
+  const [baseCurrency, setBaseCurrency] = useState("EUR");
+  const onAccountUpdate = useCallback((accountId: string, balance: Balances) => {
+    setBaseCurrency(balance.baseCurrency);
+    setTotals((prevState) => ({ ...prevState, [accountId]: balance }));
+  }, []);
   const accounts = ImportAccounts.map((x) => <AccountComponent account={x} key={x.id} onUpdate={onAccountUpdate} />);
-  const realTotal = Object.keys(total).reduce((previous, key) => previous + total[key], 0);
+  const balances = Object.values(totals);
+  const realTotal = balances.reduce((total, b) => total + b.realBase, 0);
+  const hledgerTotal = balances.reduce((total, b) => total + b.hledgerBase, 0);
+  const differenceTotal = balances.reduce((total, b) => total + b.differenceBase, 0);
+  const inSync = balances.every((b) => b.inSync);
   return (
     <Grid textAlign="center" verticalAlign="middle" style={{ height: "100vh", margin: 0 }}>
       <Grid.Column style={{ maxWidth: 1027 }} textAlign="left">
@@ -29,7 +27,7 @@ export const AccountsComponent: React.FC = () => {
             <Table.Row>
               <Table.HeaderCell>Account</Table.HeaderCell>
               <Table.HeaderCell>Commodity</Table.HeaderCell>
-              <Table.HeaderCell>Value (EUR)</Table.HeaderCell>
+              <Table.HeaderCell>Value ({baseCurrency})</Table.HeaderCell>
               <Table.HeaderCell>Real balance</Table.HeaderCell>
               <Table.HeaderCell>
                 <i>hledger</i> balance
@@ -43,9 +41,14 @@ export const AccountsComponent: React.FC = () => {
           <Table.Footer>
             <Table.Row>
               <Table.HeaderCell>Total</Table.HeaderCell>
-              <Table.HeaderCell>EUR</Table.HeaderCell>
-              <Table.HeaderCell>{asEuro(realTotal)}</Table.HeaderCell>
-              <Table.HeaderCell colSpan={5} />
+              <Table.HeaderCell>{baseCurrency}</Table.HeaderCell>
+              <Table.HeaderCell>{asCurrency(realTotal, baseCurrency)}</Table.HeaderCell>
+              <Table.HeaderCell />
+              <Table.HeaderCell>{asCurrency(hledgerTotal, baseCurrency)}</Table.HeaderCell>
+              <Table.HeaderCell textAlign="center">
+                {inSync ? <Icon name="check" color="green" /> : asCurrency(differenceTotal, baseCurrency)}
+              </Table.HeaderCell>
+              <Table.HeaderCell colSpan={2} />
             </Table.Row>
           </Table.Footer>
         </Table>
@@ -63,7 +63,7 @@ export const NetWorthPlot: React.FC<Props> = ({ startDate }) => {
   const [netWorth, setNetWorth] = useState<AlignedData | null>(null);
   useEffect(() => {
     getNetWorth(startDate).then((report) => {
-      setNetWorth(report);
+      setNetWorth(report.data);
     });
   }, [startDate]);
 
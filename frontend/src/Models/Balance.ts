@@ -1,10 +1,19 @@
 export interface Balances {
   balances: Balance[];
+  baseCurrency: string;
+  realBase: number;
+  hledgerBase: number;
+  differenceBase: number;
+  inSync: boolean;
 }
 
 export interface Balance {
   commodity: string;
   real: number;
-  realEuro?: number;
   hledger: number;
+  difference: number;
+  realBase?: number;
+  hledgerBase?: number;
+  differenceBase?: number;
+  inSync: boolean;
 }
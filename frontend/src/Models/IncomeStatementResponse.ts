@@ -1,8 +1,20 @@
 import { AlignedData } from "uplot";
 import { HledgerTransaction } from "./HledgerTransaction";
 
+export interface AccountTree {
+  account: string;
+  amounts: number[];
+  children: AccountTree[];
+}
+
 export interface IncomeStatementResponse {
   data: AlignedData;
   topRevenues: HledgerTransaction[][];
   topExpenses: HledgerTransaction[][];
+  accounts: AccountTree[];
+}
+
+export interface NetWorthResponse {
+  data: AlignedData;
+  accounts: AccountTree[];
 }
@@ -1,9 +1,8 @@
-import { AlignedData } from "uplot";
 import { getApiKey } from "../Components/Login/useApiKey";
 import { Balances } from "../Models/Balance";
 import { ImportAccount } from "../Models/ImportAccount";
 import { TransactionResponse } from "../Models/ImportRow";
-import { IncomeStatementResponse } from "../Models/IncomeStatementResponse";
+import { IncomeStatementResponse, NetWorthResponse } from "../Models/IncomeStatementResponse";
 import { Rule } from "../Models/Rule";
 import { TransactionRequest } from "../Models/TransactionRequest";
 
@@ -48,7 +47,7 @@ export const getIncomeStatement = (from?: Date, to?: Date): Promise<IncomeStatem
   return get("reports/income_statement", query);
 };
 
-export const getNetWorth = (from?: Date, to?: Date): Promise<AlignedData> => {
+export const getNetWorth = (from?: Date, to?: Date): Promise<NetWorthResponse> => {
   const query = timeRange(from, to);
   return get("reports/net_worth", query);
 };